@@ -0,0 +1,17 @@
+fn main() {
+  println!("cargo:rerun-if-changed=build.rs");
+  // crunch's crn_decomp.h isn't vendored, `reference` is for differential testing only
+  #[cfg(feature = "reference")]
+  {
+    println!("cargo:rerun-if-changed=reference/shim.cpp");
+    println!("cargo:rerun-if-env-changed=CRN_DECOMP_DIR");
+    let dir = std::env::var("CRN_DECOMP_DIR")
+      .expect("the reference feature needs CRN_DECOMP_DIR set to the directory of crunch's crn_decomp.h");
+    cc::Build::new()
+      .cpp(true)
+      .file("reference/shim.cpp")
+      .include(dir)
+      .warnings(false)
+      .compile("crnlib_reference");
+  }
+}
@@ -0,0 +1,43 @@
+//! Compare every level of crn files, or of directories of them, with crunch's own decoder and
+//! report the first block that differs per file.
+//!
+//! `CRN_DECOMP_DIR=path/to/crunch/inc cargo run --features reference --example difftest -- samples/`
+
+use std::path::{Path, PathBuf};
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+  if !path.is_dir() {
+    files.push(path.to_path_buf());
+    return Ok(())
+  }
+  for entry in std::fs::read_dir(path)? {
+    let path = entry?.path();
+    if path.is_dir() || path.extension().is_some_and(|e| e.eq_ignore_ascii_case("crn")) { collect(&path, files)? }
+  }
+  Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let skip_edges = std::env::args().any(|a| a == "--skip-edges");
+  let mut files = vec![];
+  for arg in std::env::args().skip(1).filter(|a| !a.starts_with("--")) {
+    collect(Path::new(&arg), &mut files)?;
+  }
+  let (mut same, mut diverged, mut failed) = (0, 0, 0);
+  for file in &files {
+    match crnlib::reference::compare(&std::fs::read(file)?, skip_edges) {
+      Ok(None) => same += 1,
+      Ok(Some(d)) => {
+        diverged += 1;
+        println!("{}: level {} face {} block ({}, {}): {:02x?} here, {:02x?} in crnd", file.display(), d.level, d.face, d.x, d.y, d.ours, d.reference);
+      }
+      Err(e) => {
+        failed += 1;
+        println!("{}: {}", file.display(), e);
+      }
+    }
+  }
+  println!("{} files: {} same, {} diverged, {} failed", files.len(), same, diverged, failed);
+  if diverged + failed != 0 { std::process::exit(1) }
+  Ok(())
+}
@@ -0,0 +1,29 @@
+//! Write seeds for the targets under `fuzz/` from sample crn files, or from files of
+//! `crnlib::testgen` in every format when no sample is given.
+//!
+//! `cargo run --features arbitrary --example fuzz_corpus -- samples/test.crn`
+
+use std::path::Path;
+use crnlib::{Format, testgen::TestCrn};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let samples = std::env::args().skip(1).map(|sample| {
+    let stem = Path::new(&sample).file_stem().and_then(|s| s.to_str()).unwrap_or("sample").to_string();
+    Ok((stem, std::fs::read(&sample)?))
+  }).collect::<Result<Vec<_>, std::io::Error>>()?;
+  let samples = if !samples.is_empty() { samples } else {
+    [Format::Dxt1, Format::Dxt5, Format::Dxt5A, Format::DxnXY].iter().map(|&format| {
+      Ok((format!("testgen-{:?}", format).to_lowercase(), TestCrn::new(format, 32, 16).build()?))
+    }).collect::<Result<Vec<_>, crnlib::CrnError>>()?
+  };
+  let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join("corpus");
+  for (stem, input) in samples {
+    for (target, name, seed) in crnlib::fuzz::seed_corpus(&input) {
+      let dir = corpus.join(target);
+      std::fs::create_dir_all(&dir)?;
+      std::fs::write(dir.join(format!("{}-{}", stem, name)), seed)?;
+    }
+    println!("seeded from {}", stem);
+  }
+  Ok(())
+}
@@ -0,0 +1,122 @@
+#ifndef CRNLIB_H
+#define CRNLIB_H
+
+/* generated by cbindgen from src/capi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// result of the flat `crnlib_*` functions
+typedef enum CrnStatus {
+  CRN_STATUS_OK = 0,
+  // a null pointer or a level or face past the end
+  CRN_STATUS_INVALID_ARGUMENT = 1,
+  // not a crn file, or its header doesn't fit the data
+  CRN_STATUS_INVALID_FILE = 2,
+  CRN_STATUS_CRC_MISMATCH = 3,
+  CRN_STATUS_UNSUPPORTED_FORMAT = 4,
+  // the output buffer is smaller than `crnlib_level_size` asks for
+  CRN_STATUS_BUFFER_TOO_SMALL = 5,
+  // the palettes, tables or level streams don't decode
+  CRN_STATUS_CORRUPT = 6,
+} CrnStatus;
+
+typedef uint32_t crn_uint32;
+
+// `crn_format`, the values of `Format`
+typedef uint32_t crn_format;
+
+typedef struct crn_texture_info {
+  crn_uint32 m_struct_size;
+  crn_uint32 m_width;
+  crn_uint32 m_height;
+  crn_uint32 m_levels;
+  crn_uint32 m_faces;
+  crn_format m_format;
+  crn_uint32 m_bytes_per_block;
+  crn_uint32 m_userdata0;
+  crn_uint32 m_userdata1;
+} crn_texture_info;
+
+typedef struct crn_level_info {
+  crn_uint32 m_struct_size;
+  crn_uint32 m_width;
+  crn_uint32 m_height;
+  crn_uint32 m_faces;
+  crn_uint32 m_blocks_x;
+  crn_uint32 m_blocks_y;
+  crn_uint32 m_bytes_per_block;
+  crn_format m_format;
+} crn_level_info;
+
+typedef void *crnd_unpack_context;
+
+typedef struct CrnTextureInfo {
+  uint32_t width;
+  uint32_t height;
+  uint32_t levels;
+  uint32_t faces;
+  // the values of `crn_format`
+  uint32_t format;
+  uint32_t bytes_per_block;
+} CrnTextureInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Fill `info` from the header of `data`, `info.m_struct_size` has to be set by the caller.
+bool crnd_get_texture_info(const void *data, crn_uint32 data_size, struct crn_texture_info *info);
+
+// Fill `info` for level `level_index` of `data`, `info.m_struct_size` has to be set by the caller.
+bool crnd_get_level_info(const void *data,
+                         crn_uint32 data_size,
+                         crn_uint32 level_index,
+                         struct crn_level_info *info);
+
+// Parse the header and tables of `data` into a context for `crnd_unpack_level`, null if the
+// file is invalid. Free it with `crnd_unpack_end`.
+crnd_unpack_context crnd_unpack_begin(const void *data, crn_uint32 data_size);
+
+// Decode level `level_index` into `dst[face]` for every face, `dst_size_in_bytes` each with rows
+// of blocks `row_pitch_in_bytes` apart, 0 for tightly packed rows.
+bool crnd_unpack_level(crnd_unpack_context context,
+                       void **dst,
+                       crn_uint32 dst_size_in_bytes,
+                       crn_uint32 row_pitch_in_bytes,
+                       crn_uint32 level_index);
+
+// Free a context of `crnd_unpack_begin`, false for null.
+bool crnd_unpack_end(crnd_unpack_context context);
+
+// Check the header of the `size` bytes at `data` and describe the texture in `info`.
+CrnStatus crnlib_texture_info(const uint8_t *data, size_t size, struct CrnTextureInfo *info);
+
+// Bytes `crnlib_decode_level` writes for `level`, the blocks of all faces.
+CrnStatus crnlib_level_size(const uint8_t *data, size_t size, uint32_t level, size_t *out_size);
+
+// Decode the blocks of `level` into `out`, rows of blocks tightly packed and faces one after another.
+CrnStatus crnlib_decode_level(const uint8_t *data,
+                              size_t size,
+                              uint32_t level,
+                              uint8_t *out,
+                              size_t out_size);
+
+// Decode `face` of `level` into `width * height * 4` bytes of RGBA at `out`.
+CrnStatus crnlib_decode_level_rgba(const uint8_t *data,
+                                   size_t size,
+                                   uint32_t level,
+                                   uint32_t face,
+                                   uint8_t *out,
+                                   size_t out_size);
+
+// A static NUL terminated description of the `CrnStatus` value `status`. It takes a plain int,
+// hosts may pass anything there, which an enum parameter would turn into undefined behavior.
+const char *crnlib_status_message(int status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CRNLIB_H */
@@ -0,0 +1,33 @@
+// Flat entry points into crunch's crn_decomp.h for `crnlib::reference`, built by build.rs
+// with the `reference` feature.
+#include <stdint.h>
+#include <string.h>
+#include "crn_decomp.h"
+
+using namespace crnd;
+
+extern "C" bool crnlib_reference_level_size(const void *data, uint32_t size, uint32_t level, uint32_t *out_size) {
+  crn_level_info info;
+  info.m_struct_size = sizeof(info);
+  if (!crnd_get_level_info(data, size, level, &info)) return false;
+  *out_size = info.m_blocks_x * info.m_blocks_y * info.m_bytes_per_block * info.m_faces;
+  return true;
+}
+
+// blocks of every face one after another, rows tightly packed as `Header::unpack_level` returns them
+extern "C" bool crnlib_reference_unpack_level(const void *data, uint32_t size, uint32_t level, void *out, uint32_t out_size) {
+  crn_level_info info;
+  info.m_struct_size = sizeof(info);
+  if (!crnd_get_level_info(data, size, level, &info) || info.m_faces > 6) return false;
+  uint32_t row_size = info.m_blocks_x * info.m_bytes_per_block;
+  uint32_t face_size = row_size * info.m_blocks_y;
+  if (out_size < face_size * info.m_faces) return false;
+  memset(out, 0, out_size);
+  void *faces[6];
+  for (uint32_t f = 0; f < info.m_faces; f++) faces[f] = (uint8_t *)out + f * face_size;
+  crnd_unpack_context context = crnd_unpack_begin(data, size);
+  if (!context) return false;
+  bool result = crnd_unpack_level(context, faces, face_size, row_size, level);
+  crnd_unpack_end(context);
+  return result;
+}
@@ -0,0 +1,153 @@
+//! Decoding many files at once on a bounded number of threads, for asset pipelines.
+
+use std::{borrow::Cow, path::PathBuf, sync::{Mutex, atomic::{AtomicUsize, Ordering}}, time::{Duration, Instant}};
+use crate::error::{Context, CrnError as Error};
+use crate::{DecodeLimits, DecodeSession, Header, ParseOptions};
+
+/// A file to decode, either read from `Path` or already in memory.
+#[derive(Debug, Clone)]
+pub enum BatchInput<'a> {
+  Path(PathBuf),
+  Buffer(Cow<'a, [u8]>),
+}
+
+impl From<PathBuf> for BatchInput<'_> {
+  fn from(path: PathBuf) -> Self {
+    BatchInput::Path(path)
+  }
+}
+
+impl From<&std::path::Path> for BatchInput<'_> {
+  fn from(path: &std::path::Path) -> Self {
+    BatchInput::Path(path.to_path_buf())
+  }
+}
+
+impl<'a> From<&'a [u8]> for BatchInput<'a> {
+  fn from(input: &'a [u8]) -> Self {
+    BatchInput::Buffer(input.into())
+  }
+}
+
+impl From<Vec<u8>> for BatchInput<'_> {
+  fn from(input: Vec<u8>) -> Self {
+    BatchInput::Buffer(input.into())
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BatchOptions {
+  /// files decoded at once, 0 for one per core
+  pub threads: usize,
+  pub parse: ParseOptions,
+  pub limits: DecodeLimits,
+}
+
+/// Every level of a file unpacked into BCn blocks.
+#[derive(Debug, Clone)]
+pub struct DecodedFile {
+  pub header: Header,
+  pub levels: Vec<Vec<u8>>,
+}
+
+/// Outcome of one input of [`decode_many`].
+#[derive(Debug)]
+pub struct BatchResult {
+  /// reading (for paths), parsing and unpacking
+  pub elapsed: Duration,
+  pub result: Result<DecodedFile, Error>,
+}
+
+fn read<'a>(input: &'a BatchInput) -> Result<Cow<'a, [u8]>, Error> {
+  Ok(match input {
+    BatchInput::Path(path) => Cow::Owned(std::fs::read(path).with_context(|| format!("read {}", path.display()))?),
+    BatchInput::Buffer(input) => Cow::Borrowed(&input[..]),
+  })
+}
+
+fn decode_one(input: &[u8], options: &BatchOptions) -> Result<DecodedFile, Error> {
+  let session = DecodeSession::with_options(input, &options.parse, &options.limits)?;
+  let levels = (0..session.level_count()).map(|idx| session.unpack_level(idx)).collect::<Result<_, _>>()?;
+  Ok(DecodedFile { header: session.header().clone(), levels })
+}
+
+/// decode every level of every input on at most `options.threads` threads, results are in input order
+pub fn decode_many<'a, I>(inputs: I, options: &BatchOptions) -> Vec<BatchResult>
+  where I: IntoIterator, I::Item: Into<BatchInput<'a>> {
+  decode_each(inputs, options, |_, _, result| result)
+}
+
+/// Like `decode_many`, but every result goes to `f` on the thread that decoded it, together with
+/// the input (empty if it couldn't be read), and only what `f` returns is kept, in input order. So
+/// at most `threads` files are in memory at once when `f` drops the levels.
+pub fn decode_each<'a, I, T, F>(inputs: I, options: &BatchOptions, f: F) -> Vec<T>
+  where I: IntoIterator, I::Item: Into<BatchInput<'a>>, T: Send, F: Fn(usize, &[u8], BatchResult) -> T + Sync {
+  let inputs = inputs.into_iter().map(Into::into).collect::<Vec<BatchInput>>();
+  let threads = match options.threads {
+    0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    n => n,
+  }.min(inputs.len()).max(1);
+  let results = Mutex::new((0..inputs.len()).map(|_| None).collect::<Vec<_>>());
+  let next = AtomicUsize::new(0);
+  std::thread::scope(|scope| {
+    for _ in 0..threads {
+      scope.spawn(|| loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let Some(input) = inputs.get(i) else { break };
+        let start = Instant::now();
+        let (input, result) = match read(input) {
+          Ok(input) => { let result = decode_one(&input, options); (input, result) }
+          Err(e) => (Cow::Borrowed(&[][..]), Err(e)),
+        };
+        let elapsed = start.elapsed();
+        let output = f(i, &input, BatchResult { elapsed, result });
+        results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(output);
+      });
+    }
+  });
+  results.into_inner().unwrap_or_else(|e| e.into_inner()).into_iter().map(|r| r.expect("every input decoded")).collect()
+}
+
+#[test]
+fn test_decode_many() {
+  use crate::{Format, testgen::TestCrn};
+  let mut inputs = vec![TestCrn::sample().build().unwrap()];
+  for (format, faces) in [(Format::Dxt1, 1), (Format::Dxt5, 6), (Format::Dxt5A, 1), (Format::DxnXY, 2)] {
+    inputs.push(TestCrn { faces, ..TestCrn::new(format, 20, 12) }.build().unwrap());
+  }
+  let missing = std::env::temp_dir().join("crnlib-test-batch-missing.crn");
+  let mut batch: Vec<BatchInput> = inputs.iter().map(|input| input[..].into()).collect();
+  batch.insert(2, missing.into());
+  batch.push((&inputs[0][1..]).into());
+  let results = decode_many(batch, &BatchOptions { threads: 2, ..Default::default() });
+  assert_eq!(results.len(), inputs.len() + 2);
+  assert!(matches!(results[2].result.as_ref().unwrap_err().root(), Error::Io(_)));
+  assert!(results.last().unwrap().result.is_err());
+  // the rest are in input order, decoded as unpack_level does
+  let decoded = results[..2].iter().chain(&results[3..results.len() - 1]);
+  for (input, result) in inputs.iter().zip(decoded) {
+    let file = result.result.as_ref().unwrap();
+    let header = Header::parse(input).unwrap();
+    let tables = header.get_table(input).unwrap();
+    assert_eq!(file.header.format, header.format);
+    let levels = (0..header.level_count as usize).map(|l| header.unpack_level(&tables, input, l).unwrap()).collect::<Vec<_>>();
+    assert_eq!(file.levels, levels);
+  }
+}
+
+#[test]
+fn test_decode_each() {
+  use crate::{Format, testgen::TestCrn};
+  let inputs = [TestCrn::sample().build().unwrap(), TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 20, 12) }.build().unwrap()];
+  let missing = std::env::temp_dir().join("crnlib-test-batch-missing.crn");
+  let batch = vec![BatchInput::from(&inputs[0][..]), missing.into(), (&inputs[1][..]).into()];
+  let sizes = decode_each(batch, &BatchOptions { threads: 2, ..Default::default() }, |i, input, result| {
+    let levels = result.result.map(|file| file.levels.iter().map(Vec::len).sum::<usize>());
+    (i, input.len(), levels.ok())
+  });
+  let expected = inputs.iter().map(|input| {
+    let header = Header::parse(input).unwrap();
+    (0..header.level_count as usize).map(|l| header.unpack_level(&header.get_table(input).unwrap(), input, l).unwrap().len()).sum::<usize>()
+  }).collect::<Vec<_>>();
+  assert_eq!(sizes, [(0, inputs[0].len(), Some(expected[0])), (1, 0, None), (2, inputs[1].len(), Some(expected[1]))]);
+}
@@ -0,0 +1,112 @@
+//! A Bevy `AssetLoader` for crn files, turning them into an `Image` through `gpu::TextureUpload`.
+//!
+//! Register it with `app.register_asset_loader(CrnLoader::new(formats))`, where `formats` is the
+//! `CompressedImageFormats` of the render device; without `BC` the levels are expanded into RGBA8.
+//! Bevy uploads `Image::data` layer by layer, every layer with all its levels, so the levels are
+//! laid out without row padding and the faces moved to the front. Six faces are viewed as a cube.
+
+use bevy_asset::{AssetLoader, LoadContext, RenderAssetUsages, io::Reader};
+use bevy_image::{CompressedImageFormats, Image, ImageSampler};
+use serde::{Serialize, Deserialize};
+use crate::error::CrnError as Error;
+use crate::gpu::TextureUpload;
+use crate::{Header, ParseOptions};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrnLoaderSettings {
+  /// the color levels hold sRGB, like `ImageLoaderSettings::is_srgb`
+  pub srgb: bool,
+  pub sampler: ImageSampler,
+  pub asset_usage: RenderAssetUsages,
+}
+
+impl Default for CrnLoaderSettings {
+  fn default() -> Self {
+    Self { srgb: true, sampler: ImageSampler::Default, asset_usage: RenderAssetUsages::default() }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CrnLoader {
+  supported_compressed_formats: CompressedImageFormats,
+}
+
+impl CrnLoader {
+  pub fn new(supported_compressed_formats: CompressedImageFormats) -> Self {
+    Self { supported_compressed_formats }
+  }
+
+  /// the `Image` of a whole crn file, what `load` returns once the file is read
+  pub fn image(&self, input: &[u8], settings: &CrnLoaderSettings) -> Result<Image, Error> {
+    let header = Header::parse_with(input, &ParseOptions::default())?;
+    let tables = header.get_table(input)?;
+    let mut upload = TextureUpload::new(&header, settings.srgb, 1)?;
+    if !self.supported_compressed_formats.supports(upload.format.into()) {
+      upload = TextureUpload::rgba(&header, settings.srgb, 1)?;
+    }
+    let data = upload.unpack(&header, &tables, input)?;
+    let mut layered = Vec::with_capacity(upload.size);
+    for layer in 0..upload.layers as usize {
+      for level in &upload.levels {
+        let slice_size = level.bytes_per_row as usize * level.rows_per_image as usize;
+        layered.extend_from_slice(&data[level.range.start + layer * slice_size..][..slice_size]);
+      }
+    }
+    let mut image = Image {
+      data: layered,
+      texture_descriptor: upload.into_descriptor(None, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST),
+      sampler: settings.sampler.clone(),
+      texture_view_descriptor: None,
+      asset_usage: settings.asset_usage,
+    };
+    if upload.layers == 6 {
+      // the view descriptor is wgpu's own, not in wgpu-types
+      image.texture_view_descriptor.insert(Default::default()).dimension = Some(wgpu::TextureViewDimension::Cube);
+    }
+    Ok(image)
+  }
+}
+
+/// the device formats are unknown, BC is what desktop GPUs have
+impl Default for CrnLoader {
+  fn default() -> Self {
+    Self::new(CompressedImageFormats::BC)
+  }
+}
+
+impl AssetLoader for CrnLoader {
+  type Asset = Image;
+  type Settings = CrnLoaderSettings;
+  type Error = Error;
+
+  async fn load(&self, reader: &mut dyn Reader, settings: &CrnLoaderSettings, _: &mut LoadContext<'_>) -> Result<Image, Error> {
+    let mut input = vec![];
+    reader.read_to_end(&mut input).await?;
+    self.image(&input, settings)
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["crn"]
+  }
+}
+
+#[test]
+fn test_bevy() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt1, 12, 8) };
+  let (input, header, tables) = gen.parsed().unwrap();
+  let image = CrnLoader::default().image(&input, &CrnLoaderSettings::default()).unwrap();
+  assert_eq!(image.texture_descriptor.format, wgpu::TextureFormat::Bc1RgbaUnormSrgb);
+  assert_eq!(image.texture_descriptor.size, wgpu::Extent3d { width: 12, height: 8, depth_or_array_layers: 6 });
+  assert_eq!(image.texture_descriptor.mip_level_count, header.level_count as u32);
+  assert_eq!(image.texture_view_descriptor.unwrap().dimension, Some(wgpu::TextureViewDimension::Cube));
+  // face by face, every face with its levels
+  let levels = (0..header.level_count as usize).map(|l| header.unpack_level(&tables, &input, l).unwrap()).collect::<Vec<_>>();
+  let expected = (0..6).flat_map(|face| levels.iter().flat_map(move |blocks| blocks.chunks_exact(blocks.len() / 6).nth(face).unwrap()).copied()).collect::<Vec<_>>();
+  assert_eq!(image.data, expected);
+
+  let rgba = CrnLoader::new(CompressedImageFormats::NONE).image(&input, &CrnLoaderSettings { srgb: false, ..Default::default() }).unwrap();
+  assert_eq!(rgba.texture_descriptor.format, wgpu::TextureFormat::Rgba8Unorm);
+  assert_eq!(&rgba.data[..12 * 8 * 4], &crate::rgba::decode_image(Format::Dxt1, &levels[0][..levels[0].len() / 6], 12, 8).unwrap()[..]);
+  assert!(CrnLoader::default().image(&input[1..], &CrnLoaderSettings::default()).is_err());
+}
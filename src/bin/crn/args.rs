@@ -0,0 +1,57 @@
+//! `--name value` options and positional arguments in any order.
+
+use crate::Result;
+
+pub struct Args {
+  positional: Vec<String>,
+  options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+  /// split `args`, `flags` are the options taking no value
+  pub fn parse(args: &[String], flags: &[&str]) -> Result<Self> {
+    let (mut positional, mut options) = (vec![], vec![]);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+      let Some(name) = arg.strip_prefix("--") else { positional.push(arg.clone()); continue };
+      if let Some((name, value)) = name.split_once('=') {
+        options.push((name.to_string(), Some(value.to_string())));
+      } else if flags.contains(&name) {
+        options.push((name.to_string(), None));
+      } else {
+        let value = iter.next().ok_or_else(|| format!("--{} needs a value", name))?;
+        options.push((name.to_string(), Some(value.clone())));
+      }
+    }
+    Ok(Self { positional, options })
+  }
+
+  pub fn positional(&self) -> &[String] {
+    &self.positional
+  }
+
+  pub fn value(&self, name: &str) -> Option<&str> {
+    self.options.iter().rev().find(|(n, _)| n == name).and_then(|(_, v)| v.as_deref())
+  }
+
+  /// whether the option was given, for `flags`
+  pub fn flag(&self, name: &str) -> bool {
+    self.options.iter().any(|(n, _)| n == name)
+  }
+
+  /// `value` parsed, `default` when missing
+  pub fn parse_value<T: std::str::FromStr>(&self, name: &str, default: T) -> Result<T> {
+    match self.value(name) {
+      Some(v) => v.parse().map_err(|_| format!("invalid --{} {:?}", name, v).into()),
+      None => Ok(default),
+    }
+  }
+
+  /// fail on options besides `known`, so typos don't go unnoticed
+  pub fn check(&self, known: &[&str]) -> Result<()> {
+    match self.options.iter().find(|(n, _)| !known.contains(&n.as_str())) {
+      Some((name, _)) => Err(format!("unknown option --{}", name).into()),
+      None => Ok(()),
+    }
+  }
+}
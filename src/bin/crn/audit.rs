@@ -0,0 +1,68 @@
+//! `crn audit`: parse, check the CRCs of and fully decode every crn file under some directories,
+//! with a JSON line per file that failed.
+
+use crnlib::CrnError;
+use crnlib::batch::{BatchOptions, decode_each};
+use crate::{Result, args::Args, describe, glob::expand, value::{Value::*, map}};
+
+/// (status, stage) of a failure, the stage is where it was detected
+fn classify(e: &CrnError) -> (&'static str, &'static str) {
+  match e.root() {
+    CrnError::Io(_) => ("unreadable", "read"),
+    CrnError::InvalidMagic(_) | CrnError::Header(_) | CrnError::SizeMismatch { .. } | CrnError::UnknownFlags(_) | CrnError::LimitExceeded { .. } => ("corrupt", "header"),
+    CrnError::CrcMismatch => ("corrupt", "crc"),
+    CrnError::UnsupportedFormat { .. } => ("unsupported", "header"),
+    _ => ("corrupt", "decode"),
+  }
+}
+
+pub fn run(args: &[String]) -> Result<bool> {
+  let args = Args::parse(args, &["all"])?;
+  args.check(&["jobs", "all"])?;
+  if args.positional().is_empty() { return Err("no directories".into()) }
+  let mut paths = vec![];
+  for pattern in args.positional() {
+    paths.extend(expand(pattern)?.into_iter().map(|m| m.path));
+  }
+  let options = BatchOptions { threads: args.parse_value("jobs", 0)?, ..Default::default() };
+  // only the header is kept, the levels are dropped on the worker as soon as they are decoded
+  let results = decode_each(paths.iter().map(|p| p.as_path()), &options, |_, _, result| result.result.map(|file| file.header));
+  let mut counts = [0; 3];
+  for (path, result) in paths.iter().zip(results) {
+    let path = Text(path.display().to_string());
+    let line = match result {
+      Ok(header) => {
+        counts[0] += 1;
+        if !args.flag("all") { continue }
+        map([
+          ("path", path), ("status", Text("ok".to_string())),
+          ("format", Text(format!("{:?}", header.format))), ("width", Int(header.width as u64)), ("height", Int(header.height as u64)),
+          ("levels", Int(header.level_count as u64)), ("faces", Int(header.face_count as u64)),
+        ])
+      }
+      Err(e) => {
+        let (status, stage) = classify(&e);
+        counts[if status == "unsupported" { 2 } else { 1 }] += 1;
+        let mut fields = vec![
+          ("path".to_string(), path), ("status".to_string(), Text(status.to_string())), ("stage".to_string(), Text(stage.to_string())),
+          ("error".to_string(), Text(describe(&e))),
+        ];
+        if let Some(bit) = e.bit_position() { fields.push(("bit".to_string(), Int(bit as u64))) }
+        Map(fields)
+      }
+    };
+    let mut out = String::new();
+    line.json(&mut out);
+    println!("{}", out);
+  }
+  eprintln!("audited {} files: {} ok, {} corrupt or unreadable, {} unsupported", paths.len(), counts[0], counts[1], counts[2]);
+  Ok(counts[0] == paths.len())
+}
+
+#[test]
+fn test_classify() {
+  assert_eq!(classify(&CrnError::CrcMismatch), ("corrupt", "crc"));
+  assert_eq!(classify(&CrnError::UnsupportedFormat { format: crnlib::Format::Etc1 }), ("unsupported", "header"));
+  assert_eq!(classify(&crnlib::Header::parse(b"Hx").unwrap_err()), ("corrupt", "header"));
+  assert_eq!(classify(&CrnError::TrailingData), ("corrupt", "decode"));
+}
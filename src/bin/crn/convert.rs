@@ -0,0 +1,183 @@
+//! `crn convert`: crn files to DDS on several threads, keeping the directory structure. With
+//! `--watch` it keeps polling the inputs and converts files again as they are added or modified
+//! (failed ones on every poll until they convert), with `--sidecar` a `transcode::Sidecar` JSON is
+//! written next to every output. `--progress` decodes one file after another with a status line
+//! on stderr, for a few large textures.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use crnlib::CrnError;
+use crnlib::batch::{BatchOptions, decode_each};
+use crnlib::transcode::{Dds, Sidecar};
+use crate::{Result, args::Args, describe, glob::{Matched, expand}, size};
+
+/// a file to convert and where its output goes
+struct Job {
+  input: Matched,
+  output: PathBuf,
+}
+
+/// how often `--watch` looks for changes
+const POLL: Duration = Duration::from_millis(500);
+/// how often `--progress` redraws its line
+const REDRAW: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+struct Options {
+  threads: usize,
+  sidecar: bool,
+  progress: bool,
+}
+
+/// `warn` about patterns matching nothing, which is expected while watching. A file matched twice
+/// is converted once, two files with the same output (e.g. `a/x.crn` and `b/x.crn` into one
+/// `--out-dir`) are an error.
+fn jobs(inputs: &[String], out_dir: Option<&str>, extension: &str, warn: bool) -> Result<Vec<Job>> {
+  let mut jobs = vec![];
+  let mut outputs: HashMap<PathBuf, PathBuf> = HashMap::new();
+  for pattern in inputs {
+    let matched = expand(pattern).map_err(|e| format!("{}: {}", pattern, e))?;
+    if matched.is_empty() && warn { eprintln!("crn: nothing matches {}", pattern) }
+    for input in matched {
+      let output = match out_dir {
+        Some(dir) => PathBuf::from(dir).join(&input.relative),
+        None => input.path.clone(),
+      }.with_extension(extension);
+      match outputs.get(&output) {
+        Some(other) if *other == input.path => continue,
+        Some(other) => return Err(format!("{} and {} both convert to {}", other.display(), input.path.display(), output.display()).into()),
+        None => { outputs.insert(output.clone(), input.path.clone()); }
+      }
+      jobs.push(Job { input, output });
+    }
+  }
+  Ok(jobs)
+}
+
+/// write the output of `job` and with `sidecar` the sidecar of `input`, the bytes read and written
+fn write(job: &Job, input: &[u8], dds: Dds, sidecar: bool) -> std::result::Result<(usize, usize), String> {
+  let dds = dds.to_bytes().map_err(|e| describe(&e))?;
+  if let Some(dir) = job.output.parent() { std::fs::create_dir_all(dir).map_err(|e| e.to_string())? }
+  std::fs::write(&job.output, &dds).map_err(|e| e.to_string())?;
+  if sidecar {
+    let json = Sidecar::new(input).map_err(|e| describe(&e))?.to_json() + "\n";
+    std::fs::write(job.output.with_extension("json"), json).map_err(|e| e.to_string())?;
+  }
+  Ok((input.len(), dds.len()))
+}
+
+/// convert one job after another with a progress line on stderr
+fn convert_with_progress(jobs: &[Job], sidecar: bool) -> Vec<std::result::Result<(usize, usize), String>> {
+  let results = jobs.iter().enumerate().map(|(i, job)| {
+    let input = std::fs::read(&job.input.path).map_err(|e| e.to_string())?;
+    let mut redrawn: Option<Instant> = None;
+    let texture = crnlib::decode_with_progress(&input, |p| {
+      if redrawn.is_some_and(|t| t.elapsed() < REDRAW) && p.blocks != p.total_blocks { return Ok(()) }
+      redrawn = Some(Instant::now());
+      let eta = p.eta().map_or("?".to_string(), |eta| format!("{:.1}s", eta.as_secs_f64()));
+      eprint!(
+        "\r[{}/{}] {} level {}/{} {:.0}% eta {}\x1b[K",
+        i + 1, jobs.len(), job.input.relative.display(), p.level + 1, p.levels, p.fraction() * 100.0, eta,
+      );
+      Ok(())
+    }).map_err(|e| describe(&e))?;
+    let info = texture.info;
+    write(job, &input, Dds { format: info.format, width: info.width as u16, height: info.height as u16, faces: info.faces as u8, levels: texture.levels }, sidecar)
+  }).collect();
+  if !jobs.is_empty() { eprintln!() }
+  results
+}
+
+/// convert every job, printing failures and a summary, whether each of them succeeded
+fn convert(jobs: &[Job], options: Options) -> Vec<bool> {
+  let start = Instant::now();
+  let results = if options.progress { convert_with_progress(jobs, options.sidecar) } else {
+    // every worker writes its file and drops the blocks before taking the next one
+    let batch = BatchOptions { threads: options.threads, ..Default::default() };
+    decode_each(jobs.iter().map(|job| job.input.path.as_path()), &batch, |i, input, result| {
+      // the path is in front of the message already
+      let file = result.result.map_err(|e| match e.root() { CrnError::Io(e) => e.to_string(), _ => describe(&e) })?;
+      let header = file.header;
+      write(&jobs[i], input, Dds { format: header.format, width: header.width, height: header.height, faces: header.face_count, levels: file.levels }, options.sidecar)
+    })
+  };
+  let (mut succeeded, mut read, mut written) = (vec![], 0, 0);
+  for (job, result) in jobs.iter().zip(results) {
+    match result {
+      Ok((size, dds)) => { read += size; written += dds }
+      Err(ref e) => eprintln!("{}: {}", job.input.path.display(), e),
+    }
+    succeeded.push(result.is_ok());
+  }
+  let converted = succeeded.iter().filter(|&&ok| ok).count();
+  println!("converted {} of {} files, {} to {} in {:.2}s", converted, jobs.len(), size(read), size(written), start.elapsed().as_secs_f64());
+  succeeded
+}
+
+/// convert the inputs, then every file added or modified since, until interrupted;
+/// files that failed are tried again on the next poll
+fn watch(inputs: &[String], out_dir: Option<&str>, options: Options) -> Result<bool> {
+  let mut seen: HashMap<PathBuf, SystemTime> = HashMap::new();
+  let mut first = true;
+  let mut failed: Option<String> = None;
+  loop {
+    // a directory could be missing or unreadable for a moment, keep watching and report it once
+    let jobs = match jobs(inputs, out_dir, "dds", first) {
+      Ok(jobs) => { failed = None; jobs }
+      Err(e) => {
+        let e = e.to_string();
+        if failed.as_ref() != Some(&e) { eprintln!("crn: {}", e) }
+        failed = Some(e);
+        vec![]
+      }
+    };
+    let changed: Vec<Job> = jobs.into_iter().filter(|job| {
+      // unreadable files are left for a later poll
+      let Ok(modified) = std::fs::metadata(&job.input.path).and_then(|m| m.modified()) else { return false };
+      seen.insert(job.input.path.clone(), modified) != Some(modified)
+    }).collect();
+    if !changed.is_empty() {
+      for (job, ok) in changed.iter().zip(convert(&changed, options)) {
+        if !ok { seen.remove(&job.input.path); }
+      }
+    }
+    if first { println!("watching for changes") }
+    first = false;
+    std::thread::sleep(POLL);
+  }
+}
+
+pub fn run(args: &[String]) -> Result<bool> {
+  let args = Args::parse(args, &["watch", "sidecar", "progress"])?;
+  args.check(&["to", "jobs", "out-dir", "watch", "sidecar", "progress"])?;
+  match args.value("to") {
+    Some("dds") => {}
+    Some(to) => return Err(format!("can't convert to {}, only to dds", to).into()),
+    None => return Err("--to is required".into()),
+  }
+  if args.positional().is_empty() { return Err("no inputs".into()) }
+  let options = Options { threads: args.parse_value("jobs", 0)?, sidecar: args.flag("sidecar"), progress: args.flag("progress") };
+  if args.flag("watch") { return watch(args.positional(), args.value("out-dir"), options) }
+  let jobs = jobs(args.positional(), args.value("out-dir"), "dds", true)?;
+  Ok(convert(&jobs, options).into_iter().all(|ok| ok))
+}
+
+#[test]
+fn test_jobs() {
+  let dir = std::env::temp_dir().join(format!("crn-convert-{}", std::process::id()));
+  for file in ["a/x.crn", "b/x.crn", "b/y.crn"] {
+    let path = dir.join(file);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, b"").unwrap();
+  }
+  let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+  let out = dir.join("out");
+  let outputs = |inputs: &[String], out_dir: Option<&str>| jobs(inputs, out_dir, "dds", false).map(|jobs| jobs.into_iter().map(|j| j.output).collect::<Vec<_>>());
+  // next to the inputs, or in the out dir by file name
+  assert_eq!(outputs(&[path("a/x.crn"), path("b/x.crn")], None).unwrap(), [dir.join("a/x.dds"), dir.join("b/x.dds")]);
+  assert_eq!(outputs(&[path("b"), path("b/y.crn")], out.to_str()).unwrap(), [out.join("x.dds"), out.join("y.dds")]);
+  let error = outputs(&[path("a/x.crn"), path("b")], out.to_str()).unwrap_err().to_string();
+  assert!(error.contains("both convert to") && error.contains(&path("b/x.crn")), "{}", error);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
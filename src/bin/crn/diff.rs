@@ -0,0 +1,77 @@
+//! `crn diff`: where two crn files differ, header fields, palette entries and decoded blocks.
+
+use crnlib::{DecodeSession, Header};
+use crate::{Result, args::Args};
+
+/// the header fields worth comparing, the CRCs and offsets follow from the rest
+fn fields(h: &Header) -> Vec<(&'static str, String)> {
+  vec![
+    ("width", h.width.to_string()), ("height", h.height.to_string()),
+    ("levels", h.level_count.to_string()), ("faces", h.face_count.to_string()),
+    ("format", format!("{:?}", h.format)), ("flags", format!("{:#x}", h.flags)),
+    ("userdata", format!("{:x?}", h.userdata)), ("file_size", h.file_size.to_string()),
+    ("color_endpoints", h.color_endpoints.count.to_string()), ("color_selectors", h.color_selectors.count.to_string()),
+    ("alpha_endpoints", h.alpha_endpoints.count.to_string()), ("alpha_selectors", h.alpha_selectors.count.to_string()),
+  ]
+}
+
+/// print the first entry of a palette that differs, true if they are the same
+fn palette<T: PartialEq + std::fmt::Debug>(name: &str, a: Result<Vec<T>>, b: Result<Vec<T>>) -> bool {
+  let (a, b) = match (a, b) {
+    (Ok(a), Ok(b)) => (a, b),
+    (a, b) => {
+      println!("{}: can't compare, {:?} and {:?}", name, a.err().map(|e| e.to_string()), b.err().map(|e| e.to_string()));
+      return false
+    }
+  };
+  match a.iter().zip(&b).position(|(a, b)| a != b) {
+    Some(i) => println!("{} entry {}: {:x?} and {:x?}", name, i, a[i], b[i]),
+    None if a.len() != b.len() => println!("{}: {} and {} entries, the first {} are the same", name, a.len(), b.len(), a.len().min(b.len())),
+    None => return true,
+  }
+  false
+}
+
+pub fn run(args: &[String]) -> Result<bool> {
+  let args = Args::parse(args, &[])?;
+  args.check(&[])?;
+  let [a, b] = args.positional() else { return Err("diff takes two files".into()) };
+  let (a_input, b_input) = (std::fs::read(a)?, std::fs::read(b)?);
+  let (a, b) = (DecodeSession::new(&a_input[..])?, DecodeSession::new(&b_input[..])?);
+  let (ha, hb) = (a.header(), b.header());
+  let mut same = true;
+
+  for ((name, va), (_, vb)) in fields(ha).into_iter().zip(fields(hb)) {
+    if va != vb {
+      println!("header {}: {} and {}", name, va, vb);
+      same = false;
+    }
+  }
+
+  same &= palette("color_endpoints", ha.get_color_endpoints(a.input()).map_err(Into::into), hb.get_color_endpoints(b.input()).map_err(Into::into));
+  same &= palette("color_selectors", ha.get_color_selectors(a.input()).map_err(Into::into), hb.get_color_selectors(b.input()).map_err(Into::into));
+  same &= palette("alpha_endpoints", ha.get_alpha_endpoints(a.input()).map_err(Into::into), hb.get_alpha_endpoints(b.input()).map_err(Into::into));
+  same &= palette("alpha_selectors", ha.get_alpha_selectors(a.input()).map_err(Into::into), hb.get_alpha_selectors(b.input()).map_err(Into::into));
+
+  let block_size = ha.block_size();
+  for level in 0..a.level_count().min(b.level_count()) {
+    let (Some(size), true) = (ha.get_level_info(level), ha.get_level_info(level) == hb.get_level_info(level) && ha.face_count == hb.face_count && block_size == hb.block_size()) else {
+      println!("level {}: dimensions or format differ, not compared", level);
+      same = false;
+      continue
+    };
+    let (la, lb) = (a.unpack_level(level)?, b.unpack_level(level)?);
+    let (block_x, block_y) = (size.0.div_ceil(4) as usize, size.1.div_ceil(4) as usize);
+    let differing = la.chunks_exact(block_size).zip(lb.chunks_exact(block_size)).enumerate().filter(|(_, (a, b))| a != b).map(|(i, _)| i).collect::<Vec<_>>();
+    if let Some(&first) = differing.first() {
+      let (face, y, x) = (first / (block_x * block_y), first / block_x % block_y, first % block_x);
+      println!(
+        "level {} face {} block ({}, {}): {:02x?} and {:02x?}, {} of {} blocks differ",
+        level, face, x, y, &la[first * block_size..][..block_size], &lb[first * block_size..][..block_size], differing.len(), la.len() / block_size,
+      );
+      same = false;
+    }
+  }
+  if same { println!("same") }
+  Ok(same)
+}
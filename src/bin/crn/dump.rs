@@ -0,0 +1,161 @@
+//! `crn dump`: the header, byte ranges and with `--tables` the code lengths and palette entries of
+//! a file, as text or JSON. Every part is read on its own and failures are shown in place, so it
+//! also works on files the decoder rejects.
+
+use crnlib::{Header, ParseOptions, Palette};
+use crnlib::codec::{Codec, Huffman, decode_code_lengths};
+use crate::{Result, args::Args, describe, value::{Value::{self, *}, map}};
+
+fn error(e: &crnlib::CrnError) -> Value {
+  map([("error", Text(describe(e)))])
+}
+
+impl Value {
+  /// `key: value` indented, lists of scalars 16 or 8 to a line prefixed by the index of the first
+  fn text(&self, key: &str, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match self {
+      List(items) if items.iter().all(Value::is_scalar) => {
+        *out += &format!("{}{}: {} entries\n", pad, key, items.len());
+        let items: Vec<String> = items.iter().map(Value::scalar).collect();
+        let per_row = if items.iter().any(|s| s.len() > 4) { 8 } else { 16 };
+        for (i, row) in items.chunks(per_row).enumerate() {
+          *out += &format!("{}  {:>5}: {}\n", pad, i * per_row, row.join(" "));
+        }
+      }
+      List(items) => {
+        *out += &format!("{}{}:\n", pad, key);
+        for (i, item) in items.iter().enumerate() { item.text(&i.to_string(), indent + 1, out) }
+      }
+      Map(entries) => {
+        *out += &format!("{}{}:\n", pad, key);
+        for (key, value) in entries { value.text(key, indent + 1, out) }
+      }
+      value => *out += &format!("{}{}: {}\n", pad, key, value.scalar()),
+    }
+  }
+}
+
+fn palettes(header: &Header) -> [(&'static str, Palette); 4] {
+  [
+    ("color_endpoints", header.color_endpoints), ("color_selectors", header.color_selectors),
+    ("alpha_endpoints", header.alpha_endpoints), ("alpha_selectors", header.alpha_selectors),
+  ]
+}
+
+fn header_value(header: &Header, input: &[u8]) -> Value {
+  let report = header.validate(input);
+  map([
+    ("magic", Text(String::from_utf8_lossy(&header.magic).into_owned())),
+    ("header_size", Int(header.header_size as u64)),
+    ("header_crc16", Text(format!("{:#06x}", header.header_crc16))),
+    ("file_size", Int(header.file_size as u64)),
+    ("data_crc16", Text(format!("{:#06x}", header.data_crc16))),
+    ("width", Int(header.width as u64)),
+    ("height", Int(header.height as u64)),
+    ("levels", Int(header.level_count as u64)),
+    ("faces", Int(header.face_count as u64)),
+    ("format", Text(format!("{:?}", header.format))),
+    ("flags", Text(format!("{:#06x}", header.flags))),
+    ("userdata", List(header.userdata.iter().map(|&u| Text(format!("{:#010x}", u))).collect())),
+    ("findings", List(report.findings.iter().map(|f| Text(format!("{:?}: {}", f.severity, f.message))).collect())),
+  ])
+}
+
+/// where every part lies in the file, a level ends where the next begins
+fn ranges(header: &Header) -> Value {
+  let mut result = vec![("header".to_string(), Range(0, header.header_size as u64))];
+  for (name, p) in palettes(header) {
+    if p.count == 0 { continue }
+    result.push((name.to_string(), Range(p.offset as u64, p.offset as u64 + p.size as u64)));
+  }
+  result.push(("table".to_string(), Range(header.table_offset as u64, header.table_offset as u64 + header.table_size as u64)));
+  let segmented = header.flags & Header::SEGMENTED != 0;
+  for (idx, &start) in header.level_offset.iter().enumerate() {
+    let end = match header.level_offset.get(idx + 1) {
+      Some(&next) => next as u64,
+      None if segmented => continue,
+      None => header.file_size as u64,
+    };
+    result.push((format!("level {}", idx), Range(start as u64, end)));
+  }
+  Map(result)
+}
+
+/// Code lengths of the chunk encoding and palette delta tables, read without building the codes
+/// so tables `Header::get_table` rejects still show up to where they break.
+fn code_lengths(header: &Header, input: &[u8]) -> Value {
+  let (start, size) = (header.table_offset as usize, header.table_size as usize);
+  let Some(data) = input.get(start..).and_then(|data| data.get(..size)) else {
+    return map([("error", Text(format!("table {}..{} out of {} bytes", start, start + size, input.len())))])
+  };
+  let mut codec = Codec::new(data);
+  let names = std::iter::once("chunk_encoding").chain(Vec::from(palettes(header)).into_iter().filter(|(_, p)| p.count != 0).map(|(name, _)| name));
+  let mut result = vec![];
+  for name in names {
+    let bit = start * 8 + codec.current();
+    let lengths = codec.read_bits(Huffman::<()>::MAX_SYMBOL_COUNT_BIT).and_then(|count| decode_code_lengths(&mut codec, count as usize));
+    match lengths {
+      Ok(lengths) => result.push((name.to_string(), map([
+        ("bit", Int(bit as u64)),
+        ("lengths", List(lengths.into_iter().map(|l| Int(l as u64)).collect())),
+      ]))),
+      Err(e) => {
+        result.push((name.to_string(), map([("bit", Int(bit as u64)), ("error", Text(describe(&e)))])));
+        break
+      }
+    }
+  }
+  Map(result)
+}
+
+fn entries<T>(entries: std::result::Result<Vec<T>, crnlib::CrnError>, hex: impl Fn(&T) -> String) -> Value {
+  match entries {
+    Ok(entries) => List(entries.iter().map(|e| Text(hex(e))).collect()),
+    Err(e) => error(&e),
+  }
+}
+
+fn palette_entries(header: &Header, input: &[u8]) -> Value {
+  let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+  map([
+    ("color_endpoints", entries(header.get_color_endpoints(input), |(a, b)| format!("{:04x}:{:04x}", a, b))),
+    ("color_selectors", entries(header.get_color_selectors(input), |s| hex(s))),
+    ("alpha_endpoints", entries(header.get_alpha_endpoints(input), |(a, b)| format!("{:02x}:{:02x}", a, b))),
+    ("alpha_selectors", entries(header.get_alpha_selectors(input), |s| hex(s))),
+  ])
+}
+
+pub fn run(args: &[String]) -> Result<bool> {
+  let args = Args::parse(args, &["tables", "json"])?;
+  args.check(&["tables", "json"])?;
+  let [path] = args.positional() else { return Err("dump takes one file".into()) };
+  let input = std::fs::read(path)?;
+  let options = ParseOptions { verify_crc: false, allow_size_mismatch: true, allow_unknown_flags: true, ..Default::default() };
+  let header = Header::parse_with(&input, &options)?;
+  let mut dump = vec![
+    ("header".to_string(), header_value(&header, &input)),
+    ("ranges".to_string(), ranges(&header)),
+  ];
+  if args.flag("tables") {
+    dump.push(("code_lengths".to_string(), code_lengths(&header, &input)));
+    dump.push(("palettes".to_string(), palette_entries(&header, &input)));
+  }
+  let mut out = String::new();
+  if args.flag("json") {
+    Map(dump).json(&mut out);
+    out.push('\n');
+  } else {
+    for (key, value) in &dump { value.text(key, 0, &mut out) }
+  }
+  print!("{}", out);
+  Ok(true)
+}
+
+#[test]
+fn test_dump() {
+  let value = map([("name", Text("a \"b\"".to_string())), ("range", Range(2, 5)), ("lengths", List(vec![Int(1), Int(0)])), ("empty", Map(vec![]))]);
+  let mut out = String::new();
+  value.text("dump", 0, &mut out);
+  assert_eq!(out, "dump:\n  name: a \"b\"\n  range: 0x2..0x5 (3 bytes)\n  lengths: 2 entries\n        0: 1 0\n  empty:\n");
+}
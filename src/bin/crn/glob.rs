@@ -0,0 +1,96 @@
+//! Input patterns: files, directories taken recursively, and globs with `*`, `?` and `**`.
+
+use std::path::{Path, PathBuf};
+
+/// a file an input pattern matched, `relative` to the literal directory the pattern starts with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matched {
+  pub path: PathBuf,
+  pub relative: PathBuf,
+}
+
+fn is_glob(component: &str) -> bool {
+  component.contains(['*', '?'])
+}
+
+/// `name` against a single component pattern with `*` and `?`
+fn matches(pattern: &[char], name: &[char]) -> bool {
+  match pattern.split_first() {
+    None => name.is_empty(),
+    Some(('*', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+    Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+    Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+  }
+}
+
+/// the components of a relative path against pattern components, `**` taking any number of them
+fn matches_path(pattern: &[&str], path: &[&str]) -> bool {
+  match pattern.split_first() {
+    None => path.is_empty(),
+    Some((&"**", rest)) => (0..=path.len()).any(|i| matches_path(rest, &path[i..])),
+    Some((p, rest)) => !path.is_empty()
+      && matches(&p.chars().collect::<Vec<_>>(), &path[0].chars().collect::<Vec<_>>())
+      && matches_path(rest, &path[1..]),
+  }
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+  let mut entries = std::fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<std::io::Result<Vec<_>>>()?;
+  entries.sort();
+  for path in entries {
+    if path.is_dir() { walk(&path, files)? } else { files.push(path) }
+  }
+  Ok(())
+}
+
+/// The files of `pattern` in sorted order: a file itself, the .crn files under a directory, or
+/// the files matching a glob under its literal prefix.
+pub fn expand(pattern: &str) -> std::io::Result<Vec<Matched>> {
+  let path = Path::new(pattern);
+  let components: Vec<&str> = pattern.split(['/', '\\']).filter(|c| !c.is_empty() && *c != ".").collect();
+  let Some(first) = components.iter().position(|c| is_glob(c)) else {
+    if !path.is_dir() {
+      let relative = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+      return Ok(vec![Matched { path: path.to_path_buf(), relative }])
+    }
+    let mut files = vec![];
+    walk(path, &mut files)?;
+    return Ok(files.into_iter()
+      .filter(|f| f.extension().is_some_and(|e| e.eq_ignore_ascii_case("crn")))
+      .map(|f| Matched { relative: f.strip_prefix(path).unwrap_or(&f).to_path_buf(), path: f })
+      .collect())
+  };
+  let mut base: PathBuf = components[..first].iter().collect();
+  if pattern.starts_with('/') { base = Path::new("/").join(base) }
+  let walk_base = if base.as_os_str().is_empty() { Path::new(".") } else { &base };
+  let mut files = vec![];
+  walk(walk_base, &mut files)?;
+  Ok(files.into_iter().filter_map(|f| {
+    let relative = f.strip_prefix(walk_base).ok()?.to_path_buf();
+    let parts: Vec<&str> = relative.iter().map(|c| c.to_str()).collect::<Option<_>>()?;
+    matches_path(&components[first..], &parts).then(|| Matched { path: base.join(&relative), relative })
+  }).collect())
+}
+
+#[test]
+fn test_glob() {
+  let m = |p: &str, n: &str| matches(&p.chars().collect::<Vec<_>>(), &n.chars().collect::<Vec<_>>());
+  assert!(m("*.crn", "a.crn") && m("a?c", "abc") && m("*", "") && !m("*.crn", "a.dds") && !m("a?", "a"));
+  let p = |p: &str, path: &str| matches_path(&p.split('/').collect::<Vec<_>>(), &path.split('/').collect::<Vec<_>>());
+  assert!(p("**/*.crn", "a.crn") && p("**/*.crn", "x/y/a.crn") && p("x/**/a.crn", "x/a.crn"));
+  assert!(!p("*/*.crn", "a.crn") && !p("**/*.crn", "x/a.dds"));
+
+  let dir = std::env::temp_dir().join(format!("crn-glob-{}", std::process::id()));
+  for file in ["a.crn", "b.dds", "sub/c.crn", "sub/deep/d.CRN"] {
+    let path = dir.join(file);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, b"").unwrap();
+  }
+  let relative = |pattern: &str| expand(pattern).unwrap().into_iter().map(|m| m.relative.to_str().unwrap().replace('\\', "/")).collect::<Vec<_>>();
+  let root = dir.to_str().unwrap().to_string();
+  assert_eq!(relative(&root), ["a.crn", "sub/c.crn", "sub/deep/d.CRN"]);
+  assert_eq!(relative(&format!("{}/**/*.crn", root)), ["a.crn", "sub/c.crn"]);
+  assert_eq!(relative(&format!("{}/*/*", root)), ["sub/c.crn"]);
+  assert_eq!(expand(&format!("{}/sub/c.crn", root)).unwrap(), [Matched { path: dir.join("sub/c.crn"), relative: "c.crn".into() }]);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,71 @@
+//! `crn`, command line tools around crnlib.
+
+mod args;
+mod audit;
+mod convert;
+mod diff;
+mod dump;
+mod glob;
+mod stats;
+mod value;
+
+const USAGE: &str = "\
+usage: crn <command> [options]
+
+commands:
+  audit <dirs>... [--jobs N] [--all]
+      parse, check and decode every crn file, a JSON line per failure (per file with --all)
+  convert <inputs>... --to dds [--jobs N] [--out-dir DIR] [--watch] [--sidecar] [--progress]
+      convert crn files, directories or globs like 'textures/**/*.crn',
+      with --watch again whenever one is added or modified,
+      with --sidecar also writing a .json of the source hash, format and decode stats,
+      with --progress one file after another showing level, percentage and time left
+  diff <a> <b>
+      header fields, palette entries and decoded blocks where two files differ
+  dump <file> [--tables] [--json]
+      header, byte ranges and with --tables huffman code lengths and palette entries
+  stats <inputs>...
+      compression ratio, bits per texel, palette utilization and chunk encodings";
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// `e` with all its sources, `crnlib::CrnError` keeps the details in those
+fn describe(e: &dyn std::error::Error) -> String {
+  let mut result = e.to_string();
+  let mut source = e.source();
+  while let Some(e) = source {
+    result += &format!(": {}", e);
+    source = e.source();
+  }
+  result
+}
+
+/// `n` bytes in B, KiB or MiB
+fn size(n: usize) -> String {
+  match n {
+    n if n < 1 << 10 => format!("{} B", n),
+    n if n < 1 << 20 => format!("{:.1} KiB", n as f64 / 1024.0),
+    n => format!("{:.1} MiB", n as f64 / (1 << 20) as f64),
+  }
+}
+
+fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let result = match args.first().map(String::as_str) {
+    Some("audit") => audit::run(&args[1..]),
+    Some("convert") => convert::run(&args[1..]),
+    Some("diff") => diff::run(&args[1..]),
+    Some("dump") => dump::run(&args[1..]),
+    Some("stats") => stats::run(&args[1..]),
+    Some("help" | "--help" | "-h") => { println!("{}", USAGE); Ok(true) }
+    _ => { eprintln!("{}", USAGE); std::process::exit(2) }
+  };
+  match result {
+    Ok(true) => {}
+    Ok(false) => std::process::exit(1),
+    Err(e) => {
+      eprintln!("crn: {}", describe(e.as_ref()));
+      std::process::exit(2)
+    }
+  }
+}
@@ -0,0 +1,98 @@
+//! `crn stats`: compression ratio, bits per texel, palette utilization, the bytes of every level
+//! stream by component, chunk encodings and how close the palette deltas come to their entropy,
+//! to compare encoder settings.
+
+use crnlib::DecodeSession;
+use crnlib::stats::{CompressionBreakdown, DecodeStats, PaletteStats};
+use crnlib::unpack::{Dxt1, Unpack, level_size};
+use crate::{Result, args::Args, describe, glob::expand, size};
+
+fn percent(n: usize, total: usize) -> f64 {
+  if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 }
+}
+
+fn print(session: &DecodeSession, stats: &DecodeStats) -> Result<()> {
+  let header = session.header();
+  let faces = header.face_count as usize;
+  let (mut blocks, mut texels) = (0, 0);
+  for level in &stats.levels {
+    blocks += level_size(level.width, level.height, header.face_count, header.block_size())?;
+    texels += level.width as usize * level.height as usize * faces;
+  }
+  let file = session.input().len();
+  println!("{:?} {}x{}, {} levels, {} {}", header.format, header.width, header.height, header.level_count, faces, if faces == 1 { "face" } else { "faces" });
+  println!(
+    "  {}, {:.2} bits/texel, {:.2}:1 against {:?} ({}), {:.2}:1 against RGBA ({})",
+    size(file), file as f64 * 8.0 / texels as f64,
+    blocks as f64 / file as f64, header.format, size(blocks), texels as f64 * 4.0 / file as f64, size(texels * 4),
+  );
+
+  println!("palettes          entries  used   bytes");
+  let palettes = [
+    ("color endpoints", stats.color_endpoints), ("color selectors", stats.color_selectors),
+    ("alpha endpoints", stats.alpha_endpoints), ("alpha selectors", stats.alpha_selectors),
+  ];
+  for (name, PaletteStats { entries, used, bits }) in palettes {
+    if entries == 0 { continue }
+    println!("  {:<15} {:>7} {:>5.1}% {:>7}", name, entries, percent(used, entries), bits / 8);
+  }
+  println!("  tables {:>31}", stats.table_bits / 8);
+
+  println!("levels       size  bits/texel  chunks  tiles/chunk");
+  for (idx, level) in stats.levels.iter().enumerate() {
+    let texels = level.width as usize * level.height as usize * faces;
+    println!(
+      "  {:>2} {:>11}  {:>10.2}  {:>6}  {:>11.2}",
+      idx, format!("{}x{}", level.width, level.height), level.bits as f64 / texels as f64, level.chunks, level.mean_tile_count(),
+    );
+  }
+
+  println!("{:<14}{:>10}{:>10}{:>11}{:>10}{:>11}", "streams, bytes", "chunks", "color ep", "color sel", "alpha ep", "alpha sel");
+  let breakdown = CompressionBreakdown::from_stats(header, session.input(), stats)?;
+  let bytes = |bits: usize| bits as f64 / 8.0;
+  for (idx, s) in breakdown.levels.iter().enumerate() {
+    println!(
+      "  {:>2}{:>20.1}{:>10.1}{:>11.1}{:>10.1}{:>11.1}",
+      idx, bytes(s.chunk_encoding), bytes(s.color_endpoint), bytes(s.color_selector), bytes(s.alpha_endpoint), bytes(s.alpha_selector),
+    );
+  }
+
+  println!("deltas, bits per symbol spent and entropy");
+  for idx in 0..stats.levels.len() {
+    let entropy = session.entropy(idx)?;
+    let kinds = [&entropy.color_endpoint, &entropy.color_selector, &entropy.alpha_endpoint, &entropy.alpha_selector];
+    let columns = palettes.iter().zip(kinds).filter(|((_, p), h)| p.entries != 0 && h.symbols() != 0).map(|((name, _), h)| {
+      format!("{} {:.2}/{:.2}", name, h.bits as f64 / h.symbols() as f64, h.entropy())
+    }).collect::<Vec<_>>();
+    println!("  {:>2} {}", idx, columns.join(", "));
+  }
+
+  let layouts = stats.tile_layouts();
+  let chunks = layouts.iter().sum();
+  println!("chunk encodings");
+  for (i, &n) in layouts.iter().enumerate() {
+    println!("  {} {:?} {:>8} {:>5.1}%", i, Dxt1::TILES[i], n, percent(n, chunks));
+  }
+  Ok(())
+}
+
+pub fn run(args: &[String]) -> Result<bool> {
+  let args = Args::parse(args, &[])?;
+  args.check(&[])?;
+  if args.positional().is_empty() { return Err("no inputs".into()) }
+  let mut ok = true;
+  for pattern in args.positional() {
+    for matched in expand(pattern)? {
+      println!("{}", matched.path.display());
+      let result = std::fs::read(&matched.path).map_err(Into::into).and_then(|input| {
+        let session = DecodeSession::new(input)?;
+        print(&session, &session.stats()?)
+      });
+      if let Err(e) = result {
+        eprintln!("{}: {}", matched.path.display(), describe(e.as_ref()));
+        ok = false;
+      }
+    }
+  }
+  Ok(ok)
+}
@@ -0,0 +1,81 @@
+//! Output of `dump` and `audit` as a tree, written as JSON by hand since it is all the binary needs.
+
+/// what gets printed, as text or JSON
+pub enum Value {
+  Int(u64),
+  Text(String),
+  /// byte range `start..end` of the file
+  Range(u64, u64),
+  List(Vec<Value>),
+  Map(Vec<(String, Value)>),
+}
+
+use Value::*;
+
+pub fn map<const N: usize>(entries: [(&str, Value); N]) -> Value {
+  Map(Vec::from(entries).into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+impl Value {
+  pub fn is_scalar(&self) -> bool {
+    matches!(self, Int(_) | Text(_) | Range(..))
+  }
+
+  pub fn scalar(&self) -> String {
+    match self {
+      Int(n) => n.to_string(),
+      Text(s) => s.clone(),
+      Range(start, end) => format!("{:#x}..{:#x} ({} bytes)", start, end, end.saturating_sub(*start)),
+      List(_) | Map(_) => unreachable!(),
+    }
+  }
+
+  pub fn json(&self, out: &mut String) {
+    match self {
+      Int(n) => *out += &n.to_string(),
+      Text(s) => string(s, out),
+      Range(start, end) => *out += &format!("{{\"start\":{},\"end\":{}}}", start, end),
+      List(items) => {
+        out.push('[');
+        for (i, item) in items.iter().enumerate() {
+          if i != 0 { out.push(',') }
+          item.json(out);
+        }
+        out.push(']');
+      }
+      Map(entries) => {
+        out.push('{');
+        for (i, (key, value)) in entries.iter().enumerate() {
+          if i != 0 { out.push(',') }
+          string(key, out);
+          out.push(':');
+          value.json(out);
+        }
+        out.push('}');
+      }
+    }
+  }
+}
+
+/// `s` as a JSON string
+fn string(s: &str, out: &mut String) {
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => *out += "\\\"",
+      '\\' => *out += "\\\\",
+      '\n' => *out += "\\n",
+      c if (c as u32) < 0x20 => *out += &format!("\\u{:04x}", c as u32),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+#[test]
+fn test_json() {
+  let value = map([("name", Text("a \"b\"\n".to_string())), ("range", Range(2, 5)), ("lengths", List(vec![Int(1), Int(0)])), ("empty", Map(vec![]))]);
+  let mut out = String::new();
+  value.json(&mut out);
+  assert_eq!(out, r#"{"name":"a \"b\"\n","range":{"start":2,"end":5},"lengths":[1,0],"empty":{}}"#);
+}
@@ -0,0 +1,176 @@
+//! Assemble crn files from palettes, tables and level streams that are already coded, e.g. to
+//! trim, append or splice mip levels between files without decoding them.
+//!
+//! Level streams are only meaningful with the palettes and tables they were written against, so
+//! levels can move between files sharing those, like the files `CrnBuilder::from_crn` of one
+//! encode gives.
+
+use crate::error::{CrnError as Error, ensure, format_err};
+use crate::{Format, Header, Palette, unpack::Kind};
+
+/// a palette as stored in a file, `count` entries coded into `data`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PaletteData {
+  pub count: u16,
+  pub data: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CrnBuilder {
+  /// dimensions, format, flags and userdata of the file, sizes and offsets are filled in by `build`
+  pub header: Header,
+  /// indexed by `Kind::index`
+  pub palettes: [PaletteData; 4],
+  /// the huffman tables of the chunk encodings and palette deltas
+  pub table: Vec<u8>,
+  pub levels: Vec<Vec<u8>>,
+}
+
+/// `data` starting with room for `header` and its level offsets, with those and the CRCs written
+pub(crate) fn finish(header: &Header, mut data: Vec<u8>) -> Result<Vec<u8>, Error> {
+  let mut header = header.clone();
+  header.file_size = data.len() as u32;
+  let prefix = header.to_bytes();
+  let header_size = header.header_size as usize;
+  ensure!(prefix.len() == header_size && data.len() >= header_size, "header size {} for {} levels", header_size, header.level_count);
+  data[..header_size].copy_from_slice(&prefix);
+  let data_crc16 = !Header::crc16(0, &data[header_size..]);
+  data[10..12].copy_from_slice(&data_crc16.to_be_bytes());
+  let header_crc16 = !Header::crc16(0, &data[6..header_size]);
+  data[4..6].copy_from_slice(&header_crc16.to_be_bytes());
+  Ok(data)
+}
+
+impl CrnBuilder {
+  pub fn new(format: Format, width: u16, height: u16, faces: u8) -> Self {
+    Self { header: Header { width, height, face_count: faces, format, ..Default::default() }, ..Default::default() }
+  }
+
+  /// the palettes, tables and levels of a complete crn file
+  pub fn from_crn(input: &[u8]) -> Result<Self, Error> {
+    let header = Header::parse(input)?;
+    ensure!(header.flags & Header::SEGMENTED == 0, "levels of a segmented file are elsewhere");
+    let slice = |offset: u32, size: usize| input.get(offset as usize..).and_then(|data| data.get(..size)).map(<[u8]>::to_vec);
+    let mut palettes: [PaletteData; 4] = Default::default();
+    for (data, p) in palettes.iter_mut().zip([header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors]) {
+      if p.count == 0 { continue }
+      *data = PaletteData { count: p.count, data: slice(p.offset, p.size as usize).ok_or_else(|| format_err!("palette {:?} out of input", p))? };
+    }
+    let table = slice(header.table_offset, header.table_size as usize).ok_or_else(|| format_err!("table out of input"))?;
+    let levels = (0..header.level_count as usize).map(|idx| Ok(header.get_level_data(input, idx)?.to_vec())).collect::<Result<Vec<_>, Error>>()?;
+    Ok(Self { header, palettes, table, levels })
+  }
+
+  pub fn palette(&mut self, kind: Kind, count: u16, data: Vec<u8>) -> &mut Self {
+    self.palettes[kind.index()] = PaletteData { count, data };
+    self
+  }
+
+  pub fn table(&mut self, data: Vec<u8>) -> &mut Self {
+    self.table = data;
+    self
+  }
+
+  pub fn push_level(&mut self, data: Vec<u8>) -> &mut Self {
+    self.levels.push(data);
+    self
+  }
+
+  /// drop the `count` largest levels, the next one becomes the base level
+  pub fn trim_top(&mut self, count: usize) -> &mut Self {
+    let count = count.min(self.levels.len());
+    let shift = |v: u16| 1.max(v.checked_shr(count as u32).unwrap_or(0));
+    (self.header.width, self.header.height) = (shift(self.header.width), shift(self.header.height));
+    self.levels.drain(..count);
+    self
+  }
+
+  /// the crn file with header, level offsets and CRCs filled in
+  pub fn build(&self) -> Result<Vec<u8>, Error> {
+    let level_count = self.levels.len();
+    ensure!(level_count != 0 && level_count <= u8::MAX as usize, "level count {}", level_count);
+    ensure!(self.header.width != 0 && self.header.height != 0 && self.header.face_count != 0, "empty texture");
+    ensure!(!self.table.is_empty() && self.table.len() <= u16::MAX as usize, "table size {}", self.table.len());
+    let mut header = self.header.clone();
+    header.magic = *b"Hx";
+    header.level_count = level_count as u8;
+    header.header_size = (Header::fixed_size() + 4 * level_count) as u16;
+    let mut offset = header.header_size as usize;
+    let mut palettes = [Palette::default(); 4];
+    for (p, data) in palettes.iter_mut().zip(&self.palettes) {
+      *p = Palette { offset: offset as u32, size: data.data.len() as u32, count: data.count };
+      offset += data.data.len();
+    }
+    [header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors] = palettes;
+    header.table_size = self.table.len() as u16;
+    header.table_offset = offset as u32;
+    offset += self.table.len();
+    ensure!(offset < 1 << 24, "palettes and table exceed 24-bit offsets {}", offset);
+    header.level_offset = self.levels.iter().map(|data| {
+      let start = offset;
+      offset += data.len();
+      start as u32
+    }).collect();
+    ensure!(offset <= u32::MAX as usize, "file too large {}", offset);
+
+    let mut output = vec![0; header.header_size as usize];
+    for data in self.palettes.iter().map(|p| &p.data).chain(Some(&self.table)).chain(&self.levels) {
+      output.extend_from_slice(data);
+    }
+    finish(&header, output)
+  }
+}
+
+/// Level `idx` of `input` as a crn file of its own, sharing the palettes and tables of `input`,
+/// e.g. to ship a low resolution variant without encoding it again.
+pub fn extract_level(input: &[u8], idx: usize) -> Result<Vec<u8>, Error> {
+  let mut builder = CrnBuilder::from_crn(input)?;
+  ensure!(idx < builder.levels.len(), Error::level(idx));
+  builder.trim_top(idx).levels.truncate(1);
+  builder.build()
+}
+
+#[test]
+fn test_builder() {
+  use crate::encode::encode;
+  let levels: Vec<Vec<u8>> = [64, 16, 4].iter().map(|&n| (0..n * 8).map(|i| (i * 29 % 251) as u8).collect()).collect();
+  let input = encode(Format::Dxt1, 32, 32, 1, &levels.iter().map(Vec::as_slice).collect::<Vec<_>>()).unwrap();
+  let original = Header::parse(&input).unwrap();
+  let tables = original.get_table(&input).unwrap();
+  let builder = CrnBuilder::from_crn(&input).unwrap();
+  assert_eq!(builder.build().unwrap(), input);
+
+  // the two smaller levels as a file of their own
+  let mut trimmed = builder.clone();
+  trimmed.trim_top(1);
+  let output = trimmed.build().unwrap();
+  let header = Header::parse(&output).unwrap();
+  assert!(header.validate(&output).is_valid());
+  assert_eq!((header.width, header.height, header.level_count), (16, 16, 2));
+  for idx in 0..2 {
+    assert_eq!(header.unpack_level(&tables, &output, idx).unwrap(), original.unpack_level(&tables, &input, idx + 1).unwrap());
+  }
+
+  // splice the level back
+  let mut spliced = CrnBuilder::from_crn(&output).unwrap();
+  spliced.levels.insert(0, builder.levels[0].clone());
+  (spliced.header.width, spliced.header.height) = (32, 32);
+  assert_eq!(spliced.build().unwrap(), input);
+
+  let mut truncated = builder.clone();
+  truncated.levels.truncate(1);
+  let output = truncated.build().unwrap();
+  assert_eq!(Header::parse(&output).unwrap().unpack_level(&tables, &output, 0).unwrap(), original.unpack_level(&tables, &input, 0).unwrap());
+  for idx in 0..3 {
+    let output = extract_level(&input, idx).unwrap();
+    let header = Header::parse(&output).unwrap();
+    assert!(header.check_crc(&output));
+    assert_eq!((header.width, header.height, header.level_count), (32 >> idx, 32 >> idx, 1));
+    assert_eq!(header.unpack_level(&tables, &output, 0).unwrap(), original.unpack_level(&tables, &input, idx).unwrap());
+  }
+  assert!(extract_level(&input, 3).is_err());
+  assert!(CrnBuilder::new(Format::Dxt1, 4, 4, 1).build().is_err());
+  let mut empty = CrnBuilder::new(Format::Dxt1, 4, 4, 1);
+  empty.table(vec![0]).push_level(vec![]);
+  assert!(empty.build().is_ok());
+}
@@ -0,0 +1,133 @@
+//! A least recently used cache of unpacked levels, for streamers touching the same mips again and again.
+
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+use crate::error::CrnError as Error;
+use crate::{Header, Tables, codec::Symbol};
+
+/// Identifies a face of a level of a file, by the CRCs of its header and data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LevelKey {
+  pub header_crc16: u16,
+  pub data_crc16: u16,
+  pub level: usize,
+  pub face: usize,
+}
+
+impl LevelKey {
+  pub fn new(header: &Header, level: usize, face: usize) -> Self {
+    Self { header_crc16: header.header_crc16, data_crc16: header.data_crc16, level, face }
+  }
+}
+
+/// Unpacked faces of levels kept up to `budget` bytes, the least recently used are evicted first.
+#[derive(Debug, Clone)]
+pub struct DecodedLevelCache {
+  budget: usize,
+  used: usize,
+  tick: u64,
+  entries: HashMap<LevelKey, (u64, Arc<[u8]>)>,
+  /// keys by the tick they were last used at
+  order: BTreeMap<u64, LevelKey>,
+  pub hits: usize,
+  pub misses: usize,
+}
+
+impl DecodedLevelCache {
+  pub fn new(budget: usize) -> Self {
+    Self { budget, used: 0, tick: 0, entries: HashMap::new(), order: BTreeMap::new(), hits: 0, misses: 0 }
+  }
+
+  pub fn budget(&self) -> usize {
+    self.budget
+  }
+
+  /// bytes of the cached levels
+  pub fn used(&self) -> usize {
+    self.used
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// shrink or grow the budget, evicting what no longer fits
+  pub fn set_budget(&mut self, budget: usize) {
+    self.budget = budget;
+    self.evict(0);
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.order.clear();
+    self.used = 0;
+  }
+
+  fn touch(&mut self, key: LevelKey) -> Option<Arc<[u8]>> {
+    self.tick += 1;
+    let (tick, data) = self.entries.get_mut(&key)?;
+    self.order.remove(tick);
+    self.order.insert(self.tick, key);
+    *tick = self.tick;
+    Some(data.clone())
+  }
+
+  /// evict until `extra` more bytes fit in the budget
+  fn evict(&mut self, extra: usize) {
+    while self.used + extra > self.budget {
+      let Some((_, key)) = self.order.pop_first() else { break };
+      if let Some((_, data)) = self.entries.remove(&key) { self.used -= data.len() }
+    }
+  }
+
+  /// the cached data of `key`, marking it as most recently used
+  pub fn get(&mut self, key: LevelKey) -> Option<Arc<[u8]>> {
+    self.touch(key)
+  }
+
+  /// cache `data` under `key`, data larger than the whole budget isn't kept
+  pub fn insert(&mut self, key: LevelKey, data: Arc<[u8]>) {
+    if let Some((tick, old)) = self.entries.remove(&key) {
+      self.order.remove(&tick);
+      self.used -= old.len();
+    }
+    if data.len() > self.budget { return }
+    self.evict(data.len());
+    self.tick += 1;
+    self.used += data.len();
+    self.order.insert(self.tick, key);
+    self.entries.insert(key, (self.tick, data));
+  }
+
+  /// face `face` of level `level` from the cache, unpacked with [`Header::unpack_level_face`] on a miss
+  pub fn get_or_unpack<S: Symbol>(&mut self, header: &Header, tables: &Tables<S>, input: &[u8], level: usize, face: usize) -> Result<Arc<[u8]>, Error> {
+    let key = LevelKey::new(header, level, face);
+    if let Some(data) = self.touch(key) {
+      self.hits += 1;
+      return Ok(data);
+    }
+    self.misses += 1;
+    let data: Arc<[u8]> = header.unpack_level_face(tables, input, level, face)?.into();
+    self.insert(key, data.clone());
+    Ok(data)
+  }
+}
+
+#[test]
+fn test_cache() {
+  let (input, header, tables) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  // level 0 takes 48 bytes and level 1 16, so only two of them fit in 64
+  let mut cache = DecodedLevelCache::new(64);
+  let level0 = cache.get_or_unpack(&header, &tables, &input, 0, 0).unwrap();
+  assert_eq!(&level0[..], &header.unpack_level(&tables, &input, 0).unwrap()[..]);
+  cache.get_or_unpack(&header, &tables, &input, 1, 0).unwrap();
+  assert!(Arc::ptr_eq(&cache.get_or_unpack(&header, &tables, &input, 0, 0).unwrap(), &level0));
+  assert_eq!((cache.hits, cache.misses, cache.used(), cache.len()), (1, 2, 64, 2));
+  cache.set_budget(50);
+  assert!(cache.get(LevelKey::new(&header, 1, 0)).is_none());
+  assert!(cache.get(LevelKey::new(&header, 0, 0)).is_some());
+  assert!(cache.get_or_unpack(&header, &tables, &input, 0, 1).is_err());
+}
@@ -0,0 +1,313 @@
+//! A C ABI mirroring `crn_decomp.h`, so engines calling crnd can link this crate instead, and a
+//! flat `crnlib_*` API returning `CrnStatus` codes for other FFI hosts, declared in
+//! `include/crnlib.h`.
+//!
+//! The structs and functions keep the names, layouts and semantics of crnd: info structs are
+//! checked by `m_struct_size`, failures return `false` or null, and `crnd_unpack_level` writes
+//! every face to its own destination with the given row pitch. Unlike crnd the context keeps a
+//! copy of the file, so the data may be freed after `crnd_unpack_begin`.
+//!
+//! The crate builds as an rlib only, the shared library comes from
+//! `cargo rustc --lib --release --features capi --crate-type cdylib`.
+
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use std::os::raw::{c_char, c_int, c_void};
+use crate::{CrnError, Header, ParseOptions, Tables};
+
+pub type crn_uint32 = u32;
+/// `crn_format`, the values of `Format`
+pub type crn_format = u32;
+pub type crnd_unpack_context = *mut c_void;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct crn_texture_info {
+  pub m_struct_size: crn_uint32,
+  pub m_width: crn_uint32,
+  pub m_height: crn_uint32,
+  pub m_levels: crn_uint32,
+  pub m_faces: crn_uint32,
+  pub m_format: crn_format,
+  pub m_bytes_per_block: crn_uint32,
+  pub m_userdata0: crn_uint32,
+  pub m_userdata1: crn_uint32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct crn_level_info {
+  pub m_struct_size: crn_uint32,
+  pub m_width: crn_uint32,
+  pub m_height: crn_uint32,
+  pub m_faces: crn_uint32,
+  pub m_blocks_x: crn_uint32,
+  pub m_blocks_y: crn_uint32,
+  pub m_bytes_per_block: crn_uint32,
+  pub m_format: crn_format,
+}
+
+struct Context {
+  input: Vec<u8>,
+  header: Header,
+  tables: Tables,
+}
+
+unsafe fn input<'a>(data: *const c_void, size: crn_uint32) -> Option<&'a [u8]> {
+  if data.is_null() || size == 0 { return None }
+  Some(std::slice::from_raw_parts(data as *const u8, size as usize))
+}
+
+fn header(input: &[u8]) -> Option<Header> {
+  Header::parse(input).ok().filter(|header| header.level_count != 0 && header.file_size as usize <= input.len())
+}
+
+/// Fill `info` from the header of `data`, `info.m_struct_size` has to be set by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn crnd_get_texture_info(data: *const c_void, data_size: crn_uint32, info: *mut crn_texture_info) -> bool {
+  let (Some(input), Some(info)) = (input(data, data_size), info.as_mut()) else { return false };
+  if info.m_struct_size as usize != std::mem::size_of::<crn_texture_info>() { return false }
+  let Some(header) = header(input) else { return false };
+  *info = (&header.texture_info()).into();
+  true
+}
+
+/// Fill `info` for level `level_index` of `data`, `info.m_struct_size` has to be set by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn crnd_get_level_info(data: *const c_void, data_size: crn_uint32, level_index: crn_uint32, info: *mut crn_level_info) -> bool {
+  let (Some(input), Some(info)) = (input(data, data_size), info.as_mut()) else { return false };
+  if info.m_struct_size as usize != std::mem::size_of::<crn_level_info>() { return false }
+  let Some(header) = header(input) else { return false };
+  let Some((width, height)) = header.get_level_info(level_index as usize) else { return false };
+  *info = crn_level_info {
+    m_struct_size: info.m_struct_size,
+    m_width: width as u32,
+    m_height: height as u32,
+    m_faces: header.face_count as u32,
+    m_blocks_x: width.div_ceil(4) as u32,
+    m_blocks_y: height.div_ceil(4) as u32,
+    m_bytes_per_block: header.block_size() as u32,
+    m_format: header.format as u32,
+  };
+  true
+}
+
+/// Parse the header and tables of `data` into a context for `crnd_unpack_level`, null if the
+/// file is invalid. Free it with `crnd_unpack_end`.
+#[no_mangle]
+pub unsafe extern "C" fn crnd_unpack_begin(data: *const c_void, data_size: crn_uint32) -> crnd_unpack_context {
+  let Some(input) = input(data, data_size) else { return std::ptr::null_mut() };
+  let Some(header) = header(input) else { return std::ptr::null_mut() };
+  let Ok(tables) = header.get_table(input) else { return std::ptr::null_mut() };
+  Box::into_raw(Box::new(Context { input: input.to_vec(), header, tables })) as crnd_unpack_context
+}
+
+/// Decode level `level_index` into `dst[face]` for every face, `dst_size_in_bytes` each with rows
+/// of blocks `row_pitch_in_bytes` apart, 0 for tightly packed rows.
+#[no_mangle]
+pub unsafe extern "C" fn crnd_unpack_level(
+  context: crnd_unpack_context, dst: *mut *mut c_void, dst_size_in_bytes: crn_uint32, row_pitch_in_bytes: crn_uint32, level_index: crn_uint32,
+) -> bool {
+  let Some(context) = (context as *const Context).as_ref() else { return false };
+  if dst.is_null() { return false }
+  let header = &context.header;
+  let Some((width, height)) = header.get_level_info(level_index as usize) else { return false };
+  let (block_x, block_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+  let row_size = block_x * header.block_size();
+  let pitch = if row_pitch_in_bytes == 0 { row_size } else { row_pitch_in_bytes as usize };
+  if pitch < row_size || (dst_size_in_bytes as usize) < pitch * (block_y - 1) + row_size { return false }
+  let faces = std::slice::from_raw_parts(dst, header.face_count as usize);
+  if faces.iter().any(|face| face.is_null()) { return false }
+  let Ok(level) = header.unpack_level(&context.tables, &context.input, level_index as usize) else { return false };
+  for (face, blocks) in faces.iter().zip(level.chunks_exact(row_size * block_y)) {
+    let out = std::slice::from_raw_parts_mut(*face as *mut u8, dst_size_in_bytes as usize);
+    for (y, row) in blocks.chunks_exact(row_size).enumerate() {
+      out[y * pitch..y * pitch + row_size].copy_from_slice(row);
+    }
+  }
+  true
+}
+
+/// Free a context of `crnd_unpack_begin`, false for null.
+#[no_mangle]
+pub unsafe extern "C" fn crnd_unpack_end(context: crnd_unpack_context) -> bool {
+  if context.is_null() { return false }
+  drop(Box::from_raw(context as *mut Context));
+  true
+}
+
+/// result of the flat `crnlib_*` functions
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrnStatus {
+  Ok = 0,
+  /// a null pointer or a level or face past the end
+  InvalidArgument = 1,
+  /// not a crn file, or its header doesn't fit the data
+  InvalidFile = 2,
+  CrcMismatch = 3,
+  UnsupportedFormat = 4,
+  /// the output buffer is smaller than `crnlib_level_size` asks for
+  BufferTooSmall = 5,
+  /// the palettes, tables or level streams don't decode
+  Corrupt = 6,
+}
+
+impl From<&CrnError> for CrnStatus {
+  fn from(e: &CrnError) -> Self {
+    match e.root() {
+      CrnError::InvalidMagic(_) | CrnError::Header(_) | CrnError::SizeMismatch { .. } | CrnError::UnknownFlags(_) | CrnError::LimitExceeded { .. } => CrnStatus::InvalidFile,
+      CrnError::CrcMismatch => CrnStatus::CrcMismatch,
+      CrnError::UnsupportedFormat { .. } => CrnStatus::UnsupportedFormat,
+      _ => CrnStatus::Corrupt,
+    }
+  }
+}
+
+pub use crate::info::CrnTextureInfo;
+
+/// the header of `data` parsed with the default `ParseOptions`, which check the CRCs
+unsafe fn checked<'a>(data: *const u8, size: usize) -> Result<(&'a [u8], Header), CrnStatus> {
+  if data.is_null() { return Err(CrnStatus::InvalidArgument) }
+  let input = std::slice::from_raw_parts(data, size);
+  let header = Header::parse_with(input, &ParseOptions::default()).map_err(|e| CrnStatus::from(&e))?;
+  Ok((input, header))
+}
+
+fn run(f: impl FnOnce() -> Result<(), CrnStatus>) -> CrnStatus {
+  f().err().unwrap_or(CrnStatus::Ok)
+}
+
+/// Check the header of the `size` bytes at `data` and describe the texture in `info`.
+#[no_mangle]
+pub unsafe extern "C" fn crnlib_texture_info(data: *const u8, size: usize, info: *mut CrnTextureInfo) -> CrnStatus {
+  run(|| {
+    let (_, header) = checked(data, size)?;
+    let info = info.as_mut().ok_or(CrnStatus::InvalidArgument)?;
+    *info = (&header.texture_info()).into();
+    Ok(())
+  })
+}
+
+/// Bytes `crnlib_decode_level` writes for `level`, the blocks of all faces.
+#[no_mangle]
+pub unsafe extern "C" fn crnlib_level_size(data: *const u8, size: usize, level: u32, out_size: *mut usize) -> CrnStatus {
+  run(|| {
+    let (_, header) = checked(data, size)?;
+    let (width, height) = header.get_level_info(level as usize).ok_or(CrnStatus::InvalidArgument)?;
+    let level_size = crate::unpack::level_size(width, height, header.face_count, header.block_size()).map_err(|e| CrnStatus::from(&e))?;
+    *out_size.as_mut().ok_or(CrnStatus::InvalidArgument)? = level_size;
+    Ok(())
+  })
+}
+
+/// Decode the blocks of `level` into `out`, rows of blocks tightly packed and faces one after another.
+#[no_mangle]
+pub unsafe extern "C" fn crnlib_decode_level(data: *const u8, size: usize, level: u32, out: *mut u8, out_size: usize) -> CrnStatus {
+  run(|| {
+    let (input, header) = checked(data, size)?;
+    if out.is_null() || level >= header.level_count as u32 { return Err(CrnStatus::InvalidArgument) }
+    let tables = header.get_table(input).map_err(|e| CrnStatus::from(&e))?;
+    let blocks = header.unpack_level(&tables, input, level as usize).map_err(|e| CrnStatus::from(&e))?;
+    if out_size < blocks.len() { return Err(CrnStatus::BufferTooSmall) }
+    std::slice::from_raw_parts_mut(out, blocks.len()).copy_from_slice(&blocks);
+    Ok(())
+  })
+}
+
+/// Decode `face` of `level` into `width * height * 4` bytes of RGBA at `out`.
+#[no_mangle]
+pub unsafe extern "C" fn crnlib_decode_level_rgba(data: *const u8, size: usize, level: u32, face: u32, out: *mut u8, out_size: usize) -> CrnStatus {
+  run(|| {
+    let (input, header) = checked(data, size)?;
+    if out.is_null() || face >= header.face_count as u32 { return Err(CrnStatus::InvalidArgument) }
+    let (width, height) = header.get_level_info(level as usize).ok_or(CrnStatus::InvalidArgument)?;
+    if out_size < width as usize * height as usize * 4 { return Err(CrnStatus::BufferTooSmall) }
+    let tables = header.get_table(input).map_err(|e| CrnStatus::from(&e))?;
+    let blocks = header.unpack_level(&tables, input, level as usize).map_err(|e| CrnStatus::from(&e))?;
+    let face_size = blocks.len() / header.face_count as usize;
+    let rgba = crate::rgba::decode_image(header.format, &blocks[face as usize * face_size..][..face_size], width, height).map_err(|e| CrnStatus::from(&e))?;
+    std::slice::from_raw_parts_mut(out, rgba.len()).copy_from_slice(&rgba);
+    Ok(())
+  })
+}
+
+/// A static NUL terminated description of the `CrnStatus` value `status`. It takes a plain int,
+/// hosts may pass anything there, which an enum parameter would turn into undefined behavior.
+#[no_mangle]
+pub extern "C" fn crnlib_status_message(status: c_int) -> *const c_char {
+  let message: &'static [u8] = match status {
+    0 => b"ok\0",
+    1 => b"invalid argument\0",
+    2 => b"invalid crn file\0",
+    3 => b"crc mismatch\0",
+    4 => b"unsupported format\0",
+    5 => b"buffer too small\0",
+    6 => b"corrupt data\0",
+    _ => b"unknown status\0",
+  };
+  message.as_ptr() as *const c_char
+}
+
+#[test]
+fn test_capi() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 16, 8) };
+  let input = gen.build().unwrap();
+  let blocks = gen.blocks().unwrap();
+  let (data, size) = (input.as_ptr() as *const c_void, input.len() as u32);
+  unsafe {
+    let mut info = crn_texture_info::default();
+    assert!(!crnd_get_texture_info(data, size, &mut info));
+    info.m_struct_size = std::mem::size_of::<crn_texture_info>() as u32;
+    assert!(crnd_get_texture_info(data, size, &mut info));
+    assert_eq!((info.m_width, info.m_height, info.m_levels, info.m_faces, info.m_format, info.m_bytes_per_block), (16, 8, 5, 6, Format::Dxt5 as u32, 16));
+    let mut level = crn_level_info { m_struct_size: std::mem::size_of::<crn_level_info>() as u32, ..Default::default() };
+    assert!(crnd_get_level_info(data, size, 1, &mut level));
+    assert_eq!((level.m_width, level.m_height, level.m_blocks_x, level.m_blocks_y), (8, 4, 2, 1));
+    assert!(!crnd_get_level_info(data, size, 5, &mut level));
+
+    let context = crnd_unpack_begin(data, size);
+    assert!(!context.is_null());
+    // level 0 is 4x2 blocks, rows padded to 80 bytes
+    let mut faces = vec![vec![0xffu8; 80 + 64]; 6];
+    let mut dst: Vec<*mut c_void> = faces.iter_mut().map(|face| face.as_mut_ptr() as *mut c_void).collect();
+    assert!(!crnd_unpack_level(context, dst.as_mut_ptr(), 80 + 63, 80, 0));
+    assert!(crnd_unpack_level(context, dst.as_mut_ptr(), 80 + 64, 80, 0));
+    for (face, expected) in faces.iter().zip(blocks[0].chunks_exact(128)) {
+      assert_eq!((&face[..64], &face[64..80], &face[80..]), (&expected[..64], &[0xff; 16][..], &expected[64..]));
+    }
+    assert!(crnd_unpack_level(context, dst.as_mut_ptr(), 64, 0, 1));
+    assert_eq!(&faces[5][..32], &blocks[1][5 * 32..]);
+    assert!(!crnd_unpack_level(context, dst.as_mut_ptr(), 64, 0, 5));
+    assert!(crnd_unpack_end(context));
+    assert!(!crnd_unpack_end(std::ptr::null_mut()));
+    assert!(crnd_unpack_begin(data, size - 1).is_null());
+
+    let data = input.as_ptr();
+    let mut info = CrnTextureInfo::default();
+    assert_eq!(crnlib_texture_info(data, input.len(), &mut info), CrnStatus::Ok);
+    assert_eq!(info, CrnTextureInfo { width: 16, height: 8, levels: 5, faces: 6, format: Format::Dxt5 as u32, bytes_per_block: 16 });
+    assert_eq!(crnlib_texture_info(data, input.len() - 1, &mut info), CrnStatus::InvalidFile);
+    assert_eq!(crnlib_texture_info(std::ptr::null(), 0, &mut info), CrnStatus::InvalidArgument);
+    let mut corrupt = input.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert_eq!(crnlib_texture_info(corrupt.as_ptr(), corrupt.len(), &mut info), CrnStatus::CrcMismatch);
+    let mut level_size = 0;
+    assert_eq!(crnlib_level_size(data, input.len(), 0, &mut level_size), CrnStatus::Ok);
+    assert_eq!(level_size, blocks[0].len());
+    let mut out = vec![0; level_size];
+    assert_eq!(crnlib_decode_level(data, input.len(), 0, out.as_mut_ptr(), level_size - 1), CrnStatus::BufferTooSmall);
+    assert_eq!(crnlib_decode_level(data, input.len(), 0, out.as_mut_ptr(), level_size), CrnStatus::Ok);
+    assert_eq!(out, blocks[0]);
+    assert_eq!(crnlib_decode_level(data, input.len(), 5, out.as_mut_ptr(), level_size), CrnStatus::InvalidArgument);
+    let mut rgba = vec![0; 16 * 8 * 4];
+    assert_eq!(crnlib_decode_level_rgba(data, input.len(), 0, 2, rgba.as_mut_ptr(), rgba.len()), CrnStatus::Ok);
+    assert_eq!(rgba, crate::rgba::decode_image(Format::Dxt5, &blocks[0][2 * 128..3 * 128], 16, 8).unwrap());
+    assert_eq!(crnlib_decode_level_rgba(data, input.len(), 0, 6, rgba.as_mut_ptr(), rgba.len()), CrnStatus::InvalidArgument);
+    let message = |status| std::ffi::CStr::from_ptr(crnlib_status_message(status)).to_str().unwrap();
+    assert_eq!(message(CrnStatus::BufferTooSmall as c_int), "buffer too small");
+    assert_eq!(message(7), "unknown status");
+    assert_eq!(message(-1), "unknown status");
+  }
+}
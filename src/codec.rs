@@ -1,37 +1,110 @@
 use std::collections::BTreeMap;
 use bitvec::{slice::BitSlice, order::Msb0, fields::BitField};
-use anyhow::*;
+use crate::error::{CrnError as Error, Context, bail, ensure, format_err};
+
+/// `bits.load_be::<u64>()` for up to 64 bits, in 32-bit halves since bitvec only loads `u64` on
+/// 64-bit targets
+fn load_be_u64(bits: &BitSlice<Msb0, u8>) -> u64 {
+  match bits.len().checked_sub(32) {
+    Some(0) | None => bits.load_be::<u32>() as u64,
+    Some(high) => (bits[..high].load_be::<u32>() as u64) << 32 | bits[high..].load_be::<u32>() as u64,
+  }
+}
 
 pub struct Codec<'a> {
+  bytes: &'a [u8],
   buffer: &'a BitSlice<Msb0, u8>,
   index: usize,
+  budget: Budget,
+  symbols: usize,
+}
+
+/// Upper bounds on the work a [`Codec`] may do, so decoding untrusted input takes bounded time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+  /// huffman symbols decoded
+  pub max_symbols: Option<usize>,
+  /// bits consumed
+  pub max_bits: Option<usize>,
+}
+
+/// Returned (as `CrnError::Budget`) when a [`Budget`] runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum BudgetExceeded {
+  #[error("decode budget of {0} symbols exceeded")]
+  Symbols(usize),
+  #[error("decode budget of {0} bits exceeded")]
+  Bits(usize),
+}
+
+/// An opaque read position of a [`Codec`], see [`Codec::checkpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Checkpoint {
+  index: usize,
+  /// address and length of the input, to tell checkpoints of another codec
+  input: (usize, usize),
 }
 
 impl Codec<'_> {
   pub fn new<'a>(input: &'a [u8]) -> Codec<'a> {
-    Codec { buffer: BitSlice::from_slice(input), index: 0 }
+    Codec { bytes: input, buffer: BitSlice::from_slice(input), index: 0, budget: Budget::default(), symbols: 0 }
+  }
+  /// limit symbols and bits decoded from now on (counted from the start of input for bits)
+  pub fn set_budget(&mut self, budget: Budget) {
+    self.budget = budget;
+    self.symbols = 0;
   }
+  pub fn budget(&self) -> Budget {
+    self.budget
+  }
+  /// fail unless bits up to `end` are inside both the input and the budget
+  fn ensure_bits(&self, end: usize) -> Result<(), Error> {
+    if end > self.buffer.len() {
+      bail!("read out of index {} < {}", end, self.buffer.len());
+    }
+    if let Some(max) = self.budget.max_bits {
+      if end > max { bail!(BudgetExceeded::Bits(max)) }
+    }
+    Ok(())
+  }
+  /// the next `n <= 64` bits without consuming them.
+  ///
+  /// Bits past the end of input read as zero, so the result is always the `n`-bit value
+  /// `input[index..index+n]` with missing low bits zero padded; at or past the end it is `0`.
   pub fn look_bits(&self, n: usize) -> u64 {
     assert!(n <= 64);
-    if n == 0 { return 0 }
-    if self.index + n > self.buffer.len() {
-      self.buffer[self.index..].load_be::<u64>() << (self.index + n - self.buffer.len())
-    } else {
-      self.buffer[self.index..self.index+n].load_be()
-    }
+    let available = self.buffer.len().saturating_sub(self.index).min(n);
+    if available == 0 { return 0 }
+    load_be_u64(&self.buffer[self.index..self.index+available]) << (n - available)
+  }
+  /// `look_bits` for `n <= 32`, avoiding 64-bit loads which are emulated on 32-bit targets
+  pub fn look_bits_u32(&self, n: usize) -> u32 {
+    assert!(n <= 32);
+    let available = self.buffer.len().saturating_sub(self.index).min(n);
+    if available == 0 { return 0 }
+    self.buffer[self.index..self.index+available].load_be::<u32>() << (n - available)
+  }
+  /// `read_bits` for `n <= 32`
+  pub fn read_bits_u32(&mut self, n: usize) -> Result<u32, Error> {
+    assert!(n <= 32);
+    self.ensure_bits(self.index + n)?;
+    if n == 0 { return Ok(0) }
+    let result = self.buffer[self.index..self.index+n].load_be();
+    self.index += n;
+    Ok(result)
   }
   pub fn read_bits(&mut self, n: usize) -> Result<u64, Error> {
     assert!(n <= 64);
-    if self.index + n > self.buffer.len() {
-      bail!("read out of index {} < {}", self.index+n, self.buffer.len());
-    }
+    self.ensure_bits(self.index + n)?;
     if n == 0 { return Ok(0) }
-    let result = self.buffer[self.index..self.index+n].load_be();
+    let result = load_be_u64(&self.buffer[self.index..self.index+n]);
     self.index += n;
     Ok(result)
   }
-  pub fn skip_bits(&mut self, n: usize) {
+  pub fn skip_bits(&mut self, n: usize) -> Result<(), Error> {
+    self.ensure_bits(self.index + n).context("skip bits")?;
     self.index += n;
+    Ok(())
   }
   pub fn current(&self) -> usize {
     self.index
@@ -39,52 +112,358 @@ impl Codec<'_> {
   pub fn len(&self) -> usize {
     self.buffer.len()
   }
+  pub fn is_empty(&self) -> bool {
+    self.buffer.is_empty()
+  }
   pub fn is_complete(&self) -> bool {
     self.index + 7 >= self.buffer.len() && self.index <= self.buffer.len()
   }
+  /// bits consumed so far, same as `current`
+  pub fn bits_read(&self) -> usize {
+    self.index
+  }
+  pub fn bits_remaining(&self) -> usize {
+    self.buffer.len().saturating_sub(self.index)
+  }
+  /// fraction of the input consumed, in `0.0..=1.0`, an empty input is complete
+  pub fn progress(&self) -> f64 {
+    if self.buffer.is_empty() { return 1.0 }
+    self.index.min(self.buffer.len()) as f64 / self.buffer.len() as f64
+  }
+  /// `progress` as a whole percentage
+  pub fn percent_complete(&self) -> u8 {
+    (self.progress() * 100.0) as u8
+  }
+  /// skip to the start of the next byte, if not already there
+  pub fn align_to_byte(&mut self) {
+    self.index = self.index.div_ceil(8) * 8;
+  }
+  /// fill `out` with the next `out.len()` bytes, which need not be byte aligned
+  pub fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), Error> {
+    let end = self.index + out.len() * 8;
+    self.ensure_bits(end)?;
+    if self.index.is_multiple_of(8) {
+      out.copy_from_slice(&self.bytes[self.index/8..end/8]);
+    } else {
+      for (i, b) in out.iter_mut().enumerate() {
+        *b = self.buffer[self.index+i*8..self.index+i*8+8].load_be();
+      }
+    }
+    self.index = end;
+    Ok(())
+  }
+  /// bytes after the current position rounded up to a byte boundary
+  pub fn remaining_bytes(&self) -> &[u8] {
+    self.bytes.get(self.index.div_ceil(8)..).unwrap_or(&[])
+  }
+  /// remember the current position, so a speculative decode could be undone by `restore`
+  pub fn checkpoint(&self) -> Checkpoint {
+    Checkpoint { index: self.index, input: (self.bytes.as_ptr() as usize, self.bytes.len()) }
+  }
+  /// rewind (or fast-forward) to a position taken from `checkpoint` of this codec,
+  /// fails for a checkpoint of a codec over other input
+  pub fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), Error> {
+    ensure!(checkpoint.input == self.checkpoint().input && checkpoint.index <= self.buffer.len(), "checkpoint of another codec");
+    self.index = checkpoint.index;
+    Ok(())
+  }
+}
 
-  pub fn get_huffman(&mut self) -> Result<Huffman<u32>, Error> {
+/// A source of MSB-first bits, implemented by the in-memory [`Codec`] and the streaming [`StreamCodec`].
+pub trait BitRead {
+  /// the next `n <= 64` bits without consuming them, zero padded past the end of input
+  fn peek_bits(&mut self, n: usize) -> Result<u64, Error>;
+  /// consume `n` bits, failing if fewer are left
+  fn consume_bits(&mut self, n: usize) -> Result<(), Error>;
+  /// bits consumed so far
+  fn current(&self) -> usize;
+  /// no bits are left
+  fn is_end(&mut self) -> Result<bool, Error>;
+  /// only padding of the last byte is left
+  fn is_complete(&mut self) -> bool;
+  /// account for `n` decoded symbols, failing once a symbol budget runs out
+  fn spend_symbols(&mut self, _n: usize) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn read_bits(&mut self, n: usize) -> Result<u64, Error> {
+    let result = self.peek_bits(n)?;
+    self.consume_bits(n)?;
+    Ok(result)
+  }
+
+  /// read a `n`-bit two's complement value, sign extended
+  fn read_signed_bits(&mut self, n: usize) -> Result<i64, Error> {
+    let value = self.read_bits(n)?;
+    if n == 0 || n == 64 { return Ok(value as i64) }
+    Ok(((value << (64 - n)) as i64) >> (64 - n))
+  }
+
+  /// read `n` extra bits on top of `base`, as in the run lengths of the key alphabet
+  fn read_extra_bits(&mut self, n: usize, base: u64) -> Result<u64, Error> {
+    Ok(self.read_bits(n)? + base)
+  }
+
+  fn get_huffman(&mut self) -> Result<Huffman<u32>, Error> where Self: Sized {
+    self.get_huffman_as()
+  }
+
+  /// `get_huffman` storing symbols as `T`, e.g. `u16` to halve the memory of the tables
+  fn get_huffman_as<T: Symbol>(&mut self) -> Result<Huffman<T>, Error> where Self: Sized {
     let symbol_count = self.read_bits(Huffman::<()>::MAX_SYMBOL_COUNT_BIT)? as u32;
-    // println!("construct huffman tree with {} symbols", symbol_count);
-    if symbol_count == 0 {
-      return Huffman::new(BTreeMap::new())
-    }
-    let mut tmp_symbol_depth = BTreeMap::new();
-    let tmp_symbol_count = self.read_bits(5)? as usize;
-    ensure!(tmp_symbol_count <= Key::SHUFFLE.len(),
-      "tmp_symbol_count {} > {}", tmp_symbol_count, Key::SHUFFLE.len());
-    for i in 0..tmp_symbol_count {
-      let value = self.read_bits(3)? as usize;
-      if value != 0 {
-        tmp_symbol_depth.insert(Key::SHUFFLE[i], value);
-      }
+    if symbol_count as usize > Huffman::<()>::MAX_SYMBOL_COUNT {
+      bail!(HuffmanError::TooManySymbols { count: symbol_count as usize, max: Huffman::<()>::MAX_SYMBOL_COUNT });
     }
-    let key = Huffman::new(tmp_symbol_depth).context("get key huffman")?;
-    // println!("tmp_symbol_depth: {:?}", key);
-    let mut symbol_depth = BTreeMap::new();
-    let mut i = 0;
-    let mut last = None;
-    while i < symbol_count {
-      let (len, d) = match key.next(self).context("get key content")? {
-        Depth(d) => (1, d),
-        ShortZero => (self.read_bits(3)? + 3, 0),
-        LongZero => (self.read_bits(7)? + 11, 0),
-        ShortRepeat => (self.read_bits(2)? + 3, last.ok_or_else(|| anyhow!("short repeat no last"))?),
-        LongRepeat => (self.read_bits(6)? + 7, last.ok_or_else(|| anyhow!("long repeat no last"))?),
-      };
-      last = Some(d);
-      for j in 0..len as u32 {
-        if d != 0 {
-          symbol_depth.insert(i+j, d);
+    let symbol_depth = decode_code_lengths(self, symbol_count as usize)?;
+    Huffman::from_code_lengths(&symbol_depth)
+  }
+}
+
+/// Read the code length table that follows the 14-bit symbol count of a huffman table:
+/// the depths of the `Key` tree in `Key::SHUFFLE` order, then `symbol_count` code lengths
+/// encoded with it. Zero means the symbol is absent.
+pub fn decode_code_lengths<C: BitRead>(codec: &mut C, symbol_count: usize) -> Result<Vec<u8>, Error> {
+  if symbol_count == 0 { return Ok(vec![]) }
+  let mut tmp_symbol_depth = BTreeMap::new();
+  let tmp_symbol_count = codec.read_bits(5)? as usize;
+  ensure!(tmp_symbol_count <= Key::SHUFFLE.len(),
+    "tmp_symbol_count {} > {}", tmp_symbol_count, Key::SHUFFLE.len());
+  for i in 0..tmp_symbol_count {
+    let value = codec.read_bits(3)? as usize;
+    if value != 0 {
+      tmp_symbol_depth.insert(Key::SHUFFLE[i], value);
+    }
+  }
+  let key = Huffman::new(tmp_symbol_depth).context("get key huffman")?;
+  // println!("tmp_symbol_depth: {:?}", key);
+  let mut symbol_depth = vec![0u8; symbol_count];
+  let mut i = 0;
+  let mut last = None;
+  while i < symbol_count {
+    let k = key.next(codec).with_context(|| format!("get key content of symbol {}", i))?;
+    let (extra_bits, base) = k.extra_bits();
+    let len = codec.read_extra_bits(extra_bits, base)?;
+    let d = match k {
+      Depth(d) => d,
+      ShortZero | LongZero => 0,
+      ShortRepeat => last.ok_or_else(|| format_err!("short repeat no last at symbol {}", i))?,
+      LongRepeat => last.ok_or_else(|| format_err!("long repeat no last at symbol {}", i))?,
+    };
+    last = Some(d);
+    ensure!(i + len as usize <= symbol_count, "code lengths overflow symbol count {} > {}", i + len as usize, symbol_count);
+    for depth in &mut symbol_depth[i..i + len as usize] {
+      *depth = d as u8;
+    }
+    i += len as usize;
+  }
+  Ok(symbol_depth)
+}
+
+impl BitRead for Codec<'_> {
+  fn peek_bits(&mut self, n: usize) -> Result<u64, Error> {
+    if n <= 32 { return Ok(self.look_bits_u32(n) as u64) }
+    Ok(self.look_bits(n))
+  }
+  fn consume_bits(&mut self, n: usize) -> Result<(), Error> {
+    self.skip_bits(n)
+  }
+  fn current(&self) -> usize {
+    self.index
+  }
+  fn is_end(&mut self) -> Result<bool, Error> {
+    Ok(self.index >= self.buffer.len())
+  }
+  fn is_complete(&mut self) -> bool {
+    Codec::is_complete(self)
+  }
+  fn spend_symbols(&mut self, n: usize) -> Result<(), Error> {
+    self.symbols += n;
+    if let Some(max) = self.budget.max_symbols {
+      if self.symbols > max { bail!(BudgetExceeded::Symbols(max)) }
+    }
+    Ok(())
+  }
+  fn read_bits(&mut self, n: usize) -> Result<u64, Error> {
+    if n <= 32 { return Codec::read_bits_u32(self, n).map(u64::from) }
+    Codec::read_bits(self, n)
+  }
+}
+
+/// Like [`Codec`] but pulls bytes from a [`Read`](std::io::Read) in chunks of `chunk_size`,
+/// keeping only the unread bytes in memory.
+pub struct StreamCodec<R> {
+  reader: R,
+  buffer: Vec<u8>,
+  /// bit offset of the next unread bit in `buffer`
+  offset: usize,
+  /// bits consumed before `buffer[0]`
+  dropped: usize,
+  chunk_size: usize,
+  eof: bool,
+}
+
+impl<R: std::io::Read> StreamCodec<R> {
+  pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+  pub fn new(reader: R) -> Self {
+    Self::with_chunk_size(reader, Self::DEFAULT_CHUNK_SIZE)
+  }
+  pub fn with_chunk_size(reader: R, chunk_size: usize) -> Self {
+    assert!(chunk_size > 0);
+    Self { reader, buffer: Vec::new(), offset: 0, dropped: 0, chunk_size, eof: false }
+  }
+  pub fn into_inner(self) -> R {
+    self.reader
+  }
+
+  fn available(&self) -> usize {
+    self.buffer.len() * 8 - self.offset
+  }
+
+  /// read chunks until `n` bits are buffered or the reader is exhausted
+  fn fill(&mut self, n: usize) -> Result<(), Error> {
+    while self.available() < n && !self.eof {
+      let consumed = self.offset / 8;
+      self.buffer.drain(..consumed);
+      self.offset -= consumed * 8;
+      self.dropped += consumed * 8;
+      let len = self.buffer.len();
+      self.buffer.resize(len + self.chunk_size, 0);
+      let read = loop {
+        match self.reader.read(&mut self.buffer[len..]) {
+          Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+          result => break result,
         }
-      }
-      i += len as u32;
+      };
+      let read = read.inspect_err(|_| self.buffer.truncate(len)).context("refill stream codec")?;
+      self.buffer.truncate(len + read);
+      self.eof = read == 0;
     }
-    // println!("{:?}", symbol_depth);
-    Huffman::new(symbol_depth)
+    Ok(())
   }
 }
 
+impl<R: std::io::Read> BitRead for StreamCodec<R> {
+  fn peek_bits(&mut self, n: usize) -> Result<u64, Error> {
+    assert!(n <= 64);
+    self.fill(n)?;
+    let bits = BitSlice::<Msb0, u8>::from_slice(&self.buffer);
+    let available = self.available().min(n);
+    if available == 0 { return Ok(0) }
+    Ok(load_be_u64(&bits[self.offset..self.offset+available]) << (n - available))
+  }
+  fn consume_bits(&mut self, n: usize) -> Result<(), Error> {
+    self.fill(n)?;
+    ensure!(self.available() >= n, "read out of stream {} < {}", self.current() + n, self.current() + self.available());
+    self.offset += n;
+    Ok(())
+  }
+  fn current(&self) -> usize {
+    self.dropped + self.offset
+  }
+  fn is_end(&mut self) -> Result<bool, Error> {
+    self.fill(1)?;
+    Ok(self.available() == 0)
+  }
+  fn is_complete(&mut self) -> bool {
+    self.fill(8).is_ok() && self.available() < 8
+  }
+}
+
+/// MSB-first bit packer, the counterpart of [`Codec`]
+#[derive(Debug, Default, Clone)]
+pub struct CodecWriter {
+  buffer: Vec<u8>,
+  index: usize,
+}
+
+impl CodecWriter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// append the low `n` bits of `value`, most significant bit first
+  pub fn write_bits(&mut self, n: usize, value: u64) -> Result<(), Error> {
+    assert!(n <= 64);
+    ensure!(n == 64 || value >> n == 0, "value {:#x} doesn't fit in {} bits", value, n);
+    let mut remaining = n;
+    while remaining > 0 {
+      let used = self.index % 8;
+      if used == 0 { self.buffer.push(0) }
+      let take = (8 - used).min(remaining);
+      let bits = (value >> (remaining - take)) as u8 & (0xff >> (8 - take));
+      *self.buffer.last_mut().expect("pushed above") |= bits << (8 - used - take);
+      remaining -= take;
+      self.index += take;
+    }
+    Ok(())
+  }
+  /// append `value` as `n`-bit two's complement, the inverse of `BitRead::read_signed_bits`
+  pub fn write_signed_bits(&mut self, n: usize, value: i64) -> Result<(), Error> {
+    assert!(n <= 64);
+    if n == 64 { return self.write_bits(n, value as u64) }
+    let fits = if n == 0 { value == 0 } else { (-(1 << (n - 1))..1 << (n - 1)).contains(&value) };
+    ensure!(fits, "value {} doesn't fit in {} signed bits", value, n);
+    self.write_bits(n, value as u64 & ((1 << n) - 1))
+  }
+  /// append `value - base` in `n` bits, the inverse of `BitRead::read_extra_bits`
+  pub fn write_extra_bits(&mut self, n: usize, base: u64, value: u64) -> Result<(), Error> {
+    ensure!(value >= base, "value {} below base {}", value, base);
+    self.write_bits(n, value - base)
+  }
+  /// bits written so far
+  pub fn current(&self) -> usize {
+    self.index
+  }
+  /// written bytes, the last one zero padded
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.buffer
+  }
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.buffer
+  }
+}
+
+#[test]
+fn test_write_bits() {
+  let mut writer = CodecWriter::new();
+  writer.write_bits(3, 0b110).unwrap();
+  writer.write_bits(17, 0b1010_0110_1101_1101).unwrap();
+  writer.write_bits(0, 0).unwrap();
+  assert!(writer.write_bits(2, 0b100).is_err());
+  assert_eq!(writer.current(), 20);
+  writer.write_bits(4, 0b1101).unwrap();
+  assert_eq!(writer.as_bytes(), &[0b1100_1010u8, 0b0110_1101, 0b1101_1101]);
+  writer.write_bits(64, u64::MAX).unwrap();
+  let bytes = writer.into_bytes();
+  assert_eq!(bytes.len(), 11);
+  let mut codec = Codec::new(&bytes);
+  assert_eq!(codec.read_bits(20).unwrap(), 0b1100_1010_0110_1101_1101);
+  assert_eq!(codec.read_bits(4).unwrap(), 0b1101);
+  assert_eq!(codec.read_bits(64).unwrap(), u64::MAX);
+  // wider than 32 bits, loaded in halves
+  let mut writer = CodecWriter::new();
+  writer.write_bits(40, 0x12_3456_789a).unwrap();
+  let bytes = writer.into_bytes();
+  let mut codec = Codec::new(&bytes);
+  assert_eq!(codec.look_bits(48), 0x12_3456_789a << 8);
+  assert_eq!(codec.read_bits(36).unwrap(), 0x1_2345_6789);
+
+  let mut writer = CodecWriter::new();
+  writer.write_signed_bits(4, -3).unwrap();
+  writer.write_signed_bits(4, 7).unwrap();
+  assert!(writer.write_signed_bits(4, 8).is_err());
+  assert!(writer.write_signed_bits(4, -9).is_err());
+  writer.write_extra_bits(3, 3, 10).unwrap();
+  assert!(writer.write_extra_bits(3, 3, 2).is_err());
+  writer.write_signed_bits(64, i64::MIN).unwrap();
+  let bytes = writer.into_bytes();
+  let mut codec = Codec::new(&bytes);
+  assert_eq!(codec.read_signed_bits(4).unwrap(), -3);
+  assert_eq!(codec.read_signed_bits(4).unwrap(), 7);
+  assert_eq!(codec.read_extra_bits(3, 3).unwrap(), 10);
+  assert_eq!(codec.read_signed_bits(64).unwrap(), i64::MIN);
+}
+
 #[test]
 fn test_read_bits() {
   let input = [0b1100_1010u8, 0b0110_1101, 0b1101_1001];
@@ -96,6 +475,58 @@ fn test_read_bits() {
   assert_eq!(codec.read_bits(0).unwrap(), 0);
   assert_eq!(codec.index, 20);
 
+  let cp = codec.checkpoint();
+  assert!(codec.read_bits(5).is_err());
+  assert_eq!(codec.read_bits(4).unwrap(), 0b1001);
+  codec.restore(cp).unwrap();
+  assert_eq!(codec.current(), 20);
+  assert!(codec.restore(Codec::new(&input[1..]).checkpoint()).is_err());
+  assert_eq!(codec.current(), 20);
+  assert_eq!(codec.read_bits(4).unwrap(), 0b1001);
+
+  let mut codec = Codec::new(&input);
+  let mut out = [0; 2];
+  codec.set_budget(Budget { max_symbols: None, max_bits: Some(8) });
+  let error = codec.read_bits(9).unwrap_err();
+  assert!(matches!(error, Error::Budget(BudgetExceeded::Bits(8))));
+  assert_eq!(error.to_string(), "decode budget of 8 bits exceeded");
+  codec.set_budget(Budget::default());
+  assert!(codec.skip_bits(25).is_err());
+  assert_eq!(codec.current(), 0);
+  assert_eq!((codec.bits_read(), codec.bits_remaining(), codec.percent_complete()), (0, 24, 0));
+  codec.skip_bits(4).unwrap();
+  codec.read_bytes(&mut out).unwrap();
+  assert_eq!(out, [0b1010_0110, 0b1101_1101]);
+  assert_eq!((codec.bits_read(), codec.bits_remaining(), codec.percent_complete()), (20, 4, 83));
+  assert_eq!(codec.remaining_bytes(), &[]);
+  codec.restore(Checkpoint { index: 1, ..codec.checkpoint() }).unwrap();
+  assert_eq!(codec.remaining_bytes(), &input[1..]);
+  codec.align_to_byte();
+  assert_eq!(codec.current(), 8);
+  codec.align_to_byte();
+  assert_eq!(codec.current(), 8);
+  codec.read_bytes(&mut out).unwrap();
+  assert_eq!(out, input[1..]);
+  assert!(codec.read_bytes(&mut out[..1]).is_err());
+
+  // every offset near the tail, with widths that cross the end and overshoot it by up to 64 bits
+  let input = [0xa5u8; 9];
+  let bits = (0..72).map(|i| (input[i / 8] >> (7 - i % 8) & 1) as u64).collect::<Vec<_>>();
+  let mut codec = Codec::new(&input);
+  for index in 0..=80 {
+    codec.index = index;
+    for n in 0..=64 {
+      let expected = (0..n).fold(0u64, |acc, i| acc << 1 | bits.get(index + i).copied().unwrap_or(0));
+      assert_eq!(codec.look_bits(n), expected, "index {} n {}", index, n);
+      if n <= 32 {
+        assert_eq!(codec.look_bits_u32(n) as u64, expected, "index {} n {}", index, n);
+        let mut copy = Codec { index, ..codec };
+        assert_eq!(copy.read_bits_u32(n).ok(), codec.read_bits(n).ok().map(|v| v as u32));
+        codec.index = index;
+      }
+    }
+  }
+
   assert_eq!(Huffman::<()>::MAX_SYMBOL_COUNT, 1 << (Huffman::<()>::MAX_SYMBOL_COUNT_BIT - 1));
 }
 
@@ -109,18 +540,25 @@ pub struct Huffman<T> {
   /// 4: 14..15 => 0b1110
   /// 5: 30..32 => 0b11110, 0b11111
   // depth_bound: [u32; Key::MAX_DEPTH+1],
-  symbol_depth: BTreeMap<T, usize>,
-  symbols: BTreeMap<T, u32>,
-  symbol_rev: BTreeMap<(usize, u32), T>,
+  /// (symbol, depth, code) ordered by symbol
+  codes: Vec<(T, u8, u32)>,
   max_depth: usize,
+  /// symbols ordered by canonical code, i.e. by (depth, symbol)
+  sorted: Vec<T>,
+  /// first code of each depth and the position of its symbol in `sorted`
+  first_code: [u32; Key::MAX_DEPTH+1],
+  first_index: [u32; Key::MAX_DEPTH+1],
+  /// indexed by the next `lookup_bits` bits, `index << 5 | depth` of codes no longer than `lookup_bits`, 0 otherwise
+  lookup: Vec<u32>,
+  lookup_bits: usize,
 }
 
 impl<T: std::fmt::Debug> std::fmt::Debug for Huffman<T> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.debug_struct("Huffman")
-      .field("symbol_count", &self.symbols.len())
+      .field("symbol_count", &self.codes.len())
       .field("max_depth", &self.max_depth)
-      .field("symbol_depth", &self.symbol_depth)
+      .field("symbol_depth", &self.codes.iter().map(|(k, d, _)| (k, d)).collect::<Vec<_>>())
       .field("depth_count", &self.depth_count)
       .finish()
   }
@@ -128,8 +566,21 @@ impl<T: std::fmt::Debug> std::fmt::Debug for Huffman<T> {
 
 impl<T: Ord+Copy> Huffman<T> {
   pub fn new(symbol_depth: BTreeMap<T, usize>) -> Result<Self, Error> {
+    Self::from_sorted(symbol_depth.into_iter().collect())
+  }
+
+  /// `symbol_depth` must be sorted by symbol without duplicates, symbols of zero depth get no code
+  /// but still count for the `index` of `DepthTooLarge`
+  fn from_sorted(symbol_depth: Vec<(T, usize)>) -> Result<Self, Error> {
+    if let Some((index, &(_, depth))) = symbol_depth.iter().enumerate().find(|(_, &(_, d))| d > Key::MAX_DEPTH) {
+      bail!(HuffmanError::DepthTooLarge { index, depth, max: Key::MAX_DEPTH });
+    }
+    let symbol_depth: Vec<_> = symbol_depth.into_iter().filter(|&(_, d)| d != 0).collect();
+    if symbol_depth.len() > Self::MAX_SYMBOL_COUNT {
+      bail!(HuffmanError::TooManySymbols { count: symbol_depth.len(), max: Self::MAX_SYMBOL_COUNT });
+    }
     let mut depth_count = [0; Key::MAX_DEPTH+1];
-    for &depth in symbol_depth.values() {
+    for &(_, depth) in &symbol_depth {
       depth_count[depth] += 1;
     }
     let mut max_depth = 0;
@@ -145,49 +596,240 @@ impl<T: Ord+Copy> Huffman<T> {
       }
       depth_bound[depth] = available;
     }
-    ensure!(
-      1<<max_depth == depth_bound[max_depth] || (max_depth <= 1 && depth_bound[max_depth] == max_depth as u32),
-      "depth_bound error: {:?} {:?}", depth_count, depth_bound);
-    let mut depth_current = [0; Key::MAX_DEPTH+1];
+    if !(1<<max_depth == depth_bound[max_depth] || (max_depth <= 1 && depth_bound[max_depth] == max_depth as u32)) {
+      bail!(HuffmanError::Incomplete { depth_count });
+    }
+    let mut first_code = [0; Key::MAX_DEPTH+1];
+    let mut first_index = [0; Key::MAX_DEPTH+1];
     for i in 1..=Key::MAX_DEPTH {
-      depth_current[i] = depth_bound[i-1]*2;
+      first_code[i] = depth_bound[i-1]*2;
+      first_index[i] = first_index[i-1] + if i > 1 { depth_count[i-1] as u32 } else { 0 };
     }
-    let symbols: BTreeMap<_, _> = symbol_depth.iter().filter_map(|(&key, &depth)| {
-      if depth == 0 { return None }
-      let result = depth_current[depth];
+    let mut depth_current = first_code;
+    let mut sorted = vec![None; symbol_depth.len()];
+    let mut index_current = first_index;
+    let codes = symbol_depth.iter().map(|&(key, depth)| {
+      let code = depth_current[depth];
       depth_current[depth] += 1;
-      Some((key, result))
+      sorted[index_current[depth] as usize] = Some(key);
+      index_current[depth] += 1;
+      (key, depth as u8, code)
     }).collect();
-    let symbol_rev = symbols.iter().map(|(&k, &v)| ((symbol_depth[&k], v), k)).collect();
+    let sorted: Vec<T> = sorted.into_iter().map(|k| k.expect("every index assigned")).collect();
+
+    let lookup_bits = max_depth.min(Self::LOOKUP_BITS);
+    let mut lookup = vec![0; 1 << lookup_bits];
+    for depth in 1..=lookup_bits {
+      let shift = lookup_bits - depth;
+      for i in 0..depth_count[depth] as u32 {
+        let code = (first_code[depth] + i) as usize;
+        for entry in &mut lookup[code << shift..(code + 1) << shift] {
+          *entry = (first_index[depth] + i) << 5 | depth as u32;
+        }
+      }
+    }
     Ok(Self {
-      depth_count, symbol_depth, max_depth,
-      symbols, symbol_rev,
+      depth_count, codes, max_depth,
+      sorted, first_code, first_index, lookup, lookup_bits,
     })
   }
 
-  pub fn next(&self, codec: &mut Codec<'_>) -> Result<T, Error> {
-    ensure!(codec.current() < codec.len(), "stream end {} >= {}", codec.current(), codec.len());
-    let k = codec.look_bits(self.max_depth) as u32;
-    for i in 1..=self.max_depth {
-      let t = k >> (self.max_depth - i);
-      if let Some(sym) = self.symbol_rev.get(&(i, t)) {
-        codec.index += i;
-        return Ok(*sym)
+  /// build a length-limited canonical code (package-merge) from symbol frequencies,
+  /// symbols with zero frequency get no code
+  pub fn from_frequencies(freqs: &BTreeMap<T, u64>, max_depth: usize) -> Result<Self, Error> {
+    ensure!(max_depth <= Key::MAX_DEPTH, "max_depth {} > {}", max_depth, Key::MAX_DEPTH);
+    let symbols: Vec<_> = freqs.iter().filter(|(_, &f)| f != 0).collect();
+    ensure!(max_depth != 0 || symbols.len() <= 1, "max_depth 0 can't code {} symbols", symbols.len());
+    ensure!(symbols.len() <= 1 << max_depth, "{} symbols don't fit in depth {}", symbols.len(), max_depth);
+    let weights: Vec<_> = symbols.iter().map(|(_, &f)| f).collect();
+    let lengths = package_merge(&weights, max_depth);
+    Self::from_sorted(symbols.iter().zip(lengths).map(|((&k, _), d)| (k, d)).collect())
+  }
+
+  /// canonical code and its length of `symbol`
+  pub fn code(&self, symbol: T) -> Option<(u32, usize)> {
+    let i = self.codes.binary_search_by(|(k, _, _)| k.cmp(&symbol)).ok()?;
+    let (_, depth, code) = self.codes[i];
+    Some((code, depth as usize))
+  }
+
+  pub fn encode(&self, symbol: T, writer: &mut CodecWriter) -> Result<(), Error> where T: std::fmt::Debug {
+    let (code, depth) = self.code(symbol).ok_or_else(|| format_err!("symbol {:?} has no code", symbol))?;
+    writer.write_bits(depth, code as u64)
+  }
+
+  /// `(symbol, code, length)` of every symbol with a code, ordered by symbol
+  pub fn iter(&self) -> impl Iterator<Item = (T, u32, usize)> + '_ {
+    self.codes.iter().map(|&(symbol, depth, code)| (symbol, code, depth as usize))
+  }
+
+  /// render the code tree as a Graphviz DOT graph, edges are labelled with the code bits
+  /// and leaves with symbol, depth and code
+  pub fn to_dot(&self) -> String where T: std::fmt::Debug {
+    use std::fmt::Write;
+    let name = |depth: usize, prefix: u32| if depth == 0 { "root".to_string() } else { format!("n{}_{:0w$b}", depth, prefix, w = depth) };
+    let mut nodes = std::collections::BTreeSet::new();
+    let mut result = String::from("digraph huffman {\n  node [shape=circle, label=\"\"];\n  root;\n");
+    for &(symbol, depth, code) in &self.codes {
+      let depth = depth as usize;
+      for d in 1..=depth {
+        let prefix = code >> (depth - d);
+        if d < depth && !nodes.insert((d, prefix)) { continue }
+        if d == depth {
+          let label = format!("{:?}\\ndepth {}\\ncode {:0w$b}", symbol, depth, code, w = depth).replace('"', "\\\"");
+          let _ = writeln!(result, "  {} [shape=box, label=\"{}\"];", name(d, prefix), label);
+        }
+        let _ = writeln!(result, "  {} -> {} [label=\"{}\"];", name(d - 1, prefix >> 1), name(d, prefix), prefix & 1);
+      }
+    }
+    result.push_str("}\n");
+    result
+  }
+
+  /// heap bytes held by the code and lookup tables
+  pub fn memory_usage(&self) -> usize {
+    use std::mem::size_of;
+    self.codes.capacity() * size_of::<(T, u8, u32)>() +
+    self.sorted.capacity() * size_of::<T>() +
+    self.lookup.capacity() * size_of::<u32>()
+  }
+
+  pub fn next<C: BitRead>(&self, codec: &mut C) -> Result<T, Error> {
+    ensure!(!codec.is_end()?, "stream end at {}", codec.current());
+    let k = codec.peek_bits(self.max_depth)? as u32;
+    let (symbol, len) = self.decode(k).context("incomplete huffman tree no match")?;
+    codec.consume_bits(len)?;
+    codec.spend_symbols(1)?;
+    Ok(symbol)
+  }
+
+  /// decode `n` symbols into `out[..n]`, peeking 64 bits at a time and consuming them at once
+  pub fn next_n<C: BitRead>(&self, codec: &mut C, n: usize, out: &mut [T]) -> Result<(), Error> {
+    ensure!(n <= out.len(), "output too short {} < {}", out.len(), n);
+    ensure!(n == 0 || self.max_depth != 0, "empty huffman tree");
+    codec.spend_symbols(n)?;
+    let mut i = 0;
+    while i < n {
+      ensure!(!codec.is_end()?, "stream end at {}", codec.current());
+      let mut register = codec.peek_bits(64)?;
+      let mut used = 0;
+      while i < n && used + self.max_depth <= 64 {
+        let k = (register >> (64 - self.max_depth)) as u32;
+        let (symbol, len) = self.decode(k).context("incomplete huffman tree no match")?;
+        out[i] = symbol;
+        register <<= len;
+        used += len;
+        i += 1;
+      }
+      codec.consume_bits(used)?;
+    }
+    Ok(())
+  }
+
+  /// symbol and code length of the code at the top of the `max_depth` bits `k`
+  #[inline]
+  fn decode(&self, k: u32) -> Option<(T, usize)> {
+    let entry = self.lookup[(k >> (self.max_depth - self.lookup_bits)) as usize];
+    if entry != 0 {
+      return Some((self.sorted[(entry >> 5) as usize], (entry & 0x1f) as usize))
+    }
+    for i in self.lookup_bits+1..=self.max_depth {
+      let offset = (k >> (self.max_depth - i)).wrapping_sub(self.first_code[i]);
+      if offset < self.depth_count[i] as u32 {
+        return Some((self.sorted[(self.first_index[i] + offset) as usize], i))
+      }
+    }
+    None
+  }
+}
+
+/// optimal code lengths for `weights` (all non-zero) limited to `max_depth`,
+/// `weights.len()` must not exceed `1 << max_depth`
+fn package_merge(weights: &[u64], max_depth: usize) -> Vec<usize> {
+  let mut lengths = vec![0; weights.len()];
+  if weights.len() <= 1 {
+    lengths.iter_mut().for_each(|l| *l = 1);
+    return lengths
+  }
+  let mut leaves: Vec<(u64, usize)> = weights.iter().enumerate().map(|(i, &w)| (w, i)).collect();
+  leaves.sort();
+  // every level holds (weight, Some(symbol)) for leaves or (weight, None) for packages of the previous level
+  let mut levels: Vec<Vec<(u64, Option<usize>)>> = vec![leaves.iter().map(|&(w, i)| (w, Some(i))).collect()];
+  for _ in 1..max_depth {
+    let prev = levels.last().expect("at least one level");
+    let mut merged = Vec::with_capacity(leaves.len() + prev.len() / 2);
+    let mut leaf_iter = leaves.iter().map(|&(w, i)| (w, Some(i))).peekable();
+    for package in prev.chunks_exact(2).map(|p| (p[0].0 + p[1].0, None)) {
+      while let Some(leaf) = leaf_iter.next_if(|leaf| leaf.0 <= package.0) {
+        merged.push(leaf);
       }
+      merged.push(package);
     }
-    bail!("incomplete huffman tree no match");
+    merged.extend(leaf_iter);
+    levels.push(merged);
   }
+  // the cheapest 2n-2 items of the last level, packages expand to pairs of the level below
+  let mut selected = 2 * leaves.len() - 2;
+  for level in levels.iter().rev() {
+    let mut packages = 0;
+    for &(_, symbol) in &level[..selected] {
+      match symbol {
+        Some(i) => lengths[i] += 1,
+        None => packages += 1,
+      }
+    }
+    selected = 2 * packages;
+  }
+  lengths
 }
 
+/// The alphabet code lengths are encoded with, see `decode_code_lengths`.
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum Key {
+  /// a single code length `0..=MAX_DEPTH`
   Depth(usize),
-  ShortZero /* 17 */, LongZero /* 18 */,
-  ShortRepeat /* 19 */, LongRepeat /* 20 */,
+  /// 3..=10 zeros
+  ShortZero /* 17 */,
+  /// 11..=138 zeros
+  LongZero /* 18 */,
+  /// repeat the last code length 3..=6 times
+  ShortRepeat /* 19 */,
+  /// repeat the last code length 7..=70 times
+  LongRepeat /* 20 */,
 }
 use Key::*;
 
 impl Key {
+  /// numeric symbol `0..=20` as used by the reference implementation
+  pub fn symbol(self) -> usize {
+    match self {
+      Depth(d) => d,
+      ShortZero => 17,
+      LongZero => 18,
+      ShortRepeat => 19,
+      LongRepeat => 20,
+    }
+  }
+  pub fn from_symbol(symbol: usize) -> Option<Self> {
+    Some(match symbol {
+      0..=16 => Depth(symbol),
+      17 => ShortZero,
+      18 => LongZero,
+      19 => ShortRepeat,
+      20 => LongRepeat,
+      _ => return None,
+    })
+  }
+  /// (extra bits, base) of the run length this key covers, `Depth` always covers a single symbol
+  pub fn extra_bits(self) -> (usize, u64) {
+    match self {
+      Depth(_) => (0, 1),
+      ShortZero => (3, 3),
+      LongZero => (7, 11),
+      ShortRepeat => (2, 3),
+      LongRepeat => (6, 7),
+    }
+  }
   pub const MAX_DEPTH: usize = 16;
   pub const SHUFFLE: [Key; Self::MAX_DEPTH+5] = [
     ShortZero, LongZero, ShortRepeat, LongRepeat,
@@ -197,7 +839,43 @@ impl Key {
     Depth(2), Depth(14), Depth(1), Depth(15), Depth(16)];
 }
 
+/// Integer types the symbols of a huffman table decoded from a file could be stored as,
+/// `u16` is enough for `Huffman::MAX_SYMBOL_COUNT`.
+pub trait Symbol: Copy + Ord + Send + Sync + std::fmt::Debug + Into<u32> + std::convert::TryFrom<u32> {}
+impl Symbol for u16 {}
+impl Symbol for u32 {}
+
+impl<T: Symbol> Huffman<T> {
+  /// build from code lengths indexed by symbol, 0 means the symbol is absent
+  pub fn from_code_lengths(lengths: &[u8]) -> Result<Self, Error> {
+    if lengths.len() > Self::MAX_SYMBOL_COUNT {
+      bail!(HuffmanError::TooManySymbols { count: lengths.len(), max: Self::MAX_SYMBOL_COUNT });
+    }
+    Self::from_sorted(lengths.iter().enumerate().map(|(i, &d)| {
+      let symbol = T::try_from(i as u32).map_err(|_| format_err!("symbol {} out of range of {}", i, std::any::type_name::<T>()))?;
+      Ok((symbol, d as usize))
+    }).collect::<Result<_, Error>>()?)
+  }
+}
+
+/// Why a huffman table was rejected, see `CrnError::huffman`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HuffmanError {
+  /// more symbols than `Huffman::MAX_SYMBOL_COUNT`
+  #[error("huffman symbol count {count} > {max}")]
+  TooManySymbols { count: usize, max: usize },
+  /// symbol `index` has a code longer than `Key::MAX_DEPTH`, for symbols other than integers
+  /// `index` is the position in symbol order counting symbols without a code
+  #[error("huffman symbol {index} depth {depth} > {max}")]
+  DepthTooLarge { index: usize, depth: usize, max: usize },
+  /// code lengths don't form a complete prefix code, `depth_count[d]` is the number of codes of length `d`
+  #[error("huffman code lengths incomplete: {depth_count:?}")]
+  Incomplete { depth_count: [usize; Key::MAX_DEPTH+1] },
+}
+
 impl<T> Huffman<T> {
+  /// codes up to this length are resolved with a single table lookup
+  pub const LOOKUP_BITS: usize = 10;
   pub const MAX_SYMBOL_COUNT: usize = 8192;
   pub const MAX_SYMBOL_COUNT_BIT: usize = 14;
 }
@@ -224,4 +902,153 @@ fn test_huffman() {
   let huffman = Huffman::new(depth).expect("zero huffman");
   assert_eq!(huffman.next(&mut codec).unwrap(), 0x01);
   assert_eq!(huffman.next(&mut codec).unwrap(), 0xff);
+
+  let huffman = Huffman::<u32>::from_code_lengths(&[1, 0, 2, 2]).expect("from code lengths");
+  let mut codec = Codec::new(&[0b0101_1000]);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 0);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 2);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 3);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 0);
+  assert!(Huffman::<u32>::from_code_lengths(&[1, 17]).is_err());
+
+  // codes longer than LOOKUP_BITS: symbol i has depth i+1, the last two share depth 12
+  let input = [0xff, 0xff, 0xfe, 0x00];
+  let mut codec = Codec::new(&input);
+  let depth = (0..13u32).map(|i| (i, (i as usize + 1).min(12))).collect();
+  let huffman = Huffman::new(depth).expect("deep huffman");
+  assert_eq!(huffman.next(&mut codec).unwrap(), 12);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 11);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 0);
+  assert_eq!(codec.current(), 25);
+}
+
+#[test]
+fn test_huffman_encode() {
+  assert_eq!(package_merge(&[1, 1, 2, 4, 8, 16, 32], 4), vec![4, 4, 4, 4, 3, 3, 1]);
+  assert_eq!(package_merge(&[1, 1, 2, 4, 8, 16, 32], 16), vec![6, 6, 5, 4, 3, 2, 1]);
+
+  let freqs: BTreeMap<_, _> = [('a', 1), ('b', 1), ('c', 2), ('d', 4), ('e', 8), ('f', 16), ('g', 32), ('h', 0)].iter().cloned().collect();
+  let huffman = Huffman::from_frequencies(&freqs, 4).expect("from frequencies");
+  assert_eq!(huffman.code('g'), Some((0, 1)));
+  assert_eq!(huffman.code('h'), None);
+  let mut writer = CodecWriter::new();
+  let message = "gfedcbagg";
+  for c in message.chars() { huffman.encode(c, &mut writer).expect("encode") }
+  assert!(huffman.encode('h', &mut writer).is_err());
+  let bytes = writer.into_bytes();
+  let mut codec = Codec::new(&bytes);
+  for c in message.chars() { assert_eq!(huffman.next(&mut codec).unwrap(), c) }
+  assert!(codec.is_complete());
+
+  assert!(Huffman::from_frequencies(&freqs, 0).is_err());
+  let single: BTreeMap<_, _> = [('a', 0), ('b', 3)].iter().cloned().collect();
+  assert_eq!(Huffman::from_frequencies(&single, 0).unwrap().code('b'), Some((0, 1)));
+}
+
+#[test]
+fn test_stream_codec() {
+  let input = [0b1100_1010u8, 0b0110_1101, 0b1101_1001];
+  let mut codec = StreamCodec::with_chunk_size(&input[..], 1);
+  assert_eq!(codec.read_bits(3).unwrap(), 0b110);
+  assert_eq!(codec.read_bits(17).unwrap(), 0b1010_0110_1101_1101);
+  assert_eq!(codec.current(), 20);
+  assert_eq!(codec.peek_bits(8).unwrap(), 0b1001_0000);
+  assert!(codec.read_bits(5).is_err());
+  assert_eq!(codec.read_bits(4).unwrap(), 0b1001);
+  assert!(codec.is_complete());
+  assert!(codec.is_end().unwrap());
+
+  let input = [0xff, 0xff, 0xfe, 0x00];
+  let mut codec = StreamCodec::with_chunk_size(&input[..], 1);
+  let depth = (0..13u32).map(|i| (i, (i as usize + 1).min(12))).collect();
+  let huffman = Huffman::new(depth).expect("deep huffman");
+  assert_eq!(huffman.next(&mut codec).unwrap(), 12);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 11);
+  assert_eq!(huffman.next(&mut codec).unwrap(), 0);
+  assert_eq!(codec.current(), 25);
+}
+
+#[test]
+fn test_huffman_limits() {
+  let error = Huffman::<u32>::from_code_lengths(&[1, 17]).unwrap_err();
+  assert_eq!(error.huffman(), Some(&HuffmanError::DepthTooLarge { index: 1, depth: 17, max: 16 }));
+  assert_eq!(error.to_string(), "huffman symbol 1 depth 17 > 16");
+  let error = Huffman::<u32>::from_code_lengths(&[0, 0, 1, 0, 18]).unwrap_err();
+  assert_eq!(error.huffman(), Some(&HuffmanError::DepthTooLarge { index: 4, depth: 18, max: 16 }));
+  let depth = [(0u8, 0), (1, 0), (2, 1), (3, 17)].iter().cloned().collect();
+  assert_eq!(Huffman::new(depth).unwrap_err().huffman(), Some(&HuffmanError::DepthTooLarge { index: 3, depth: 17, max: 16 }));
+  let mut depth = BTreeMap::new();
+  depth.insert(0u8, 20);
+  assert!(Huffman::new(depth).unwrap_err().huffman().is_some());
+  let error = Huffman::<u32>::from_code_lengths(&[1, 2]).unwrap_err();
+  assert!(matches!(error.huffman(), Some(HuffmanError::Incomplete { .. })));
+
+  // 14-bit symbol count 0x3fff > MAX_SYMBOL_COUNT
+  let input = [0xff, 0xfc, 0x00];
+  let error = Codec::new(&input).get_huffman().unwrap_err();
+  assert_eq!(error.huffman(), Some(&HuffmanError::TooManySymbols { count: 0x3fff, max: 8192 }));
+}
+
+#[test]
+fn test_huffman_dot() {
+  let huffman = Huffman::<u32>::from_code_lengths(&[1, 0, 2, 2]).unwrap();
+  assert_eq!(huffman.iter().collect::<Vec<_>>(), vec![(0, 0b0, 1), (2, 0b10, 2), (3, 0b11, 2)]);
+  let huffman = Huffman::<u32>::from_code_lengths(&[1, 2, 2]).unwrap();
+  assert_eq!(huffman.to_dot(), r#"digraph huffman {
+  node [shape=circle, label=""];
+  root;
+  n1_0 [shape=box, label="0\ndepth 1\ncode 0"];
+  root -> n1_0 [label="0"];
+  root -> n1_1 [label="1"];
+  n2_10 [shape=box, label="1\ndepth 2\ncode 10"];
+  n1_1 -> n2_10 [label="0"];
+  n2_11 [shape=box, label="2\ndepth 2\ncode 11"];
+  n1_1 -> n2_11 [label="1"];
+}
+"#);
+}
+
+#[test]
+fn test_huffman_next_n() {
+  let input = [0xff, 0xff, 0xfe, 0x00, 0x5a, 0xa5, 0xff, 0x00, 0x12, 0x34];
+  let depth = (0..13u32).map(|i| (i, (i as usize + 1).min(12))).collect();
+  let huffman = Huffman::new(depth).expect("deep huffman");
+  let mut codec = Codec::new(&input);
+  let mut expected = vec![];
+  while let std::result::Result::Ok(symbol) = huffman.next(&mut codec) { expected.push(symbol) }
+  let mut codec = Codec::new(&input);
+  let mut out = vec![0; expected.len() + 1];
+  huffman.next_n(&mut codec, expected.len(), &mut out).unwrap();
+  assert_eq!(&out[..expected.len()], &expected[..]);
+  assert!(huffman.next_n(&mut codec, 1, &mut out).is_err());
+  assert!(huffman.next_n(&mut codec, 2, &mut out[..1]).is_err());
+
+  let mut codec = Codec::new(&input);
+  codec.set_budget(Budget { max_symbols: Some(2), max_bits: None });
+  huffman.next_n(&mut codec, 2, &mut out).unwrap();
+  assert!(matches!(huffman.next(&mut codec).unwrap_err(), Error::Budget(BudgetExceeded::Symbols(2))));
+}
+
+#[test]
+fn test_decode_code_lengths() {
+  for symbol in 0..=20 {
+    assert_eq!(Key::from_symbol(symbol).map(Key::symbol), Some(symbol));
+  }
+  assert_eq!(Key::from_symbol(21), None);
+  assert!(Key::SHUFFLE.iter().all(|k| k.symbol() <= 20));
+
+  // key tree: ShortZero and Depth(1) with depth 1, then lengths [1, 0, 0, 0, 1]
+  let mut writer = CodecWriter::new();
+  writer.write_bits(5, 19).unwrap();
+  for &key in &Key::SHUFFLE[..19] {
+    writer.write_bits(3, if key == ShortZero || key == Depth(1) { 1 } else { 0 }).unwrap();
+  }
+  // canonical codes follow `Key` order: Depth(1) => 0, ShortZero => 1
+  writer.write_bits(1, 0).unwrap();
+  writer.write_bits(1, 1).unwrap();
+  writer.write_bits(3, 0).unwrap();
+  writer.write_bits(1, 0).unwrap();
+  let bytes = writer.into_bytes();
+  assert_eq!(decode_code_lengths(&mut Codec::new(&bytes), 5).unwrap(), vec![1, 0, 0, 0, 1]);
+  assert!(decode_code_lengths(&mut Codec::new(&bytes), 3).is_err());
 }
@@ -0,0 +1,95 @@
+//! Rewrite a crn file keeping only the palette entries its levels actually reference.
+//!
+//! Every level is walked symbol by symbol to collect the referenced endpoint/selector
+//! indices, the palettes are rebuilt without the unused entries, and the palettes,
+//! tables and level streams are re-encoded with huffman codes fitted to the new data.
+
+use crate::error::{Context, CrnError as Error};
+use crate::{Header, encode::{Palettes, write_crn}};
+use crate::{symbols::{components, read_level}, unpack::Kind};
+
+/// Result of [`compact_palettes`]
+#[derive(Debug, Clone)]
+pub struct Compacted {
+  pub data: Vec<u8>,
+  /// entry count of (color_endpoints, color_selectors, alpha_endpoints, alpha_selectors) before and after
+  pub counts: [(u16, u16); 4],
+}
+
+/// drop unreferenced palette entries from a crn file and re-encode it
+pub fn compact_palettes(input: &[u8]) -> Result<Compacted, Error> {
+  let header = Header::parse(input).context("parse header")?;
+  let tables = header.get_table(input).context("read table")?;
+  let palettes = Palettes::from_tables(&tables);
+  let mut levels = (0..header.level_count as usize).map(|idx| {
+    read_level(&header, &tables, input, idx).with_context(|| format!("read level {}", idx))
+  }).collect::<Result<Vec<_>, Error>>()?;
+
+  let (endpoint_kinds, selector_kinds) = components(header.format)?;
+  let mut used: Vec<Vec<bool>> = Kind::ALL.iter().map(|&k| vec![false; palettes.len(k)]).collect();
+  for level in &levels {
+    for (&k, indices) in endpoint_kinds.iter().zip(&level.endpoints).chain(selector_kinds.iter().zip(&level.selectors)) {
+      for &i in indices { used[k.index()][i] = true }
+    }
+  }
+  let keep: [Vec<usize>; 4] = [0, 1, 2, 3].map(|k| (0..used[k].len()).filter(|&i| used[k][i]).collect());
+  let remap: Vec<Vec<usize>> = keep.iter().zip(&used).map(|(keep, used)| {
+    let mut remap = vec![0; used.len()];
+    for (new, &old) in keep.iter().enumerate() { remap[old] = new }
+    remap
+  }).collect();
+  for level in &mut levels {
+    for (&k, indices) in endpoint_kinds.iter().zip(&mut level.endpoints).chain(selector_kinds.iter().zip(&mut level.selectors)) {
+      for i in indices { *i = remap[k.index()][*i] }
+    }
+  }
+
+  let compacted = palettes.retain(&keep);
+  let data = write_crn(&header, &compacted, &levels)?;
+  let counts = [0, 1, 2, 3].map(|k| (palettes.len(Kind::ALL[k]) as u16, compacted.len(Kind::ALL[k]) as u16));
+  Ok(Compacted { data, counts })
+}
+
+#[test]
+fn test_compact() {
+  use crate::{Format, symbols::LevelSymbols};
+  // the encoder never leaves unused entries, so the palettes are written by hand: level 0 is
+  // 3x2 blocks => 2x1 chunks with tile layouts 7 and 1, level 1 is 2x1 blocks => 1 chunk
+  let template = Header {
+    width: 12, height: 8, level_count: 2, face_count: 1, format: Format::Dxt1,
+    ..Default::default()
+  };
+  let palettes = Palettes {
+    color_endpoints: vec![(0xf800, 0x001f), (0x07e0, 0xffff), (0x1234, 0x4321), (0, 0xffff)],
+    color_selectors: vec![[0x00; 4], [0xe4, 0x1b, 0x55, 0xaa], [0xff; 4]],
+    ..Default::default()
+  };
+  let levels = vec![
+    LevelSymbols { tiles: vec![7, 1], endpoints: vec![vec![3, 0, 3, 1, 0, 3]], selectors: vec![vec![2, 2, 0, 2, 0, 0, 2, 2]], ..Default::default() },
+    LevelSymbols { tiles: vec![0], endpoints: vec![vec![1]], selectors: vec![vec![0, 2, 2, 0]], ..Default::default() },
+  ];
+  let input = write_crn(&template, &palettes, &levels).expect("encode");
+  let header = Header::parse(&input).expect("parse");
+  assert!(header.check_crc(&input));
+  let tables = header.get_table(&input).expect("read table");
+  assert_eq!(tables.color_endpoint.as_ref().unwrap().entries, palettes.color_endpoints);
+  assert_eq!(tables.color_selector.as_ref().unwrap().entries, palettes.color_selectors);
+  for (l, level) in levels.iter().enumerate() {
+    let symbols = read_level(&header, &tables, &input, l).expect("read level");
+    assert_eq!(symbols.tiles, level.tiles);
+    assert_eq!(symbols.endpoints, level.endpoints);
+    assert_eq!(symbols.selectors, level.selectors);
+  }
+
+  let compacted = compact_palettes(&input).expect("compact");
+  assert_eq!(compacted.counts, [(4, 3), (3, 2), (0, 0), (0, 0)]);
+  let output = compacted.data;
+  let new_header = Header::parse(&output).expect("parse compacted");
+  assert!(new_header.check_crc(&output));
+  let new_tables = new_header.get_table(&output).expect("read compacted table");
+  for l in 0..2 {
+    assert_eq!(
+      header.unpack_level(&tables, &input, l).expect("unpack"),
+      new_header.unpack_level(&new_tables, &output, l).expect("unpack compacted"));
+  }
+}
@@ -0,0 +1,330 @@
+//! Compress RGBA8 texels into BCn blocks, the inverse of `rgba`.
+//!
+//! Color endpoints are fitted along the principal axis of the block, either to the extremes of
+//! the texels projected on it (range fit) or by least squares over every ordered split of the
+//! texels into the 4 palette entries (cluster fit). Channel blocks take the range of the values,
+//! cluster fit also tries narrower ranges and the 6-value mode with explicit 0 and 255.
+
+use crate::error::{CrnError as Error, bail, ensure};
+use crate::{Format, unpack::{Block, BlockKind, Dxn, Dxt1, Dxt5, Dxt5A}};
+
+/// how the endpoints of a block are searched, trading speed for quality
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+  /// endpoints at the extremes of the texels, a single pass
+  #[default]
+  Range,
+  /// least squares endpoints of the best split of the texels, several times slower
+  Cluster,
+}
+
+type Vec3 = [f32; 3];
+
+fn rgb(texel: [u8; 4]) -> Vec3 {
+  [texel[0] as f32, texel[1] as f32, texel[2] as f32]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+  a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn quantize_565(c: Vec3) -> u16 {
+  let q = |v: f32, max: f32| (v * max / 255.0).round().clamp(0.0, max) as u16;
+  q(c[0], 31.0) << 11 | q(c[1], 63.0) << 5 | q(c[2], 31.0)
+}
+
+/// mean and (unnormalized) principal axis of the points, by power iteration on the covariance
+fn principal_axis(points: &[Vec3]) -> (Vec3, Vec3) {
+  let n = points.len() as f32;
+  let mut mean = [0.0; 3];
+  for p in points { for i in 0..3 { mean[i] += p[i] / n } }
+  let mut cov = [[0.0f32; 3]; 3];
+  for p in points {
+    let d = [p[0] - mean[0], p[1] - mean[1], p[2] - mean[2]];
+    for i in 0..3 { for j in 0..3 { cov[i][j] += d[i] * d[j] } }
+  }
+  let mut axis = [1.0, 1.0, 1.0];
+  for _ in 0..8 {
+    let next = [dot(cov[0], axis), dot(cov[1], axis), dot(cov[2], axis)];
+    let len = dot(next, next).sqrt();
+    if len < 1e-6 { return (mean, [0.0; 3]) }
+    axis = next.map(|v| v / len);
+  }
+  (mean, axis)
+}
+
+/// endpoints at the extremes of the points projected on the principal axis
+fn range_fit(points: &[Vec3]) -> (u16, u16) {
+  let (mean, axis) = principal_axis(points);
+  let (mut min, mut max) = (f32::MAX, f32::MIN);
+  for p in points {
+    let t = dot([p[0] - mean[0], p[1] - mean[1], p[2] - mean[2]], axis);
+    min = min.min(t);
+    max = max.max(t);
+  }
+  let at = |t: f32| quantize_565([mean[0] + axis[0] * t, mean[1] + axis[1] * t, mean[2] + axis[2] * t]);
+  (at(max), at(min))
+}
+
+/// least squares endpoints of the split of the points (ordered along the principal axis) into the
+/// 4 palette entries with the smallest error
+fn cluster_fit(points: &[Vec3]) -> (u16, u16) {
+  let (mean, axis) = principal_axis(points);
+  let mut sorted = points.to_vec();
+  sorted.sort_by(|a, b| dot(*b, axis).total_cmp(&dot(*a, axis)));
+  // prefix sums of the sorted points
+  let mut sums = vec![[0.0f32; 3]; sorted.len() + 1];
+  for (i, p) in sorted.iter().enumerate() {
+    for c in 0..3 { sums[i + 1][c] = sums[i][c] + p[c] }
+  }
+  let range = |from: usize, to: usize| [0, 1, 2].map(|c| sums[to][c] - sums[from][c]);
+  let n = sorted.len();
+  let mut best = (f32::MAX, mean, mean);
+  for i in 0..=n {
+    for j in i..=n {
+      for k in j..=n {
+        // weights of the first endpoint are 1, 2/3, 1/3 and 0 in the 4 clusters
+        let (n0, n1, n2, n3) = (i as f32, (j - i) as f32, (k - j) as f32, (n - k) as f32);
+        let (s0, s1, s2, s3) = (range(0, i), range(i, j), range(j, k), range(k, n));
+        let aa = n0 + n1 * 4.0 / 9.0 + n2 / 9.0;
+        let bb = n1 / 9.0 + n2 * 4.0 / 9.0 + n3;
+        let ab = (n1 + n2) * 2.0 / 9.0;
+        let det = aa * bb - ab * ab;
+        if det.abs() < 1e-6 { continue }
+        let x = [0, 1, 2].map(|c| s0[c] + s1[c] * 2.0 / 3.0 + s2[c] / 3.0);
+        let y = [0, 1, 2].map(|c| s1[c] / 3.0 + s2[c] * 2.0 / 3.0 + s3[c]);
+        let a = [0, 1, 2].map(|c| ((x[c] * bb - y[c] * ab) / det).clamp(0.0, 255.0));
+        let b = [0, 1, 2].map(|c| ((y[c] * aa - x[c] * ab) / det).clamp(0.0, 255.0));
+        // the squared error without the constant sum of the squared points
+        let error = aa * dot(a, a) + bb * dot(b, b) + 2.0 * ab * dot(a, b) - 2.0 * dot(a, x) - 2.0 * dot(b, y);
+        if error < best.0 { best = (error, a, b) }
+      }
+    }
+  }
+  (quantize_565(best.1), quantize_565(best.2))
+}
+
+/// the 4 palette entries of a color block
+pub(crate) fn color_palette(endpoint: (u16, u16), punchthrough: bool) -> [[u8; 4]; 4] {
+  // selector 0b11100100 picks entries 0 to 3 for the first row
+  let color_selector = [0xe4; 4];
+  let texels = if punchthrough {
+    Dxt1 { color_endpoint: endpoint, color_selector }.decode_texels()
+  } else {
+    Dxt5 { color_endpoint: endpoint, color_selector, ..Default::default() }.decode_texels()
+  };
+  [texels[0], texels[1], texels[2], texels[3]]
+}
+
+/// selectors of the nearest of the first `count` palette entries for the opaque texels, entry 3
+/// for the transparent ones, and the squared error
+pub(crate) fn pick_colors(texels: &[[u8; 4]; 16], palette: &[[u8; 4]; 4], count: usize, transparent: &[bool; 16]) -> ([u8; 4], u32) {
+  let mut selector = [0; 4];
+  let mut error = 0;
+  for (i, &texel) in texels.iter().enumerate() {
+    let (s, e) = if transparent[i] { (3, 0) } else {
+      (0..count).map(|s| {
+        let e = (0..3).map(|c| (texel[c] as i32 - palette[s][c] as i32).pow(2) as u32).sum::<u32>();
+        (s, e)
+      }).min_by_key(|&(_, e)| e).unwrap_or((0, 0))
+    };
+    selector[i / 4] |= (s as u8) << (i % 4 * 2);
+    error += e;
+  }
+  (selector, error)
+}
+
+/// endpoints and selectors of a color block, `punchthrough` for BC1 where texels with alpha
+/// below 128 become transparent
+fn compress_color(texels: &[[u8; 4]; 16], fit: Fit, punchthrough: bool) -> ((u16, u16), [u8; 4]) {
+  let transparent = texels.map(|t| punchthrough && t[3] < 128);
+  let points: Vec<Vec3> = texels.iter().zip(&transparent).filter(|(_, &t)| !t).map(|(&t, _)| rgb(t)).collect();
+  if points.is_empty() { return ((0, 0), [0xff; 4]) }
+  let mut candidates = vec![range_fit(&points)];
+  if fit == Fit::Cluster { candidates.push(cluster_fit(&points)) }
+  let has_transparent = transparent.contains(&true);
+  candidates.into_iter().map(|(c0, c1)| {
+    // BC1 takes 4 colors when c0 > c1 and 3 plus transparent black otherwise
+    let endpoint = if punchthrough && (c0 < c1) != has_transparent { (c1, c0) } else { (c0, c1) };
+    let count = if punchthrough && endpoint.0 <= endpoint.1 { 3 } else { 4 };
+    let (selector, error) = pick_colors(texels, &color_palette(endpoint, punchthrough), count, &transparent);
+    (error, endpoint, selector)
+  }).min_by_key(|&(error, ..)| error).map(|(_, endpoint, selector)| (endpoint, selector)).unwrap_or_default()
+}
+
+/// selectors of the nearest palette entries of a channel block and the squared error
+pub(crate) fn pick_alpha(values: &[u8; 16], endpoint: (u8, u8)) -> ([u8; 6], u32) {
+  // selector 0o76543210 picks entries 0 to 7 for the first 8 texels
+  let mut selector = [0; 6];
+  selector.copy_from_slice(&(0..8u64).fold(0, |acc, i| acc | i << (3 * i)).to_le_bytes()[..6]);
+  let palette = Dxt5A { alpha_endpoint: endpoint, alpha_selector: selector }.decode_texels();
+  let mut bits = 0u64;
+  let mut error = 0;
+  for (i, &v) in values.iter().enumerate() {
+    let (s, e) = (0..8).map(|s| (s, (v as i32 - palette[s] as i32).pow(2) as u32)).min_by_key(|&(_, e)| e).unwrap_or((0, 0));
+    bits |= (s as u64) << (3 * i);
+    error += e;
+  }
+  selector.copy_from_slice(&bits.to_le_bytes()[..6]);
+  (selector, error)
+}
+
+/// endpoints and selectors of a channel block
+fn compress_alpha(values: &[u8; 16], fit: Fit) -> ((u8, u8), [u8; 6]) {
+  let (min, max) = (*values.iter().min().unwrap_or(&0), *values.iter().max().unwrap_or(&0));
+  let mut candidates = vec![(max, min)];
+  if fit == Fit::Cluster {
+    // narrower ranges for the 8-value mode, and the 6-value mode around the values other than 0 and 255
+    for lo in min..=min.saturating_add(8).min(max) {
+      for hi in max.saturating_sub(8).max(lo)..=max {
+        if hi > lo { candidates.push((hi, lo)) }
+      }
+    }
+    let inner = values.iter().filter(|&&v| v != 0 && v != 255);
+    if let (Some(&lo), Some(&hi)) = (inner.clone().min(), inner.max()) {
+      candidates.push((lo, hi));
+    }
+  }
+  candidates.into_iter().map(|endpoint| {
+    let (selector, error) = pick_alpha(values, endpoint);
+    (error, endpoint, selector)
+  }).min_by_key(|&(error, ..)| error).map(|(_, endpoint, selector)| (endpoint, selector)).unwrap_or_default()
+}
+
+fn channel(texels: &[[u8; 4]; 16], c: usize) -> [u8; 16] {
+  texels.map(|t| t[c])
+}
+
+/// a BC1 block, texels with alpha below 128 become transparent
+pub fn compress_dxt1(texels: &[[u8; 4]; 16], fit: Fit) -> Dxt1 {
+  let (color_endpoint, color_selector) = compress_color(texels, fit, true);
+  Dxt1 { color_endpoint, color_selector }
+}
+
+/// a BC3 block
+pub fn compress_dxt5(texels: &[[u8; 4]; 16], fit: Fit) -> Dxt5 {
+  let (color_endpoint, color_selector) = compress_color(texels, fit, false);
+  let (alpha_endpoint, alpha_selector) = compress_alpha(&channel(texels, 3), fit);
+  Dxt5 { alpha_endpoint, alpha_selector, color_endpoint, color_selector }
+}
+
+/// a BC4 block of single channel values
+pub fn compress_dxt5a(values: &[u8; 16], fit: Fit) -> Dxt5A {
+  let (alpha_endpoint, alpha_selector) = compress_alpha(values, fit);
+  Dxt5A { alpha_endpoint, alpha_selector }
+}
+
+/// a BC5 block of two channels
+pub fn compress_dxn(x: &[u8; 16], y: &[u8; 16], fit: Fit) -> Dxn {
+  let (alpha0_endpoint, alpha0_selector) = compress_alpha(x, fit);
+  let (alpha1_endpoint, alpha1_selector) = compress_alpha(y, fit);
+  Dxn { alpha0_endpoint, alpha0_selector, alpha1_endpoint, alpha1_selector }
+}
+
+/// compress 16 RGBA texels in row-major order into a block of `format` in `out[..block_size]`,
+/// the inverse of `rgba::decode_block`: `Dxt5A` takes red, `Dxn*` X from red and Y from green
+pub fn compress_block(format: Format, texels: &[[u8; 4]; 16], fit: Fit, out: &mut [u8]) -> Result<(), Error> {
+  let kind = BlockKind::of(format)?;
+  ensure!(out.len() >= format.block_size(), "output too short {} < {}", out.len(), format.block_size());
+  match kind {
+    BlockKind::Dxt1 => compress_dxt1(texels, fit).write_bytes(out),
+    BlockKind::Dxt3 => {
+      let (color_endpoint, color_selector) = compress_color(texels, fit, false);
+      Dxt1 { color_endpoint, color_selector }.write_bytes(&mut out[8..]);
+      for (i, pair) in texels.chunks(2).enumerate() {
+        // 4-bit alpha expands by `a << 4 | a`, i.e. times 17
+        let [a, b] = [pair[0][3], pair[1][3]].map(|a| (a as u16 + 8) / 17);
+        out[i] = (b << 4 | a) as u8;
+      }
+    }
+    BlockKind::Dxt5 => compress_dxt5(texels, fit).write_bytes(out),
+    BlockKind::Dxt5A => compress_dxt5a(&channel(texels, 0), fit).write_bytes(out),
+    BlockKind::Dxn if format == Format::DxnYX => compress_dxn(&channel(texels, 1), &channel(texels, 0), fit).write_bytes(out),
+    BlockKind::Dxn => compress_dxn(&channel(texels, 0), &channel(texels, 1), fit).write_bytes(out),
+  }
+  Ok(())
+}
+
+/// compress `width * height * 4` bytes of RGBA into tightly packed blocks of one face, the
+/// inverse of `rgba::decode_image`; blocks over the right or bottom edge repeat the last texels,
+/// compressed on all cores with the `parallel` feature
+pub fn compress_image(format: Format, rgba: &[u8], width: u16, height: u16, fit: Fit) -> Result<Vec<u8>, Error> {
+  let block_size = format.block_size();
+  let size = crate::unpack::level_size(width, height, 1, block_size)?;
+  let (width, height) = (width as usize, height as usize);
+  if rgba.len() < width * height * 4 {
+    bail!("image data too short {} < {}", rgba.len(), width * height * 4);
+  }
+  let block_x = width.div_ceil(4);
+  let mut result = vec![0; size];
+  let compress = |(b, out): (usize, &mut [u8])| {
+    let (bx, by) = (b % block_x, b / block_x);
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+      let x = (bx * 4 + i % 4).min(width - 1);
+      let y = (by * 4 + i / 4).min(height - 1);
+      texel.copy_from_slice(&rgba[(y * width + x) * 4..][..4]);
+    }
+    compress_block(format, &texels, fit, out)
+  };
+  #[cfg(feature = "parallel")]
+  {
+    use rayon::prelude::*;
+    result.par_chunks_exact_mut(block_size).enumerate().try_for_each(compress)?;
+  }
+  #[cfg(not(feature = "parallel"))]
+  result.chunks_exact_mut(block_size).enumerate().try_for_each(compress)?;
+  Ok(result)
+}
+
+#[test]
+fn test_compress() {
+  use crate::rgba::{decode_block, decode_image};
+  let error = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(&a, &b)| (a as i64 - b as i64).pow(2)).sum::<i64>();
+
+  // colors exactly representable in 565 survive
+  let texels = [[255, 0, 0, 255]; 16];
+  let mut block = [0; 8];
+  compress_block(Format::Dxt1, &texels, Fit::Range, &mut block).unwrap();
+  let mut decoded = [[0; 4]; 16];
+  decode_block(Format::Dxt1, &block, &mut decoded).unwrap();
+  assert_eq!(decoded, texels);
+  // two colors, half of them transparent
+  let texels: [[u8; 4]; 16] = std::array::from_fn(|i| match i % 3 { 0 => [0, 0, 0, 0], 1 => [255, 255, 255, 255], _ => [0, 0, 255, 255] });
+  for fit in [Fit::Range, Fit::Cluster] {
+    compress_block(Format::Dxt1, &texels, fit, &mut block).unwrap();
+    decode_block(Format::Dxt1, &block, &mut decoded).unwrap();
+    assert_eq!(decoded, texels, "{:?}", fit);
+  }
+
+  // a 16x8 gradient with a noisy alpha
+  let (width, height) = (16u16, 8u16);
+  let image: Vec<u8> = (0..width as usize * height as usize).flat_map(|i| {
+    let (x, y) = ((i % 16) as u8, (i / 16) as u8);
+    [x * 15, y * 30 + x, 200 - x * 7 - y * 9, (i as u32).wrapping_mul(2654435761).to_be_bytes()[0]]
+  }).collect();
+  for format in [Format::Dxt1, Format::Dxt3, Format::Dxt5, Format::Dxt5A, Format::DxnXY, Format::DxnYX] {
+    // BC1 would punch out the texels with low alpha
+    let mut image = image.clone();
+    if format == Format::Dxt1 { image.iter_mut().skip(3).step_by(4).for_each(|a| *a = 255) }
+    let channels = match format { Format::Dxt5A => 1, Format::DxnXY | Format::DxnYX => 2, Format::Dxt1 => 3, _ => 4 };
+    let strip = |rgba: &[u8]| rgba.chunks(4).flat_map(|t| t[..channels].to_vec()).collect::<Vec<_>>();
+    let errors = [Fit::Range, Fit::Cluster].map(|fit| {
+      let blocks = compress_image(format, &image, width, height, fit).unwrap();
+      assert_eq!(blocks.len(), 4 * 2 * format.block_size());
+      error(&strip(&decode_image(format, &blocks, width, height).unwrap()), &strip(&image))
+    });
+    assert!(errors[1] <= errors[0], "{:?} {:?}", format, errors);
+    // within 16 of every channel on average, 4 colors can't follow a gradient in both directions
+    assert!(errors[1] < 256 * channels as i64 * image.len() as i64 / 4, "{:?} {:?}", format, errors);
+  }
+  // 13x6 covers 4x2 blocks, the edges are padded by the last texels
+  let cut: Vec<u8> = image.chunks(16 * 4).take(6).flat_map(|row| row[..13 * 4].to_vec()).collect();
+  let blocks = compress_image(Format::Dxt5, &cut, 13, 6, Fit::Range).unwrap();
+  assert_eq!(blocks.len(), 4 * 2 * 16);
+  assert_eq!(decode_image(Format::Dxt5, &blocks, 13, 6).unwrap().len(), cut.len());
+  assert!(compress_image(Format::Dxt1, &cut[1..], 13, 6, Fit::Range).is_err());
+  assert!(matches!(compress_block(Format::Etc1, &texels, Fit::Range, &mut block), Err(Error::UnsupportedFormat { .. })));
+  // an 8-byte block has no room for BC3
+  assert!(compress_block(Format::Dxt5, &texels, Fit::Range, &mut block).is_err());
+}
@@ -0,0 +1,48 @@
+//! Scratch buffers of a decode kept between calls, so decoding many levels or files in a row
+//! stops allocating once the buffers have grown to the largest level.
+
+use crate::unpack::{Dxt1, Dxt5, Dxt5A, Dxn};
+
+/// Reusable buffers for [`Header::unpack_level_in`](crate::Header::unpack_level_in): the two rows
+/// of blocks buffered while walking a chunk row, per block type, and the unpacked level.
+/// Tile layouts and running palette indices live on the stack, so these are all the allocations
+/// of a decode besides the error path.
+#[derive(Debug, Default, Clone)]
+pub struct DecodeContext {
+  pub(crate) output: Vec<u8>,
+  pub(crate) dxt1: Vec<Dxt1>,
+  pub(crate) dxt5: Vec<Dxt5>,
+  pub(crate) dxt5a: Vec<Dxt5A>,
+  pub(crate) dxn: Vec<Dxn>,
+}
+
+impl DecodeContext {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// a context whose output never reallocates for levels up to `bytes`
+  pub fn with_capacity(bytes: usize) -> Self {
+    Self { output: Vec::with_capacity(bytes), ..Default::default() }
+  }
+
+  /// bytes the output could hold without reallocating
+  pub fn capacity(&self) -> usize {
+    self.output.capacity()
+  }
+}
+
+#[test]
+fn test_context() {
+  let (input, header, tables) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  // level 0 is the largest, so the output stays where it was
+  let mut context = DecodeContext::new();
+  let first = header.unpack_level_in(&tables, &input, 0, &mut context).unwrap().as_ptr();
+  for l in [1, 0, 1] {
+    let data = header.unpack_level_in(&tables, &input, l, &mut context).unwrap();
+    assert_eq!(data, &header.unpack_level(&tables, &input, l).unwrap()[..]);
+    assert_eq!(data.as_ptr(), first);
+  }
+  assert!(header.unpack_level_in(&tables, &input, 2, &mut context).is_err());
+  assert!(DecodeContext::with_capacity(100).capacity() >= 100);
+}
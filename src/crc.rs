@@ -0,0 +1,88 @@
+//! Incremental CRC-16 of crn headers and data, so a file could be checked while it's read.
+
+/// `CRC16_TABLE[x]` is what byte `x` (already xored with the high byte of the crc) shifts in
+const CRC16_TABLE: [u16; 256] = {
+  let mut table = [0; 256];
+  let mut i = 0;
+  while i < 256 {
+    let x = (i ^ (i >> 4)) as u16;
+    table[i] = (x << 12) ^ (x << 5) ^ x;
+    i += 1;
+  }
+  table
+};
+
+/// CRC-16/CCITT as in `Header::crc16`, fed a piece at a time.
+///
+/// `finish` gives the value stored in `header_crc16` and `data_crc16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc16 {
+  state: u16,
+}
+
+impl Default for Crc16 {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Crc16 {
+  pub fn new() -> Self {
+    Self::with_init(0)
+  }
+
+  /// continue from `init`, the `finish` of the crc of earlier data
+  pub fn with_init(init: u16) -> Self {
+    Self { state: !init }
+  }
+
+  pub fn update(&mut self, input: &[u8]) {
+    self.state = input.iter().fold(self.state, |v, &c| (v << 8) ^ CRC16_TABLE[(c ^ (v >> 8) as u8) as usize]);
+  }
+
+  /// the running crc, `Header::crc16(init, input)` after `update(input)`
+  pub fn value(&self) -> u16 {
+    self.state
+  }
+
+  /// the crc as stored in a header, `!value()`
+  pub fn finish(&self) -> u16 {
+    !self.state
+  }
+}
+
+impl std::io::Write for Crc16 {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.update(buf);
+    Ok(buf.len())
+  }
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn test_crc16() {
+  let input = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<_>>();
+  let mut crc = Crc16::new();
+  for chunk in input.chunks(100) {
+    crc.update(chunk);
+  }
+  assert_eq!(crc.value(), crate::Header::crc16(0, &input));
+  assert_eq!(crc.finish(), !crate::Header::crc16(0, &input));
+  let mut crc = Crc16::with_init(!crate::Header::crc16(0, &input[..10]));
+  std::io::copy(&mut &input[10..], &mut crc).unwrap();
+  assert_eq!(crc.value(), crate::Header::crc16(0, &input));
+}
+
+#[test]
+fn test_data_crc() {
+  let (input, header, _) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  let mut crc = Crc16::new();
+  for chunk in input[header.header_size as usize..].chunks(5) {
+    crc.update(chunk);
+  }
+  assert!(header.verify_data_crc(&crc));
+  crc.update(&[0]);
+  assert!(!header.verify_data_crc(&crc));
+}
@@ -0,0 +1,95 @@
+//! `image::ImageDecoder` for crn files, so code working with `image` takes them like any other
+//! format through `DynamicImage::from_decoder`.
+//!
+//! The decoder reads the whole file up front and expands a single face of a single level, the
+//! base level of the first face unless `CrnDecoder::select` picks another, into RGBA8.
+
+use std::io::{Cursor, Read};
+use image::{ColorType, ImageDecoder, ImageError, ImageResult, error::{DecodingError, ImageFormatHint}};
+use crate::error::{CrnError as Error, ensure};
+use crate::{Header, ParseOptions};
+
+pub struct CrnDecoder {
+  input: Vec<u8>,
+  header: Header,
+  level: usize,
+  face: usize,
+}
+
+impl From<Error> for ImageError {
+  fn from(e: Error) -> Self {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("crn".to_string()), e))
+  }
+}
+
+impl CrnDecoder {
+  /// read and check a crn file
+  pub fn new<R: Read>(mut reader: R) -> Result<Self, Error> {
+    let mut input = vec![];
+    reader.read_to_end(&mut input)?;
+    let header = Header::parse_with(&input, &ParseOptions::default())?;
+    Ok(Self { input, header, level: 0, face: 0 })
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  /// decode `face` of `level` instead of the base level of the first face
+  pub fn select(mut self, level: usize, face: usize) -> Result<Self, Error> {
+    ensure!(level < self.header.level_count as usize, Error::level(level));
+    ensure!(face < self.header.face_count as usize, Error::OutOfBounds { what: "face", index: face });
+    (self.level, self.face) = (level, face);
+    Ok(self)
+  }
+
+  /// the RGBA8 texels of the selected face and level
+  pub fn decode(&self) -> Result<Vec<u8>, Error> {
+    let (width, height) = self.header.get_level_info(self.level).ok_or_else(|| Error::level(self.level))?;
+    let tables = self.header.get_table(&self.input)?;
+    let blocks = self.header.unpack_level(&tables, &self.input, self.level)?;
+    let face_size = blocks.len() / self.header.face_count as usize;
+    crate::rgba::decode_image(self.header.format, &blocks[self.face * face_size..][..face_size], width, height)
+  }
+}
+
+impl<'a> ImageDecoder<'a> for CrnDecoder {
+  type Reader = Cursor<Vec<u8>>;
+
+  fn dimensions(&self) -> (u32, u32) {
+    let (width, height) = self.header.get_level_info(self.level).unwrap_or_default();
+    (width as u32, height as u32)
+  }
+
+  fn color_type(&self) -> ColorType {
+    ColorType::Rgba8
+  }
+
+  fn into_reader(self) -> ImageResult<Self::Reader> {
+    Ok(Cursor::new(self.decode()?))
+  }
+}
+
+#[test]
+fn test_decoder() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 32, 16) };
+  let input = gen.build().unwrap();
+  let levels = gen.blocks().unwrap();
+  let image = image::DynamicImage::from_decoder(CrnDecoder::new(&input[..]).unwrap()).unwrap();
+  assert_eq!((image.width(), image.height()), (32, 16));
+  assert_eq!(image.to_rgba8().into_raw(), crate::rgba::decode_image(Format::Dxt5, &levels[0][..32 * 16], 32, 16).unwrap());
+
+  let decoder = CrnDecoder::new(&input[..]).unwrap().select(1, 3).unwrap();
+  assert_eq!(decoder.dimensions(), (16, 8));
+  let mut buf = vec![0; decoder.total_bytes() as usize];
+  decoder.read_image(&mut buf).unwrap();
+  assert_eq!(buf, crate::rgba::decode_image(Format::Dxt5, &levels[1][3 * 128..][..128], 16, 8).unwrap());
+
+  assert!(CrnDecoder::new(&input[..]).unwrap().select(6, 0).is_err());
+  assert!(CrnDecoder::new(&input[..]).unwrap().select(0, 6).is_err());
+  assert!(CrnDecoder::new(&input[1..]).is_err());
+  let mut corrupt = input.clone();
+  *corrupt.last_mut().unwrap() ^= 1;
+  assert!(matches!(CrnDecoder::new(&corrupt[..]), Err(Error::CrcMismatch)));
+}
@@ -0,0 +1,925 @@
+//! Write crn files from raw BCn blocks.
+//!
+//! Every block is split into its endpoint and selector entries, which are collected into
+//! palettes of distinct entries, or clustered first down to the sizes in `EncodeOptions`. Below
+//! the full quality each 2x2 chunk first picks the tile layout trading added error against the
+//! endpoints it saves, then palettes, tables and level streams are written with huffman codes
+//! fitted to the symbols.
+
+use crate::error::{CrnError as Error, ensure, format_err};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use crate::{CrnBuilder, Format, Header, Tables, Table, codec::{CodecWriter, Huffman, Key}};
+use crate::{symbols::{LevelSymbols, components}, vq::{Parts, Texels, par_map, refit}, unpack::{Dxn, Dxt1, Dxt5, Dxt5A, Kind, Unpack, dispatch, level_size}};
+
+/// the entries of the four palettes, in file order
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Palettes {
+  pub color_endpoints: Vec<(u16, u16)>,
+  pub color_selectors: Vec<[u8; 4]>,
+  pub alpha_endpoints: Vec<(u8, u8)>,
+  pub alpha_selectors: Vec<[u8; 6]>,
+}
+
+impl Palettes {
+  pub(crate) fn from_tables(tables: &Tables) -> Self {
+    fn entries<T: Copy>(table: &Option<Table<T>>) -> Vec<T> {
+      table.as_ref().map(|t| t.entries.clone()).unwrap_or_default()
+    }
+    Self {
+      color_endpoints: entries(&tables.color_endpoint),
+      color_selectors: entries(&tables.color_selector),
+      alpha_endpoints: entries(&tables.alpha_endpoint),
+      alpha_selectors: entries(&tables.alpha_selector),
+    }
+  }
+  pub(crate) fn len(&self, kind: Kind) -> usize {
+    match kind {
+      Kind::ColorEndpoint => self.color_endpoints.len(),
+      Kind::ColorSelector => self.color_selectors.len(),
+      Kind::AlphaEndpoint => self.alpha_endpoints.len(),
+      Kind::AlphaSelector => self.alpha_selectors.len(),
+    }
+  }
+  /// keep only entries listed in `keep` (sorted) for each palette
+  pub(crate) fn retain(&self, keep: &[Vec<usize>; 4]) -> Self {
+    fn pick<T: Copy>(entries: &[T], keep: &[usize]) -> Vec<T> {
+      keep.iter().map(|&i| entries[i]).collect()
+    }
+    Self {
+      color_endpoints: pick(&self.color_endpoints, &keep[Kind::ColorEndpoint.index()]),
+      color_selectors: pick(&self.color_selectors, &keep[Kind::ColorSelector.index()]),
+      alpha_endpoints: pick(&self.alpha_endpoints, &keep[Kind::AlphaEndpoint.index()]),
+      alpha_selectors: pick(&self.alpha_selectors, &keep[Kind::AlphaSelector.index()]),
+    }
+  }
+}
+
+/// a huffman code over dense `u32` symbols, with the code lengths kept for serialization
+struct Encoder {
+  lengths: Vec<u8>,
+  huffman: Huffman<u32>,
+}
+
+impl Encoder {
+  fn from_freqs(freqs: &[u64], max_depth: usize) -> Result<Self, Error> {
+    let map = freqs.iter().enumerate().filter(|(_, &f)| f != 0).map(|(i, &f)| (i as u32, f)).collect();
+    let huffman = Huffman::from_frequencies(&map, max_depth)?;
+    let lengths = (0..freqs.len() as u32).map(|i| huffman.code(i).map_or(0, |(_, len)| len as u8)).collect();
+    Ok(Self { lengths, huffman })
+  }
+  fn write(&self, w: &mut CodecWriter, symbol: usize) -> Result<(), Error> {
+    self.huffman.encode(symbol as u32, w)
+  }
+  /// serialize the code lengths in the format read by `Codec::get_huffman`
+  fn write_table(&self, w: &mut CodecWriter) -> Result<(), Error> {
+    let symbol_count = self.lengths.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1);
+    w.write_bits(crate::codec::Huffman::<()>::MAX_SYMBOL_COUNT_BIT, symbol_count as u64)?;
+    if symbol_count == 0 { return Ok(()) }
+    write_code_lengths(w, &self.lengths[..symbol_count])
+  }
+}
+
+/// (key, number of symbols it covers) for `lengths`, taking the longest runs first
+fn greedy_keys(lengths: &[u8]) -> Vec<(Key, usize)> {
+  let mut keys = vec![];
+  let mut i = 0;
+  while i < lengths.len() {
+    let d = lengths[i];
+    let run = lengths[i..].iter().take_while(|&&l| l == d).count();
+    if d == 0 && run >= 11 {
+      let n = run.min(138);
+      keys.push((Key::LongZero, n)); i += n;
+    } else if d == 0 && run >= 3 {
+      let n = run.min(10);
+      keys.push((Key::ShortZero, n)); i += n;
+    } else {
+      keys.push((Key::Depth(d as usize), 1)); i += 1;
+      let mut rest = run - 1;
+      while rest >= 3 {
+        if rest >= 7 {
+          let n = rest.min(70);
+          keys.push((Key::LongRepeat, n)); i += n; rest -= n;
+        } else {
+          let n = rest.min(6);
+          keys.push((Key::ShortRepeat, n)); i += n; rest -= n;
+        }
+      }
+    }
+  }
+  keys
+}
+
+/// the (key, number of symbols) sequence covering `lengths` with the fewest bits, given the code
+/// length of every key. Every key leaves the last code length at `lengths[i - 1]`, so a repeat at
+/// `i` is possible exactly when `lengths[i - 1] == lengths[i]`.
+fn cheapest_keys(lengths: &[u8], cost: impl Fn(Key) -> usize) -> Vec<(Key, usize)> {
+  let n = lengths.len();
+  // cheapest bits to cover `lengths[..i]`, and the last key doing so
+  let mut best = vec![(usize::MAX, (Key::Depth(0), 0)); n + 1];
+  best[0].0 = 0;
+  for i in 0..n {
+    if best[i].0 == usize::MAX { continue }
+    let d = lengths[i];
+    let run = lengths[i..].iter().take_while(|&&l| l == d).count();
+    let mut options = vec![(Key::Depth(d as usize), 1..=1)];
+    if d == 0 {
+      options.push((Key::ShortZero, 3..=10));
+      options.push((Key::LongZero, 11..=138));
+    }
+    if i > 0 && lengths[i - 1] == d {
+      options.push((Key::ShortRepeat, 3..=6));
+      options.push((Key::LongRepeat, 7..=70));
+    }
+    for (key, counts) in options {
+      let bits = best[i].0 + cost(key) + key.extra_bits().0;
+      for count in counts.filter(|&c| c <= run) {
+        if bits < best[i + count].0 { best[i + count] = (bits, (key, count)) }
+      }
+    }
+  }
+  let mut keys = vec![];
+  let mut i = n;
+  while i > 0 {
+    let (_, (key, count)) = best[i];
+    keys.push((key, count));
+    i -= count;
+  }
+  keys.reverse();
+  keys
+}
+
+/// huffman code of the keys and the bits of the whole code length table written with it
+fn key_code(keys: &[(Key, usize)]) -> Result<(Huffman<Key>, usize), Error> {
+  let mut freqs = BTreeMap::new();
+  for &(key, _) in keys { *freqs.entry(key).or_insert(0) += 1 }
+  let huffman = Huffman::from_frequencies(&freqs, 7)?;
+  let tmp_symbol_count = Key::SHUFFLE.iter().rposition(|&k| huffman.code(k).is_some()).map_or(0, |i| i + 1);
+  let bits = 5 + 3 * tmp_symbol_count + keys.iter().map(|&(key, _)| {
+    huffman.code(key).map_or(0, |(_, len)| len) + key.extra_bits().0
+  }).sum::<usize>();
+  Ok((huffman, bits))
+}
+
+/// Write code lengths with the `Key` alphabet. Starting from the greedy runs, the cheapest key
+/// sequence for the code fitted to the previous one is searched until the table stops shrinking;
+/// keys not used yet are priced at the longest key code.
+fn write_code_lengths(w: &mut CodecWriter, lengths: &[u8]) -> Result<(), Error> {
+  let mut keys = greedy_keys(lengths);
+  let (mut huffman, mut bits) = key_code(&keys)?;
+  for _ in 0..4 {
+    let next = cheapest_keys(lengths, |key| huffman.code(key).map_or(7, |(_, len)| len));
+    let (next_huffman, next_bits) = key_code(&next)?;
+    if next_bits >= bits { break }
+    (keys, huffman, bits) = (next, next_huffman, next_bits);
+  }
+
+  let key_depth = |key| huffman.code(key).map_or(0, |(_, len)| len);
+  let tmp_symbol_count = Key::SHUFFLE.iter().rposition(|&k| key_depth(k) != 0).map_or(0, |i| i + 1);
+  w.write_bits(5, tmp_symbol_count as u64)?;
+  for &key in &Key::SHUFFLE[..tmp_symbol_count] {
+    w.write_bits(3, key_depth(key) as u64)?;
+  }
+  for (key, n) in keys {
+    huffman.encode(key, w)?;
+    let (extra_bits, base) = key.extra_bits();
+    w.write_extra_bits(extra_bits, base, n as u64)?;
+  }
+  Ok(())
+}
+
+fn histogram(symbols: &[u32], size: usize) -> Vec<u64> {
+  let mut freqs = vec![0; size];
+  for &s in symbols { freqs[s as usize] += 1 }
+  freqs
+}
+
+/// write `dm` tables followed by symbols, `groups[i]` selects which table symbol `i` uses
+fn write_palette(symbols: &[u32], groups: &[usize], table_sizes: &[usize]) -> Result<Vec<u8>, Error> {
+  let encoders = (0..table_sizes.len()).map(|t| {
+    let picked: Vec<_> = symbols.iter().enumerate().filter(|(i, _)| groups[i % groups.len()] == t).map(|(_, &s)| s).collect();
+    Encoder::from_freqs(&histogram(&picked, table_sizes[t]), Key::MAX_DEPTH)
+  }).collect::<Result<Vec<_>, Error>>()?;
+  let mut w = CodecWriter::new();
+  for encoder in &encoders { encoder.write_table(&mut w)? }
+  for (i, &s) in symbols.iter().enumerate() {
+    encoders[groups[i % groups.len()]].write(&mut w, s as usize)?;
+  }
+  Ok(w.into_bytes())
+}
+
+fn encode_color_endpoints(entries: &[(u16, u16)]) -> Result<Vec<u8>, Error> {
+  let mut last = [0u16; 6];
+  const MASK: [u16; 6] = [0x1f, 0x3f, 0x1f, 0x1f, 0x3f, 0x1f];
+  let mut symbols = vec![];
+  for &(c0, c1) in entries {
+    let current = [c0 >> 11, c0 >> 5 & 0x3f, c0 & 0x1f, c1 >> 11, c1 >> 5 & 0x3f, c1 & 0x1f];
+    for i in 0..6 {
+      symbols.push((current[i].wrapping_sub(last[i]) & MASK[i]) as u32);
+    }
+    last = current;
+  }
+  write_palette(&symbols, &[0, 1, 0, 0, 1, 0], &[32, 64])
+}
+
+fn encode_alpha_endpoints(entries: &[(u8, u8)]) -> Result<Vec<u8>, Error> {
+  let mut last = (0u8, 0u8);
+  let mut symbols = vec![];
+  for &(a, b) in entries {
+    symbols.push(a.wrapping_sub(last.0) as u32);
+    symbols.push(b.wrapping_sub(last.1) as u32);
+    last = (a, b);
+  }
+  write_palette(&symbols, &[0], &[256])
+}
+
+fn encode_color_selectors(entries: &[[u8; 4]]) -> Result<Vec<u8>, Error> {
+  const INV: [u8; 4] = [0, 3, 1, 2]; // inverse of DXT1 order in `get_color_selectors`
+  let mut last = [0u8; 16];
+  let mut symbols = vec![];
+  for entry in entries {
+    let current: Vec<_> = (0..16).map(|t| INV[(entry[t / 4] >> (2 * (t % 4)) & 3) as usize]).collect();
+    for j in 0..8 {
+      let dx = current[2*j].wrapping_sub(last[2*j]) & 3;
+      let dy = current[2*j+1].wrapping_sub(last[2*j+1]) & 3;
+      symbols.push((dx + 3) as u32 + 7 * (dy + 3) as u32);
+    }
+    last.copy_from_slice(&current);
+  }
+  write_palette(&symbols, &[0], &[49])
+}
+
+fn encode_alpha_selectors(entries: &[[u8; 6]]) -> Result<Vec<u8>, Error> {
+  const INV: [u8; 8] = [0, 7, 1, 2, 3, 4, 5, 6]; // inverse of DXT5 order in `get_alpha_selectors`
+  let mut last = [0u8; 16];
+  let mut symbols = vec![];
+  for entry in entries {
+    let bits = entry.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64);
+    let current: Vec<_> = (0..16).map(|t| INV[(bits >> (3 * t) & 7) as usize]).collect();
+    for j in 0..8 {
+      let dx = current[2*j].wrapping_sub(last[2*j]) & 7;
+      let dy = current[2*j+1].wrapping_sub(last[2*j+1]) & 7;
+      symbols.push((dx + 7) as u32 + 15 * (dy + 7) as u32);
+    }
+    last.copy_from_slice(&current);
+  }
+  write_palette(&symbols, &[0], &[225])
+}
+
+/// deltas between consecutive indices as read by `Table::next`
+fn deltas(indices: &[usize], len: usize) -> Vec<u32> {
+  let mut last = 0;
+  indices.iter().map(|&i| {
+    let delta = (i + len - last) % len;
+    last = i;
+    delta as u32
+  }).collect()
+}
+
+/// assemble a complete crn file from palettes and per level symbols, `template` provides
+/// dimensions, format and userdata
+pub(crate) fn write_crn(template: &Header, palettes: &Palettes, levels: &[LevelSymbols]) -> Result<Vec<u8>, Error> {
+  let (endpoint_kinds, selector_kinds) = components(template.format)?;
+  type U = crate::unpack::Dxt1;
+
+  let chunk_symbols: Vec<Vec<u32>> = levels.iter().map(|level| {
+    level.tiles.chunks(3).map(|t| t.iter().rev().fold(0, |acc, &t| acc << 3 | t as u32)).collect()
+  }).collect();
+  let endpoint_deltas: Vec<Vec<Vec<u32>>> = levels.iter().map(|level| {
+    endpoint_kinds.iter().zip(&level.endpoints).map(|(&k, idx)| deltas(idx, palettes.len(k))).collect()
+  }).collect();
+  let selector_deltas: Vec<Vec<Vec<u32>>> = levels.iter().map(|level| {
+    selector_kinds.iter().zip(&level.selectors).map(|(&k, idx)| deltas(idx, palettes.len(k))).collect()
+  }).collect();
+
+  let mut chunk_freqs = vec![0; 512];
+  for s in chunk_symbols.iter().flatten() { chunk_freqs[*s as usize] += 1 }
+  let chunk_encoder = Encoder::from_freqs(&chunk_freqs, Key::MAX_DEPTH)?;
+  let mut delta_freqs: Vec<Vec<u64>> = Kind::ALL.iter().map(|&k| vec![0; palettes.len(k)]).collect();
+  for level in 0..levels.len() {
+    for (&k, d) in endpoint_kinds.iter().zip(&endpoint_deltas[level]).chain(selector_kinds.iter().zip(&selector_deltas[level])) {
+      for &s in d { delta_freqs[k.index()][s as usize] += 1 }
+    }
+  }
+  let delta_encoders = delta_freqs.iter().map(|f| Encoder::from_freqs(f, Key::MAX_DEPTH)).collect::<Result<Vec<_>, Error>>()?;
+
+  let mut table = CodecWriter::new();
+  chunk_encoder.write_table(&mut table)?;
+  for kind in Kind::ALL {
+    if palettes.len(kind) != 0 {
+      delta_encoders[kind.index()].write_table(&mut table)?;
+    }
+  }
+  let table = table.into_bytes();
+  ensure!(table.len() <= u16::MAX as usize, "table too large {}", table.len());
+
+  let level_data = (0..levels.len()).map(|l| {
+    let mut w = CodecWriter::new();
+    let mut endpoint_pos = vec![0; endpoint_kinds.len()];
+    let mut selector_pos = vec![0; selector_kinds.len()];
+    for (chunk, &tile) in levels[l].tiles.iter().enumerate() {
+      if chunk % 3 == 0 {
+        chunk_encoder.write(&mut w, chunk_symbols[l][chunk / 3] as usize)?;
+      }
+      for (c, &kind) in endpoint_kinds.iter().enumerate() {
+        for _ in 0..U::COUNT_TILES[tile as usize] {
+          delta_encoders[kind.index()].write(&mut w, endpoint_deltas[l][c][endpoint_pos[c]] as usize)?;
+          endpoint_pos[c] += 1;
+        }
+      }
+      for _ in 0..U::TRUNK_SIZE * U::TRUNK_SIZE {
+        for (c, &kind) in selector_kinds.iter().enumerate() {
+          delta_encoders[kind.index()].write(&mut w, selector_deltas[l][c][selector_pos[c]] as usize)?;
+          selector_pos[c] += 1;
+        }
+      }
+    }
+    Ok(w.into_bytes())
+  }).collect::<Result<Vec<_>, Error>>()?;
+
+  let palette_data = [
+    if palettes.color_endpoints.is_empty() { vec![] } else { encode_color_endpoints(&palettes.color_endpoints)? },
+    if palettes.color_selectors.is_empty() { vec![] } else { encode_color_selectors(&palettes.color_selectors)? },
+    if palettes.alpha_endpoints.is_empty() { vec![] } else { encode_alpha_endpoints(&palettes.alpha_endpoints)? },
+    if palettes.alpha_selectors.is_empty() { vec![] } else { encode_alpha_selectors(&palettes.alpha_selectors)? },
+  ];
+
+  let mut builder = CrnBuilder { header: template.clone(), table, levels: level_data, ..Default::default() };
+  for (&kind, data) in Kind::ALL.iter().zip(palette_data) { builder.palette(kind, palettes.len(kind) as u16, data); }
+  builder.build()
+}
+
+/// palettes being built, with the index of every entry so repeated entries are shared
+#[derive(Debug, Default)]
+struct PaletteBuilder {
+  palettes: Palettes,
+  color_endpoints: HashMap<(u16, u16), usize>,
+  color_selectors: HashMap<[u8; 4], usize>,
+  alpha_endpoints: HashMap<(u8, u8), usize>,
+  alpha_selectors: HashMap<[u8; 6], usize>,
+}
+
+fn intern<T: Copy + Eq + Hash>(entries: &mut Vec<T>, index: &mut HashMap<T, usize>, value: T) -> usize {
+  *index.entry(value).or_insert_with(|| {
+    entries.push(value);
+    entries.len() - 1
+  })
+}
+
+impl PaletteBuilder {
+  fn color_endpoint(&mut self, value: (u16, u16)) -> usize {
+    intern(&mut self.palettes.color_endpoints, &mut self.color_endpoints, value)
+  }
+  fn color_selector(&mut self, value: [u8; 4]) -> usize {
+    intern(&mut self.palettes.color_selectors, &mut self.color_selectors, value)
+  }
+  fn alpha_endpoint(&mut self, value: (u8, u8)) -> usize {
+    intern(&mut self.palettes.alpha_endpoints, &mut self.alpha_endpoints, value)
+  }
+  fn alpha_selector(&mut self, value: [u8; 6]) -> usize {
+    intern(&mut self.palettes.alpha_selectors, &mut self.alpha_selectors, value)
+  }
+}
+
+/// the inverse of `Unpack::from_indices`
+trait Split: Unpack {
+  /// whether color endpoints with `c0 <= c1` select the 3-color mode with transparent black
+  const PUNCHTHROUGH: bool = false;
+  /// palette indices of the (endpoint, selector) components, in the order of `ENDPOINTS` and `SELECTORS`
+  fn split(&self, palettes: &mut PaletteBuilder) -> ([usize; 2], [usize; 2]);
+  fn parts(&self) -> Parts;
+  fn from_parts(parts: &Parts) -> Self;
+}
+
+impl Split for Dxt1 {
+  const PUNCHTHROUGH: bool = true;
+  fn split(&self, palettes: &mut PaletteBuilder) -> ([usize; 2], [usize; 2]) {
+    ([palettes.color_endpoint(self.color_endpoint), 0], [palettes.color_selector(self.color_selector), 0])
+  }
+  fn parts(&self) -> Parts {
+    Parts { color: Some((self.color_endpoint, self.color_selector)), ..Default::default() }
+  }
+  fn from_parts(parts: &Parts) -> Self {
+    let (color_endpoint, color_selector) = parts.color.unwrap_or_default();
+    Dxt1 { color_endpoint, color_selector }
+  }
+}
+
+impl Split for Dxt5 {
+  fn split(&self, palettes: &mut PaletteBuilder) -> ([usize; 2], [usize; 2]) {
+    let endpoints = [palettes.alpha_endpoint(self.alpha_endpoint), palettes.color_endpoint(self.color_endpoint)];
+    (endpoints, [palettes.alpha_selector(self.alpha_selector), palettes.color_selector(self.color_selector)])
+  }
+  fn parts(&self) -> Parts {
+    Parts { color: Some((self.color_endpoint, self.color_selector)), alpha: [Some((self.alpha_endpoint, self.alpha_selector)), None] }
+  }
+  fn from_parts(parts: &Parts) -> Self {
+    let (color_endpoint, color_selector) = parts.color.unwrap_or_default();
+    let (alpha_endpoint, alpha_selector) = parts.alpha[0].unwrap_or_default();
+    Dxt5 { alpha_endpoint, alpha_selector, color_endpoint, color_selector }
+  }
+}
+
+impl Split for Dxt5A {
+  fn split(&self, palettes: &mut PaletteBuilder) -> ([usize; 2], [usize; 2]) {
+    ([palettes.alpha_endpoint(self.alpha_endpoint), 0], [palettes.alpha_selector(self.alpha_selector), 0])
+  }
+  fn parts(&self) -> Parts {
+    Parts { alpha: [Some((self.alpha_endpoint, self.alpha_selector)), None], ..Default::default() }
+  }
+  fn from_parts(parts: &Parts) -> Self {
+    let (alpha_endpoint, alpha_selector) = parts.alpha[0].unwrap_or_default();
+    Dxt5A { alpha_endpoint, alpha_selector }
+  }
+}
+
+impl Split for Dxn {
+  fn split(&self, palettes: &mut PaletteBuilder) -> ([usize; 2], [usize; 2]) {
+    let endpoints = [palettes.alpha_endpoint(self.alpha0_endpoint), palettes.alpha_endpoint(self.alpha1_endpoint)];
+    (endpoints, [palettes.alpha_selector(self.alpha0_selector), palettes.alpha_selector(self.alpha1_selector)])
+  }
+  fn parts(&self) -> Parts {
+    Parts { alpha: [Some((self.alpha0_endpoint, self.alpha0_selector)), Some((self.alpha1_endpoint, self.alpha1_selector))], ..Default::default() }
+  }
+  fn from_parts(parts: &Parts) -> Self {
+    let (alpha0_endpoint, alpha0_selector) = parts.alpha[0].unwrap_or_default();
+    let (alpha1_endpoint, alpha1_selector) = parts.alpha[1].unwrap_or_default();
+    Dxn { alpha0_endpoint, alpha0_selector, alpha1_endpoint, alpha1_selector }
+  }
+}
+
+/// symbols of a level in the order `Unpack::unpack_rows` reads them
+fn level_symbols<U: Split>(blocks: &[U], width: u16, height: u16, faces: u8, palettes: &mut PaletteBuilder) -> LevelSymbols {
+  let block_x = width.div_ceil(4) as usize;
+  let block_y = height.div_ceil(4) as usize;
+  let chunk_x = block_x.div_ceil(U::TRUNK_SIZE);
+  let chunk_y = block_y.div_ceil(U::TRUNK_SIZE);
+  let (endpoint_count, selector_count) = (U::ENDPOINTS.len(), U::SELECTORS.len());
+  let mut result = LevelSymbols {
+    endpoints: vec![vec![]; endpoint_count],
+    selectors: vec![vec![]; selector_count],
+    ..Default::default()
+  };
+  let mut last_endpoint = [0; 2];
+  let mut last_selector = [0; 2];
+  for f in 0..faces as usize {
+    for y in 0..chunk_y {
+      for step in 0..chunk_x {
+        let x = if y & 1 == 1 { chunk_x - 1 - step } else { step };
+        // the decoder drops chunks on an odd right or bottom edge, repeating the last indices costs the least
+        if (block_x & 1 == 1 && x == chunk_x - 1) || (block_y & 1 == 1 && y == chunk_y - 1) {
+          result.tiles.push(0);
+          for (indices, &e) in result.endpoints.iter_mut().zip(&last_endpoint) { indices.push(e) }
+          for _ in 0..U::TRUNK_SIZE * U::TRUNK_SIZE {
+            for (indices, &s) in result.selectors.iter_mut().zip(&last_selector) { indices.push(s) }
+          }
+          continue
+        }
+        let mut endpoints = [[0; 2]; 4];
+        let mut selectors = [[0; 2]; 4];
+        for i in 0..4 {
+          let pos = (f * block_y + y * U::TRUNK_SIZE + i / U::TRUNK_SIZE) * block_x + x * U::TRUNK_SIZE + i % U::TRUNK_SIZE;
+          let (e, s) = blocks[pos].split(palettes);
+          endpoints[i] = e;
+          selectors[i] = s;
+        }
+        // first block of every tile of a layout, which has to share its endpoints with the rest
+        let first = |layout: &[usize; 4], i: usize| layout.iter().position(|&t| t == layout[i]).unwrap_or(i);
+        let tile = (0..U::TILES.len())
+          .filter(|&t| (0..4).all(|i| endpoints[i] == endpoints[first(&U::TILES[t], i)]))
+          .min_by_key(|&t| U::COUNT_TILES[t]).unwrap_or(U::TILES.len() - 1);
+        let layout = &U::TILES[tile];
+        result.tiles.push(tile as u8);
+        for (c, indices) in result.endpoints.iter_mut().enumerate() {
+          for t in 0..U::COUNT_TILES[tile] {
+            let i = layout.iter().position(|&l| l == t).unwrap_or(0);
+            indices.push(endpoints[i][c]);
+            last_endpoint[c] = endpoints[i][c];
+          }
+        }
+        for s in &selectors {
+          for (c, indices) in result.selectors.iter_mut().enumerate() { indices.push(s[c]) }
+        }
+        last_selector = selectors[3];
+      }
+    }
+  }
+  result
+}
+
+/// How `encode_with` trades size for fidelity, the default keeps every block.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EncodeOptions {
+  /// 0 (smallest) to 255 (exact) like crunch, sets the palette sizes left `None` and how far the
+  /// blocks of a chunk may move to share endpoints; `None` is 255
+  pub quality: Option<u8>,
+  /// search for the highest quality whose file takes at most this many bits per texel of all
+  /// levels, or quality 0 if none does; overrides `quality`
+  pub target_bits_per_texel: Option<f32>,
+  /// the most entries of each palette, `None` keeps every distinct entry up to 65535; blocks
+  /// sharing a clustered entry get their selectors picked again, which makes the output lossy
+  pub color_endpoints: Option<usize>,
+  pub color_selectors: Option<usize>,
+  pub alpha_endpoints: Option<usize>,
+  pub alpha_selectors: Option<usize>,
+}
+
+impl EncodeOptions {
+  /// the same limit for every palette
+  pub fn palette_size(size: usize) -> Self {
+    Self { color_endpoints: Some(size), color_selectors: Some(size), alpha_endpoints: Some(size), alpha_selectors: Some(size), ..Default::default() }
+  }
+  pub fn quality(quality: u8) -> Self {
+    Self { quality: Some(quality), ..Default::default() }
+  }
+  pub fn target_bits_per_texel(bits: f32) -> Self {
+    Self { target_bits_per_texel: Some(bits), ..Default::default() }
+  }
+  /// entry limits indexed by `Kind::index`, from 16 entries at quality 0 to 8192 at 254
+  fn sizes(&self) -> [usize; 4] {
+    let default = match self.quality {
+      Some(q) if q < u8::MAX => (16.0 * 2f32.powf(9.0 * q as f32 / 254.0)).round() as usize,
+      _ => u16::MAX as usize,
+    };
+    let size = |limit: Option<usize>| limit.unwrap_or(default).min(u16::MAX as usize);
+    [size(self.color_endpoints), size(self.color_selectors), size(self.alpha_endpoints), size(self.alpha_selectors)]
+  }
+  /// squared error worth one bit of output when picking tile layouts, 0 keeps every block
+  fn lambda(&self) -> f32 {
+    let t = (u8::MAX - self.quality.unwrap_or(u8::MAX)) as f32 / u8::MAX as f32;
+    1024.0 * t * t
+  }
+}
+
+/// qualities encoded at once while searching for `target_bits_per_texel`
+const QUALITY_PROBES: usize = 3;
+
+/// rough bits of one endpoint index of a tile, the rate term of `select_tiles`
+const ENDPOINT_BITS: f32 = 8.0;
+
+/// How `encode_with_stats` picked the tile layouts of full chunks, before clustering.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EncodeStats {
+  /// squared error worth one bit, from the quality
+  pub lambda: f32,
+  /// how many chunks picked each of the 8 tile layouts of `Unpack::TILES`
+  pub tile_layouts: [usize; 8],
+  /// squared error added by blocks sharing endpoints
+  pub tile_error: u64,
+  /// endpoint indices saved over a tile per block
+  pub saved_endpoints: usize,
+}
+
+impl EncodeStats {
+  fn add(&mut self, other: &Self) {
+    for (a, b) in self.tile_layouts.iter_mut().zip(&other.tile_layouts) { *a += b }
+    self.tile_error += other.tile_error;
+    self.saved_endpoints += other.saved_endpoints;
+  }
+}
+
+/// Pick the tile layout of every full chunk of a level minimizing `error + lambda * bits`, where
+/// the blocks of a tile share the endpoints of the one that fits the rest best and every tile
+/// costs `ENDPOINT_BITS` per endpoint component. Chunks on an odd edge are skipped by the decoder.
+fn select_tiles<U: Split>(parts: &mut [Parts], texels: &[Texels], width: u16, height: u16, faces: u8, lambda: f32) -> EncodeStats {
+  let block_x = width.div_ceil(4) as usize;
+  let block_y = height.div_ceil(4) as usize;
+  let chunks: Vec<[usize; 4]> = (0..faces as usize).flat_map(|f| (0..block_y / U::TRUNK_SIZE).flat_map(move |y| (0..block_x / U::TRUNK_SIZE).map(move |x| {
+    std::array::from_fn(|i| (f * block_y + y * U::TRUNK_SIZE + i / U::TRUNK_SIZE) * block_x + x * U::TRUNK_SIZE + i % U::TRUNK_SIZE)
+  }))).collect();
+  // chunks only read their own blocks, so they are picked independently
+  let picks = par_map(&chunks, |pos| {
+    let mut best: Option<(f32, usize, u32, [Parts; 4])> = None;
+    for (tile, layout) in U::TILES.iter().enumerate() {
+      let mut merged = [Parts::default(); 4];
+      let mut error = 0u32;
+      for t in 0..U::COUNT_TILES[tile] {
+        let members: Vec<usize> = (0..4).filter(|&i| layout[i] == t).collect();
+        let fit = members.iter().filter_map(|&c| {
+          let fits = members.iter().map(|&m| refit(&parts[pos[c]], &texels[pos[m]], U::PUNCHTHROUGH)).collect::<Option<Vec<_>>>()?;
+          Some((fits.iter().map(|f| f.1).sum::<u32>(), fits))
+        }).min_by_key(|f| f.0);
+        let Some((e, fits)) = fit else { error = u32::MAX; break };
+        error = error.saturating_add(e);
+        for (&m, (p, _)) in members.iter().zip(fits) { merged[m] = p }
+      }
+      if error == u32::MAX { continue }
+      let cost = error as f32 + lambda * ENDPOINT_BITS * (U::COUNT_TILES[tile] * U::ENDPOINTS.len()) as f32;
+      if best.as_ref().is_none_or(|b| cost < b.0) { best = Some((cost, tile, error, merged)) }
+    }
+    best
+  });
+  let mut stats = EncodeStats { lambda, ..Default::default() };
+  // a tile per block always fits, its blocks refit against their own texels
+  for (pos, (_, tile, error, merged)) in chunks.iter().zip(picks).filter_map(|(pos, pick)| Some((pos, pick?))) {
+    stats.tile_layouts[tile] += 1;
+    stats.tile_error += error as u64;
+    stats.saved_endpoints += (4 - U::COUNT_TILES[tile]) * U::ENDPOINTS.len();
+    for (&p, m) in pos.iter().zip(merged) { parts[p] = m }
+  }
+  stats
+}
+
+/// palettes and symbols of all levels of `template`
+fn encode_levels<U: Split>(template: &Header, levels: &[&[u8]], options: &EncodeOptions) -> Result<(Palettes, Vec<LevelSymbols>, EncodeStats), Error> {
+  let mut blocks = levels.iter().enumerate().map(|(l, &input)| {
+    let (width, height) = template.get_level_info(l).ok_or(Error::level(l))?;
+    let size = level_size(width, height, template.face_count, U::BLOCK_SIZE)?;
+    ensure!(input.len() == size, "level {} has {} bytes instead of {}", l, input.len(), size);
+    Ok(input.chunks_exact(U::BLOCK_SIZE).map(U::read_bytes).collect::<Vec<_>>())
+  }).collect::<Result<Vec<_>, Error>>()?;
+  let mut parts: Vec<Parts> = blocks.iter().flatten().map(U::parts).collect();
+  let texels = par_map(&parts, |p| Texels::new(p, U::PUNCHTHROUGH));
+  let mut stats = EncodeStats { lambda: options.lambda(), ..Default::default() };
+  if stats.lambda > 0.0 {
+    let mut start = 0;
+    for (l, blocks) in blocks.iter().enumerate() {
+      let (width, height) = template.get_level_info(l).unwrap_or_default();
+      let range = start..start + blocks.len();
+      stats.add(&select_tiles::<U>(&mut parts[range.clone()], &texels[range], width, height, template.face_count, stats.lambda));
+      start += blocks.len();
+    }
+  }
+  crate::vq::quantize(&mut parts, &texels, U::PUNCHTHROUGH, options.sizes());
+  for (block, parts) in blocks.iter_mut().flatten().zip(&parts) { *block = U::from_parts(parts) }
+
+  let mut builder = PaletteBuilder::default();
+  let symbols = blocks.iter().enumerate().map(|(l, blocks)| {
+    let (width, height) = template.get_level_info(l).unwrap_or_default();
+    level_symbols(blocks, width, height, template.face_count, &mut builder)
+  }).collect();
+  Ok((builder.palettes, symbols, stats))
+}
+
+/// Encode the BCn blocks of a texture into a crn file. `levels[l]` holds level `l` the way
+/// `Header::unpack_level` returns it, faces one after another with rows of blocks top to bottom.
+/// Palettes keep every distinct entry so the blocks decode unchanged, except for chunks on an
+/// odd right or bottom edge of a level, which the decoder skips.
+pub fn encode(format: Format, width: u16, height: u16, faces: u8, levels: &[&[u8]]) -> Result<Vec<u8>, Error> {
+  encode_with(format, width, height, faces, levels, &EncodeOptions::default())
+}
+
+/// `encode` trading size for fidelity as `options` asks
+pub fn encode_with(format: Format, width: u16, height: u16, faces: u8, levels: &[&[u8]], options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+  Ok(encode_with_stats(format, width, height, faces, levels, options)?.0)
+}
+
+/// `encode_with` also returning how the tile layouts were picked, of the quality searched for
+/// with `target_bits_per_texel`
+pub fn encode_with_stats(format: Format, width: u16, height: u16, faces: u8, levels: &[&[u8]], options: &EncodeOptions) -> Result<(Vec<u8>, EncodeStats), Error> {
+  let Some(target) = options.target_bits_per_texel else { return encode_quality(format, width, height, faces, levels, options) };
+  let template = Header { width, height, level_count: levels.len().min(u8::MAX as usize) as u8, face_count: faces, ..Default::default() };
+  let texels = (0..levels.len()).filter_map(|l| template.get_level_info(l))
+    .map(|(w, h)| w as f32 * h as f32 * faces as f32).sum::<f32>();
+  // the size shrinks with the quality, so search the highest one that fits, trying a few at once;
+  // the qualities tried don't depend on the number of threads
+  let fits = |output: &(Vec<u8>, EncodeStats)| output.0.len() as f32 * 8.0 <= target * texels;
+  let (mut low, mut high, mut best) = (0, u8::MAX as usize + 1, None);
+  while low < high {
+    let probes: Vec<usize> = if high - low <= QUALITY_PROBES { (low..high).collect() }
+      else { (1..=QUALITY_PROBES).map(|i| low + (high - low) * i / (QUALITY_PROBES + 1)).collect() };
+    let mut outputs = par_map(&probes, |&quality| {
+      let options = EncodeOptions { quality: Some(quality as u8), target_bits_per_texel: None, ..*options };
+      encode_quality(format, width, height, faces, levels, &options)
+    }).into_iter().collect::<Result<Vec<_>, Error>>()?;
+    let count = outputs.iter().take_while(|&output| fits(output)).count();
+    if count < probes.len() { high = probes[count] }
+    if count > 0 {
+      low = probes[count - 1] + 1;
+      best = Some(outputs.swap_remove(count - 1));
+    } else if probes[0] == 0 {
+      // nothing fits, quality 0 is the smallest
+      return Ok(outputs.swap_remove(0))
+    }
+  }
+  best.ok_or_else(|| format_err!("no quality to search"))
+}
+
+fn encode_quality(format: Format, width: u16, height: u16, faces: u8, levels: &[&[u8]], options: &EncodeOptions) -> Result<(Vec<u8>, EncodeStats), Error> {
+  let (endpoint_kinds, selector_kinds) = components(format)?;
+  ensure!(width != 0 && height != 0 && faces != 0, "empty texture {}x{} with {} faces", width, height, faces);
+  ensure!(!levels.is_empty() && levels.len() <= u8::MAX as usize, "level count {}", levels.len());
+  let template = Header { width, height, level_count: levels.len() as u8, face_count: faces, format, ..Default::default() };
+  let (mut palettes, symbols, stats) = dispatch!(format, B => encode_levels::<B>(&template, levels, options)?);
+  // skipped chunks refer to entry 0, which has to exist even when no block was kept
+  for &kind in endpoint_kinds.iter().chain(selector_kinds) {
+    if palettes.len(kind) != 0 { continue }
+    match kind {
+      Kind::ColorEndpoint => palettes.color_endpoints.push(Default::default()),
+      Kind::ColorSelector => palettes.color_selectors.push(Default::default()),
+      Kind::AlphaEndpoint => palettes.alpha_endpoints.push(Default::default()),
+      Kind::AlphaSelector => palettes.alpha_selectors.push(Default::default()),
+    }
+  }
+  Ok((write_crn(&template, &palettes, &symbols)?, stats))
+}
+
+/// a block decoding to other bytes than it was encoded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDifference {
+  pub level: usize,
+  /// index in the level as `Header::unpack_level` returns it, faces one after another
+  pub block: usize,
+  /// squared error over the RGBA texels of the block
+  pub squared_error: u64,
+}
+
+/// How the blocks of a crn file compare to the ones it was encoded from, see [`verify_roundtrip`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RoundtripReport {
+  /// blocks compared over all levels
+  pub blocks: usize,
+  /// blocks on an odd right or bottom edge, which the decoder skips and aren't compared
+  pub skipped: usize,
+  pub differences: Vec<BlockDifference>,
+  /// squared error over the RGBA texels of all blocks compared
+  pub squared_error: u64,
+}
+
+impl RoundtripReport {
+  pub fn is_lossless(&self) -> bool {
+    self.differences.is_empty()
+  }
+
+  /// peak signal to noise ratio over the RGBA texels in dB, infinite when nothing changed
+  pub fn psnr(&self) -> f64 {
+    if self.squared_error == 0 { return f64::INFINITY }
+    let mse = self.squared_error as f64 / (self.blocks * 16 * 4) as f64;
+    10.0 * (255.0 * 255.0 / mse).log10()
+  }
+}
+
+/// Decode `crn` and compare every level to `original`, the blocks it was encoded from in the
+/// layout `encode` takes.
+pub fn verify_roundtrip(original: &[&[u8]], crn: &[u8]) -> Result<RoundtripReport, Error> {
+  let header = Header::parse(crn)?;
+  let tables = header.get_table(crn)?;
+  ensure!(original.len() == header.level_count as usize, "{} levels instead of {}", original.len(), header.level_count);
+  let block_size = header.format.block_size();
+  let mut report = RoundtripReport::default();
+  let (mut decoded, mut expected) = ([[0; 4]; 16], [[0; 4]; 16]);
+  for (l, &original) in original.iter().enumerate() {
+    let level = header.unpack_level(&tables, crn, l)?;
+    ensure!(original.len() == level.len(), "level {} has {} bytes instead of {}", l, original.len(), level.len());
+    let (width, height) = header.get_level_info(l).ok_or(Error::level(l))?;
+    let (block_x, block_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+    for (b, (a, e)) in level.chunks_exact(block_size).zip(original.chunks_exact(block_size)).enumerate() {
+      let (x, y) = (b % block_x, b / block_x % block_y);
+      if (block_x & 1 == 1 && x == block_x - 1) || (block_y & 1 == 1 && y == block_y - 1) {
+        report.skipped += 1;
+        continue
+      }
+      report.blocks += 1;
+      if a == e { continue }
+      crate::rgba::decode_block(header.format, a, &mut decoded)?;
+      crate::rgba::decode_block(header.format, e, &mut expected)?;
+      let squared_error = decoded.iter().flatten().zip(expected.iter().flatten()).map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64).sum();
+      report.squared_error += squared_error;
+      report.differences.push(BlockDifference { level: l, block: b, squared_error });
+    }
+  }
+  Ok(report)
+}
+
+#[test]
+fn test_encode() {
+  let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+  let mut next = move |n: u64| { seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17; seed % n };
+  // blocks drawing from a few endpoints, so chunks end up with every layout
+  let mut blocks = |format: Format, count: usize| -> Vec<u8> {
+    let mut out = vec![0; count * format.block_size()];
+    for block in out.chunks_mut(format.block_size()) {
+      let (a, c) = (next(3) as u8, next(3) as u16);
+      let (alpha, color) = (((a * 80, 255 - a), [a; 6]), ((c * 0x1234, !c), [c as u8 * 0x55; 4]));
+      match format {
+        Format::Dxt1 => Dxt1 { color_endpoint: color.0, color_selector: color.1 }.write_bytes(block),
+        Format::Dxt5A => Dxt5A { alpha_endpoint: alpha.0, alpha_selector: alpha.1 }.write_bytes(block),
+        Format::DxnXY => Dxn { alpha0_endpoint: alpha.0, alpha0_selector: alpha.1, alpha1_endpoint: (c as u8, 7), alpha1_selector: [1; 6] }.write_bytes(block),
+        _ => Dxt5 { alpha_endpoint: alpha.0, alpha_selector: alpha.1, color_endpoint: color.0, color_selector: color.1 }.write_bytes(block),
+      }
+    }
+    out
+  };
+  use crate::unpack::Block;
+  for (format, faces) in [(Format::Dxt1, 1), (Format::Dxt5, 2), (Format::Dxt5A, 1), (Format::DxnXY, 6)] {
+    // 4x4, 2x2 and 1x1 blocks, the last level is all skipped
+    let levels = [blocks(format, 16 * faces), blocks(format, 4 * faces), blocks(format, faces)];
+    let input = encode(format, 16, 16, faces as u8, &levels.iter().map(|l| &l[..]).collect::<Vec<_>>()).expect("encode");
+    let header = Header::parse(&input).expect("parse");
+    assert!(header.check_crc(&input));
+    assert_eq!((header.width, header.height, header.level_count, header.face_count, header.format), (16, 16, 3, faces as u8, format));
+    let tables = header.get_table(&input).expect("read table");
+    let report = verify_roundtrip(&levels.iter().map(|l| &l[..]).collect::<Vec<_>>(), &input).expect("verify");
+    assert!(report.is_lossless() && report.psnr().is_infinite(), "{:?} {:?}", format, report);
+    assert_eq!((report.blocks, report.skipped), (20 * faces, faces));
+    assert_eq!(header.unpack_level(&tables, &input, 2).unwrap(), vec![0; levels[2].len()]);
+    let stats = crate::stats::DecodeStats::collect(&header, &tables, &input).expect("stats");
+    assert!(stats.tile_layouts().iter().filter(|&&n| n != 0).count() > 2, "{:?}", stats.tile_layouts());
+  }
+
+  let solid = Dxt1::solid([255, 0, 0, 255]);
+  let mut level = vec![0; 4 * 8];
+  for block in level.chunks_mut(8) { solid.write_bytes(block) }
+  let input = encode(Format::Dxt1, 8, 8, 1, &[&level]).expect("encode solid");
+  let header = Header::parse(&input).unwrap();
+  let tables = header.get_table(&input).unwrap();
+  assert_eq!(tables.color_endpoint.as_ref().unwrap().entries, vec![solid.color_endpoint]);
+  let stats = crate::stats::DecodeStats::collect(&header, &tables, &input).unwrap();
+  assert_eq!(stats.tile_layouts(), [1, 0, 0, 0, 0, 0, 0, 0]);
+
+  assert!(encode(Format::Dxt1, 8, 8, 1, &[&level[8..]]).is_err());
+  assert!(encode(Format::Dxt1, 8, 8, 1, &[]).is_err());
+  assert!(matches!(encode(Format::Dxt3, 8, 8, 1, &[&level]), Err(Error::UnsupportedFormat { .. })));
+
+  // clustering a 32x32 picture into smaller palettes
+  use crate::{compress::{Fit, compress_image}, rgba::decode_image};
+  let image: Vec<u8> = (0..32 * 32u32).flat_map(|i| {
+    let (x, y) = (i % 32, i / 32);
+    [(x * 8) as u8, (y * 8) as u8, ((x ^ y) * 8) as u8, if (x / 8 + y / 8) % 2 == 0 { 255 } else { (x * y % 256) as u8 }]
+  }).collect();
+  let error = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(&a, &b)| (a as i64 - b as i64).pow(2)).sum::<i64>();
+  for format in [Format::Dxt1, Format::Dxt5, Format::DxnXY] {
+    let blocks = compress_image(format, &image, 32, 32, Fit::Range).unwrap();
+    let errors = [64, 16, 4].map(|size| {
+      let input = encode_with(format, 32, 32, 1, &[&blocks], &EncodeOptions::palette_size(size)).expect("encode");
+      let header = Header::parse(&input).unwrap();
+      // one more entry is allowed per mode
+      for palette in [header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors] {
+        assert!(palette.count as usize <= size + 1, "{:?} {} {:?}", format, size, palette);
+      }
+      verify_roundtrip(&[&blocks], &input).expect("verify").squared_error
+    });
+    // 64 blocks fit in 64 entries
+    assert!(errors[0] == 0 && errors[1] <= errors[2] && errors[2] > 0, "{:?} {:?}", format, errors);
+  }
+  // lower quality makes smaller files, a target size picks the highest quality that fits
+  for format in [Format::Dxt1, Format::Dxt5] {
+    let blocks = compress_image(format, &image, 32, 32, Fit::Range).unwrap();
+    let reference = decode_image(format, &blocks, 32, 32).unwrap();
+    let run = |options: EncodeOptions| {
+      let input = encode_with(format, 32, 32, 1, &[&blocks], &options).expect("encode");
+      let header = Header::parse(&input).unwrap();
+      let level = header.unpack_level(&header.get_table(&input).unwrap(), &input, 0).unwrap();
+      (input.len(), error(&decode_image(format, &level, 32, 32).unwrap(), &reference))
+    };
+    let [low, middle, high] = [0, 128, 255].map(|q| run(EncodeOptions::quality(q)));
+    assert!(high.1 == 0 && low.0 < middle.0 && middle.0 <= high.0 && low.1 > 0, "{:?} {:?}", format, [low, middle, high]);
+    let target = middle.0 as f32 * 8.0 / 1024.0;
+    let fit = run(EncodeOptions::target_bits_per_texel(target));
+    assert!(fit.0 <= middle.0 && fit.0 >= low.0, "{:?} {:?} {:?}", format, fit, middle);
+    assert_eq!(run(EncodeOptions::target_bits_per_texel(0.0)), low);
+    // every full chunk got a layout, more endpoints get shared at lower quality
+    let stats = [0, 128, 255].map(|q| encode_with_stats(format, 32, 32, 1, &[&blocks], &EncodeOptions::quality(q)).expect("encode").1);
+    assert_eq!(stats[0].tile_layouts.iter().sum::<usize>(), 16);
+    assert!(stats[0].saved_endpoints >= stats[1].saved_endpoints && stats[0].tile_error > 0, "{:?}", stats);
+    assert_eq!(stats[2], EncodeStats::default());
+  }
+  let input = encode_with(Format::Dxt1, 8, 8, 1, &[&level], &EncodeOptions::palette_size(1)).expect("encode one entry");
+  let header = Header::parse(&input).unwrap();
+  assert_eq!(header.unpack_level(&header.get_table(&input).unwrap(), &input, 0).unwrap(), level);
+}
+
+#[test]
+fn test_code_lengths() {
+  use crate::codec::{Codec, decode_code_lengths};
+  let mut seed = 0x2545_f491_4f6c_dd1du64;
+  let mut next = move |n: u64| { seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17; seed % n };
+  let mut cases = vec![vec![3], vec![0, 0, 0, 1], vec![2; 200], [vec![0; 150], vec![1, 1]].concat()];
+  // many short zero runs and a single one of 12, cheaper as two short runs than a long one
+  cases.push((0..40).flat_map(|i| [vec![0; if i == 20 { 12 } else { 3 + i % 5 }], vec![(1 + i % 7) as u8]].concat()).collect());
+  // runs of random lengths and depths, zeros more often
+  for _ in 0..20 {
+    let mut lengths = vec![];
+    while lengths.len() < 300 {
+      let d = if next(2) == 0 { 0 } else { next(17) as u8 };
+      lengths.extend(std::iter::repeat_n(d, 1 + next(20) as usize));
+    }
+    cases.push(lengths);
+  }
+  for (i, lengths) in cases.into_iter().enumerate() {
+    let mut w = CodecWriter::new();
+    write_code_lengths(&mut w, &lengths).unwrap();
+    let (bits, greedy) = (w.current(), key_code(&greedy_keys(&lengths)).unwrap().1);
+    assert!(bits < greedy || (bits == greedy && i != 4), "{} {} {:?}", bits, greedy, lengths);
+    let bytes = w.into_bytes();
+    assert_eq!(decode_code_lengths(&mut Codec::new(&bytes), lengths.len()).unwrap(), lengths);
+  }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_encode_threads() {
+  use crate::compress::{Fit, compress_image};
+  let image: Vec<u8> = (0..64 * 32u32).flat_map(|i| {
+    let (x, y) = (i % 64, i / 64);
+    [(x * 4) as u8, (y * 8) as u8, ((x ^ y) * 8) as u8, (x * y % 256) as u8]
+  }).collect();
+  let run = |threads: usize| rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap().install(|| {
+    [Format::Dxt1, Format::Dxt5].map(|format| {
+      let blocks = compress_image(format, &image, 64, 32, Fit::Cluster).unwrap();
+      let options = [EncodeOptions::quality(100), EncodeOptions::target_bits_per_texel(2.0)];
+      (options.map(|o| encode_with_stats(format, 64, 32, 1, &[&blocks], &o).unwrap()), blocks)
+    })
+  });
+  assert_eq!(run(1), run(4));
+}
@@ -0,0 +1,164 @@
+//! Typed errors of the crate. Everything returns [`CrnError`], which could be matched on by kind;
+//! with the `anyhow` feature an `anyhow::Error` (e.g. from a progress callback) converts into it.
+
+use std::fmt::Display;
+use crate::{Format, codec::{BudgetExceeded, HuffmanError}};
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CrnError {
+  #[error("invalid magic {0:02x?}")]
+  InvalidMagic([u8; 2]),
+  #[error("crc mismatch")]
+  CrcMismatch,
+  #[error("file size {expected} doesn't match input of {actual} bytes")]
+  SizeMismatch { expected: usize, actual: usize },
+  #[error("unknown flags {0:#x}")]
+  UnknownFlags(u16),
+  #[error("malformed header: {0}")]
+  Header(String),
+  /// boxed as `HuffmanError::Incomplete` is large
+  #[error(transparent)]
+  Huffman(Box<HuffmanError>),
+  #[error(transparent)]
+  Budget(#[from] BudgetExceeded),
+  #[error("{what} {index} out of bounds")]
+  OutOfBounds { what: &'static str, index: usize },
+  /// a region the header points at doesn't fit in the input
+  #[error("{what} at {start}..{end} out of input of {len} bytes")]
+  OutOfInput { what: String, start: usize, end: usize, len: usize },
+  #[error("arithmetic overflow computing {0}")]
+  Overflow(&'static str),
+  /// see `DecodeLimits`
+  #[error("{what} {value} exceeds limit {max}")]
+  LimitExceeded { what: &'static str, value: usize, max: usize },
+  #[error("unsupported format {format:?}")]
+  UnsupportedFormat { format: Format },
+  #[error("extra bytes in codec")]
+  TrailingData,
+  /// returned by callbacks to abort a decode
+  #[error("cancelled")]
+  Cancelled,
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+  /// anything without a kind of its own
+  #[error("{0}")]
+  Invalid(String),
+  #[cfg(feature = "anyhow")]
+  #[error(transparent)]
+  Other(#[from] anyhow::Error),
+  #[error("{context}")]
+  Context { context: String, #[source] source: Box<CrnError> },
+  /// a context with the bit offset into the file where decoding stopped
+  #[error("{context} at bit {bit} (byte {})", bit / 8)]
+  At { context: String, bit: usize, #[source] source: Box<CrnError> },
+}
+
+impl CrnError {
+  /// the error without the contexts wrapped around it
+  pub fn root(&self) -> &CrnError {
+    match self {
+      CrnError::Context { source, .. } | CrnError::At { source, .. } => source.root(),
+      e => e,
+    }
+  }
+
+  /// the outermost bit offset recorded by `At`
+  pub fn bit_position(&self) -> Option<usize> {
+    match self {
+      CrnError::At { bit, .. } => Some(*bit),
+      CrnError::Context { source, .. } => source.bit_position(),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn at<C: Display>(self, context: C, bit: usize) -> Self {
+    CrnError::At { context: context.to_string(), bit, source: Box::new(self) }
+  }
+
+  pub fn huffman(&self) -> Option<&HuffmanError> {
+    match self.root() {
+      CrnError::Huffman(e) => Some(e),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn level(index: usize) -> Self {
+    CrnError::OutOfBounds { what: "level", index }
+  }
+}
+
+impl From<HuffmanError> for CrnError {
+  fn from(e: HuffmanError) -> Self {
+    CrnError::Huffman(Box::new(e))
+  }
+}
+
+/// `anyhow::Context` for `CrnError`
+pub(crate) trait Context<T> {
+  fn context<C: Display>(self, context: C) -> Result<T, CrnError>;
+  fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T, CrnError>;
+}
+
+impl<T, E: Into<CrnError>> Context<T> for Result<T, E> {
+  fn context<C: Display>(self, context: C) -> Result<T, CrnError> {
+    self.map_err(|e| CrnError::Context { context: context.to_string(), source: Box::new(e.into()) })
+  }
+  fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T, CrnError> {
+    self.map_err(|e| CrnError::Context { context: f().to_string(), source: Box::new(e.into()) })
+  }
+}
+
+impl<T> Context<T> for Option<T> {
+  fn context<C: Display>(self, context: C) -> Result<T, CrnError> {
+    self.ok_or_else(|| CrnError::Invalid(context.to_string()))
+  }
+  fn with_context<C: Display, F: FnOnce() -> C>(self, f: F) -> Result<T, CrnError> {
+    self.ok_or_else(|| CrnError::Invalid(f().to_string()))
+  }
+}
+
+/// `CrnError::Invalid` from a format string
+macro_rules! format_err {
+  ($($arg:tt)*) => { $crate::error::CrnError::Invalid(format!($($arg)*)) };
+}
+
+macro_rules! bail {
+  ($msg:literal $(, $arg:expr)* $(,)?) => { return Err($crate::error::format_err!($msg $(, $arg)*)) };
+  ($err:expr) => { return Err($err.into()) };
+}
+
+macro_rules! ensure {
+  ($cond:expr, $($arg:tt)*) => { if !$cond { $crate::error::bail!($($arg)*) } };
+}
+
+pub(crate) use {bail, ensure, format_err};
+
+#[test]
+fn test_error() {
+  let error = Err::<(), _>(HuffmanError::Incomplete { depth_count: [0; 17] }).context("read table").unwrap_err();
+  assert_eq!(error.to_string(), "read table");
+  assert!(matches!(error.huffman(), Some(HuffmanError::Incomplete { .. })));
+  assert!(std::error::Error::source(&error).is_some());
+  let error = (|| -> Result<(), CrnError> { bail!("value {} too large", 3) })().unwrap_err();
+  assert!(matches!(error, CrnError::Invalid(ref s) if s == "value 3 too large"));
+  assert!(matches!(None::<()>.context("missing").unwrap_err(), CrnError::Invalid(_)));
+  let error = Err::<(), _>(CrnError::Cancelled.at("read table", 17)).context("decode").unwrap_err();
+  assert_eq!(error.bit_position(), Some(17));
+  assert_eq!(std::error::Error::source(&error).unwrap().to_string(), "read table at bit 17 (byte 2)");
+  assert!(matches!(error.root(), CrnError::Cancelled));
+  let (input, ..) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  assert!(matches!(crate::Header::parse(&input[1..]), Err(CrnError::InvalidMagic(_))));
+}
+
+#[test]
+fn test_error_position() {
+  let (input, header, _) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  // more entries than the palette holds, decoding runs out of its bits
+  let mut overrun = header.clone();
+  overrun.color_endpoints.count += 100;
+  let error = overrun.get_color_endpoints(&input).unwrap_err();
+  assert!(matches!(&error, CrnError::At { context, .. } if context.starts_with("color_endpoints entry ")), "{}", error);
+  let bit = error.bit_position().unwrap();
+  assert!((header.color_endpoints.offset as usize * 8..=(header.color_endpoints.offset + header.color_endpoints.size) as usize * 8).contains(&bit));
+}
@@ -0,0 +1,87 @@
+//! Memory-mapped crn files, so large collections are decoded without reading them into memory.
+
+use std::path::Path;
+use crate::error::{Context, CrnError as Error};
+use crate::{DecodeContext, DecodeLimits, Header, ParseOptions, Tables};
+
+/// A crn file mapped into memory with its `Header` and `Tables`, pages are only read as levels
+/// are unpacked.
+///
+/// The mapping assumes the file is left untouched while it's open, as is usual for assets;
+/// truncating it meanwhile makes reads of the lost pages fault.
+pub struct CrnFile {
+  map: memmap2::Mmap,
+  header: Header,
+  tables: Tables,
+}
+
+impl std::fmt::Debug for CrnFile {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CrnFile")
+      .field("len", &self.map.len())
+      .field("header", &self.header)
+      .field("tables", &self.tables)
+      .finish()
+  }
+}
+
+impl CrnFile {
+  /// map `path` and parse its header and tables, headers beyond the default `DecodeLimits` are rejected
+  pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+    Self::open_with(path, &ParseOptions::default(), &DecodeLimits::default())
+  }
+
+  pub fn open_with(path: impl AsRef<Path>, options: &ParseOptions, limits: &DecodeLimits) -> Result<Self, Error> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    // safety: see the type level doc, mapped assets aren't expected to change under us
+    let map = unsafe { memmap2::Mmap::map(&file) }.with_context(|| format!("map {}", path.display()))?;
+    let header = Header::parse_with(&map, options).context("parse header")?;
+    header.check_limits(limits)?;
+    let tables = header.get_table(&map).context("read table")?;
+    Ok(Self { map, header, tables })
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  pub fn tables(&self) -> &Tables {
+    &self.tables
+  }
+
+  /// the mapped bytes of the whole file
+  pub fn input(&self) -> &[u8] {
+    &self.map
+  }
+
+  pub fn level_count(&self) -> usize {
+    self.header.level_count as usize
+  }
+
+  /// unpack level `idx` into BCn blocks, see [`Header::unpack_level`]
+  pub fn unpack_level(&self, idx: usize) -> Result<Vec<u8>, Error> {
+    self.header.unpack_level(&self.tables, &self.map, idx)
+  }
+
+  /// unpack level `idx` into the buffers of `context`, see [`Header::unpack_level_in`]
+  pub fn unpack_level_in<'c>(&self, idx: usize, context: &'c mut DecodeContext) -> Result<&'c [u8], Error> {
+    self.header.unpack_level_in(&self.tables, &self.map, idx, context)
+  }
+}
+
+#[test]
+fn test_mmap() {
+  let (input, header, tables) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  let path = std::env::temp_dir().join(format!("crnlib-test-mmap-{}.crn", std::process::id()));
+  std::fs::write(&path, &input).unwrap();
+  let file = CrnFile::open(&path).expect("open");
+  assert_eq!(file.input(), &input[..]);
+  assert_eq!(file.level_count(), 2);
+  assert_eq!(file.unpack_level(1).unwrap(), header.unpack_level(&tables, &input, 1).unwrap());
+  let mut context = DecodeContext::new();
+  assert_eq!(file.unpack_level_in(0, &mut context).unwrap(), &file.unpack_level(0).unwrap()[..]);
+  drop(file);
+  std::fs::remove_file(&path).unwrap();
+  assert!(matches!(CrnFile::open(&path).unwrap_err().root(), Error::Io(_)));
+}
@@ -0,0 +1,109 @@
+//! Entry points for fuzzers, decoding must return errors but never panic whatever the input is.
+
+use crate::{DecodeLimits, Header, ParseOptions, Tables, unpack::{Fill, Rect}};
+
+/// parse `input` leniently and run the checks that don't decode anything
+pub fn fuzz_parse(input: &[u8]) -> Option<Header> {
+  let header = Header::parse_with(input, &ParseOptions::lenient()).ok()?;
+  let _ = Header::parse_with(input, &ParseOptions::default());
+  let _ = header.validate(input);
+  header.check_limits(&DecodeLimits::default()).ok()?;
+  Some(header)
+}
+
+/// `fuzz_parse` then read the huffman tables and palettes
+pub fn fuzz_tables(input: &[u8]) -> Option<(Header, Tables)> {
+  let header = fuzz_parse(input)?;
+  let tables = header.get_table(input).ok()?;
+  Some((header, tables))
+}
+
+/// parse `input` leniently and run every decoder on it, errors are ignored
+pub fn fuzz_decode(input: &[u8]) {
+  let (header, tables) = match fuzz_tables(input) { Some(result) => result, None => return };
+  for l in 0..header.level_count as usize {
+    let _ = header.unpack_level(&tables, input, l);
+    let _ = header.unpack_level_lenient(&tables, input, l, &Fill::Zero);
+    let _ = header.unpack_level_region(&tables, input, l, 0, Rect { x: 0, y: 0, width: 4, height: 4 });
+  }
+  let _ = crate::stats::DecodeStats::collect(&header, &tables, input);
+}
+
+/// seeds for the targets under `fuzz/` derived from a valid `sample`, as `(target, name, input)`
+pub fn seed_corpus(sample: &[u8]) -> Vec<(&'static str, String, Vec<u8>)> {
+  let mut seeds = vec![];
+  let header_size = Header::parse(sample).map(|header| header.header_size as usize).unwrap_or(0);
+  if let Some(header) = sample.get(..header_size) {
+    seeds.push(("parse_header", "header".to_string(), header.to_vec()));
+  }
+  let mut inputs = vec![("sample".to_string(), sample.to_vec())];
+  if let Ok(compacted) = crate::compact::compact_palettes(sample) {
+    inputs.push(("compacted".to_string(), compacted.data));
+  }
+  for (name, input) in inputs {
+    for target in ["parse_header", "decode_tables", "unpack"] {
+      seeds.push((target, name.clone(), input.clone()));
+    }
+  }
+  seeds
+}
+
+/// `fuzz_decode` a file made of an arbitrary `header` followed by `data`, magic and sizes are fixed
+/// up so fuzzers don't spend their time finding a parsable header
+#[cfg(feature = "arbitrary")]
+pub fn fuzz_header(header: &Header, data: &[u8]) {
+  let mut header = header.clone();
+  header.level_offset.truncate(u8::MAX as usize);
+  header.magic = *b"Hx";
+  header.level_count = header.level_offset.len() as u8;
+  header.header_size = (Header::fixed_size() + 4 * header.level_offset.len()) as u16;
+  header.file_size = (header.header_size as usize + data.len()) as u32;
+  let mut input = header.to_bytes();
+  input.extend_from_slice(data);
+  fuzz_decode(&input);
+}
+
+#[test]
+fn test_no_panic() {
+  use crate::{Format, testgen::TestCrn};
+  // all four palettes, small enough to flip every bit
+  let input = TestCrn { format: Format::Dxt5, palette_size: 2, ..TestCrn::sample() }.build().unwrap();
+  let seeds = seed_corpus(&input);
+  assert_eq!(seeds.len(), 7);
+  assert!(seeds.iter().filter(|(target, ..)| *target == "unpack").all(|(_, _, seed)| fuzz_tables(seed).is_some()));
+  fuzz_decode(&input);
+  for len in 0..input.len() {
+    fuzz_decode(&input[..len]);
+  }
+  // regressions: offsets past the input, more levels than bits in the width
+  let mut header = Header::parse(&input).unwrap();
+  for (table_offset, level_count) in [(1 << 20, 2), (input.len() as u32, 2), (header.table_offset, 40)] {
+    header.table_offset = table_offset;
+    header.level_count = level_count;
+    let mut damaged = input.clone();
+    let bytes = header.to_bytes();
+    damaged[..bytes.len()].copy_from_slice(&bytes);
+    fuzz_decode(&damaged);
+  }
+  let mut damaged = input.clone();
+  for i in 0..input.len() {
+    for mask in [0x01, 0x10, 0x80, 0xff] {
+      damaged[i] ^= mask;
+      fuzz_decode(&damaged);
+      damaged[i] = input[i];
+    }
+  }
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn test_fuzz_header() {
+  use arbitrary::{Arbitrary, Unstructured};
+  let bytes = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<_>>();
+  for start in (0..bytes.len()).step_by(97) {
+    let mut u = Unstructured::new(&bytes[start..]);
+    if let Ok(header) = Header::arbitrary(&mut u) {
+      fuzz_header(&header, u.take_rest());
+    }
+  }
+}
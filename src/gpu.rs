@@ -0,0 +1,281 @@
+//! What a GPU upload of a crn texture needs: the compressed texture format, the size of every
+//! mip level and where its blocks go in a staging buffer.
+//!
+//! The names follow `wgpu::TextureFormat` so the mapping is one to one; a texture is created with
+//! `size` and `mip_level_count`, then every `LevelUpload` is one `Queue::write_texture` of
+//! `data[range]` with `bytes_per_row` and `rows_per_image` at `mip_level`. Faces are array layers.
+//! With the `wgpu` feature the formats convert into `wgpu::TextureFormat`,
+//! `TextureUpload::into_descriptor` is the `TextureDescriptor` and every `LevelUpload` converts
+//! into the `ImageDataLayout` and `Extent3d` of its copy.
+//!
+//! Engine loaders pick `TextureUpload::new` when the device has the compressed format and
+//! `TextureUpload::rgba` otherwise, the rest of the upload stays the same; with the `bevy` feature
+//! `bevy::CrnLoader` does so for Bevy.
+
+use std::ops::Range;
+use crate::error::{CrnError as Error, bail};
+use crate::{Format, Header, Tables, unpack::BlockKind};
+
+/// wgpu's `COPY_BYTES_PER_ROW_ALIGNMENT`, for copies from a buffer rather than `write_texture`
+pub const COPY_ROW_ALIGNMENT: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+  Bc1RgbaUnorm, Bc1RgbaUnormSrgb,
+  Bc2RgbaUnorm, Bc2RgbaUnormSrgb,
+  Bc3RgbaUnorm, Bc3RgbaUnormSrgb,
+  Bc4RUnorm,
+  Bc5RgUnorm,
+  Etc2Rgb8Unorm, Etc2Rgb8UnormSrgb,
+  /// the blocks expanded, for devices without the compressed formats
+  Rgba8Unorm, Rgba8UnormSrgb,
+}
+
+impl TextureFormat {
+  /// The format the blocks of `format` upload as. The swizzled DXT5 variants and `DxnYX` keep
+  /// their channels where crunch put them, shaders have to swap them back.
+  pub fn of(format: Format, srgb: bool) -> Result<Self, Error> {
+    use TextureFormat::*;
+    if format == Format::Etc1 {
+      return Ok(if srgb { Etc2Rgb8UnormSrgb } else { Etc2Rgb8Unorm });
+    }
+    let kind = BlockKind::of(format)?;
+    // the swizzled DXT5 variants don't hold colors
+    let srgb = srgb && (kind != BlockKind::Dxt5 || format == Format::Dxt5);
+    Ok(match (kind, srgb) {
+      (BlockKind::Dxt1, false) => Bc1RgbaUnorm,
+      (BlockKind::Dxt1, true) => Bc1RgbaUnormSrgb,
+      (BlockKind::Dxt3, false) => Bc2RgbaUnorm,
+      (BlockKind::Dxt3, true) => Bc2RgbaUnormSrgb,
+      (BlockKind::Dxt5, false) => Bc3RgbaUnorm,
+      (BlockKind::Dxt5, true) => Bc3RgbaUnormSrgb,
+      (BlockKind::Dxt5A, _) => Bc4RUnorm,
+      (BlockKind::Dxn, _) => Bc5RgUnorm,
+    })
+  }
+
+  pub fn is_compressed(&self) -> bool {
+    !matches!(self, TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb)
+  }
+}
+
+#[cfg(feature = "wgpu")]
+impl From<TextureFormat> for wgpu::TextureFormat {
+  fn from(format: TextureFormat) -> Self {
+    use wgpu::TextureFormat as W;
+    match format {
+      TextureFormat::Bc1RgbaUnorm => W::Bc1RgbaUnorm,
+      TextureFormat::Bc1RgbaUnormSrgb => W::Bc1RgbaUnormSrgb,
+      TextureFormat::Bc2RgbaUnorm => W::Bc2RgbaUnorm,
+      TextureFormat::Bc2RgbaUnormSrgb => W::Bc2RgbaUnormSrgb,
+      TextureFormat::Bc3RgbaUnorm => W::Bc3RgbaUnorm,
+      TextureFormat::Bc3RgbaUnormSrgb => W::Bc3RgbaUnormSrgb,
+      TextureFormat::Bc4RUnorm => W::Bc4RUnorm,
+      TextureFormat::Bc5RgUnorm => W::Bc5RgUnorm,
+      TextureFormat::Etc2Rgb8Unorm => W::Etc2Rgb8Unorm,
+      TextureFormat::Etc2Rgb8UnormSrgb => W::Etc2Rgb8UnormSrgb,
+      TextureFormat::Rgba8Unorm => W::Rgba8Unorm,
+      TextureFormat::Rgba8UnormSrgb => W::Rgba8UnormSrgb,
+    }
+  }
+}
+
+/// one `write_texture` call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelUpload {
+  pub mip_level: u32,
+  /// texels of the copy extent, whole blocks as the copy has to cover when compressed
+  pub width: u32,
+  pub height: u32,
+  /// faces
+  pub layers: u32,
+  pub bytes_per_row: u32,
+  /// rows of blocks or texels per face
+  pub rows_per_image: u32,
+  /// the blocks of every face in the staging buffer
+  pub range: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureUpload {
+  pub format: TextureFormat,
+  /// the base level, wgpu wants multiples of 4 for block compressed textures
+  pub width: u32,
+  pub height: u32,
+  pub layers: u32,
+  pub mip_level_count: u32,
+  pub levels: Vec<LevelUpload>,
+  /// bytes of the staging buffer
+  pub size: usize,
+}
+
+impl TextureUpload {
+  /// Lay out every level of `header` after one another, rows aligned to `row_alignment`, 1 for
+  /// `write_texture` and `COPY_ROW_ALIGNMENT` for `copy_buffer_to_texture`.
+  pub fn new(header: &Header, srgb: bool, row_alignment: usize) -> Result<Self, Error> {
+    let format = TextureFormat::of(header.format, srgb)?;
+    let mut offset = 0usize;
+    let levels = (0..header.level_count as usize).map(|idx| {
+      let layout = header.level_layout(idx, row_alignment, 1)?;
+      let start = offset.next_multiple_of(row_alignment);
+      offset = start + layout.size();
+      Ok(LevelUpload {
+        mip_level: idx as u32,
+        width: layout.block_x as u32 * 4, height: layout.block_y as u32 * 4,
+        layers: layout.faces as u32,
+        bytes_per_row: layout.pitch as u32, rows_per_image: layout.block_y as u32,
+        range: start..offset,
+      })
+    }).collect::<Result<Vec<_>, Error>>()?;
+    Ok(Self {
+      format, width: header.width as u32, height: header.height as u32, layers: header.face_count as u32,
+      mip_level_count: header.level_count as u32, levels, size: offset,
+    })
+  }
+
+  /// `new` for the texels expanded into RGBA8, e.g. when the device lacks the compressed format
+  pub fn rgba(header: &Header, srgb: bool, row_alignment: usize) -> Result<Self, Error> {
+    if row_alignment == 0 { bail!("alignment must not be zero") }
+    TextureFormat::of(header.format, srgb)?;
+    let mut offset = 0usize;
+    let levels = (0..header.level_count as usize).map(|idx| {
+      let (width, height) = header.get_level_info(idx).ok_or_else(|| Error::level(idx))?;
+      let bytes_per_row = (width as usize * 4).next_multiple_of(row_alignment);
+      let start = offset.next_multiple_of(row_alignment);
+      offset = start + bytes_per_row * height as usize * header.face_count as usize;
+      Ok(LevelUpload {
+        mip_level: idx as u32, width: width as u32, height: height as u32, layers: header.face_count as u32,
+        bytes_per_row: bytes_per_row as u32, rows_per_image: height as u32, range: start..offset,
+      })
+    }).collect::<Result<Vec<_>, Error>>()?;
+    Ok(Self {
+      format: if srgb { TextureFormat::Rgba8UnormSrgb } else { TextureFormat::Rgba8Unorm },
+      width: header.width as u32, height: header.height as u32, layers: header.face_count as u32,
+      mip_level_count: header.level_count as u32, levels, size: offset,
+    })
+  }
+
+  /// The texture the levels go to, a 2D array of `layers` with the base level rounded up to whole
+  /// blocks as wgpu wants for compressed formats; `usage` needs `COPY_DST`.
+  #[cfg(feature = "wgpu")]
+  pub fn into_descriptor<'a>(&self, label: Option<&'a str>, usage: wgpu::TextureUsages) -> wgpu::TextureDescriptor<Option<&'a str>, &'a [wgpu::TextureFormat]> {
+    let block = if self.format.is_compressed() { 4 } else { 1 };
+    wgpu::TextureDescriptor {
+      label,
+      size: wgpu::Extent3d { width: self.width.next_multiple_of(block), height: self.height.next_multiple_of(block), depth_or_array_layers: self.layers },
+      mip_level_count: self.mip_level_count,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: self.format.into(),
+      usage,
+      view_formats: &[],
+    }
+  }
+
+  /// the staging buffer with every level unpacked, and expanded for RGBA8, into its range
+  pub fn unpack(&self, header: &Header, tables: &Tables, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; self.size];
+    for level in &self.levels {
+      let (idx, pitch) = (level.mip_level as usize, level.bytes_per_row as usize);
+      let slice_size = pitch * level.rows_per_image as usize;
+      let out = &mut data[level.range.clone()];
+      if self.format.is_compressed() {
+        header.unpack_level_into_slices(tables, input, idx, out, pitch, slice_size)?;
+        continue
+      }
+      let blocks = header.unpack_level(tables, input, idx)?;
+      let row_size = level.width as usize * 4;
+      for (face, slice) in blocks.chunks_exact(blocks.len() / level.layers as usize).zip(out.chunks_exact_mut(slice_size)) {
+        let rgba = crate::rgba::decode_image(header.format, face, level.width as u16, level.height as u16)?;
+        for (row, dst) in rgba.chunks_exact(row_size).zip(slice.chunks_exact_mut(pitch)) {
+          dst[..row_size].copy_from_slice(row);
+        }
+      }
+    }
+    Ok(data)
+  }
+}
+
+/// where the level is in the whole staging buffer, for `write_texture` of all of `data` or a
+/// `copy_buffer_to_texture`
+#[cfg(feature = "wgpu")]
+impl From<&LevelUpload> for wgpu::ImageDataLayout {
+  fn from(level: &LevelUpload) -> Self {
+    Self { offset: level.range.start as u64, bytes_per_row: Some(level.bytes_per_row), rows_per_image: Some(level.rows_per_image) }
+  }
+}
+
+/// the copy size of the level, all faces
+#[cfg(feature = "wgpu")]
+impl From<&LevelUpload> for wgpu::Extent3d {
+  fn from(level: &LevelUpload) -> Self {
+    Self { width: level.width, height: level.height, depth_or_array_layers: level.layers }
+  }
+}
+
+#[test]
+fn test_gpu() {
+  use crate::testgen::TestCrn;
+  assert_eq!(TextureFormat::of(Format::Dxt1, true).unwrap(), TextureFormat::Bc1RgbaUnormSrgb);
+  assert_eq!(TextureFormat::of(Format::DxnYX, true).unwrap(), TextureFormat::Bc5RgUnorm);
+  assert!(TextureFormat::of(Format::Invalid, false).is_err());
+
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt1, 32, 16) };
+  let input = gen.build().unwrap();
+  let header = Header::parse(&input).unwrap();
+  let tables = header.get_table(&input).unwrap();
+  let upload = TextureUpload::new(&header, false, 1).unwrap();
+  assert_eq!((upload.format, upload.width, upload.height, upload.layers, upload.mip_level_count), (TextureFormat::Bc1RgbaUnorm, 32, 16, 6, 6));
+  assert_eq!(upload.levels[0], LevelUpload { mip_level: 0, width: 32, height: 16, layers: 6, bytes_per_row: 64, rows_per_image: 4, range: 0..1536 });
+  // 2x1 and 1x1 still copy a whole block
+  assert_eq!(upload.levels[5], LevelUpload { mip_level: 5, width: 4, height: 4, layers: 6, bytes_per_row: 8, rows_per_image: 1, range: 1536 + 384 + 96 + 48 + 48..2160 });
+  let data = upload.unpack(&header, &tables, &input).unwrap();
+  for (level, blocks) in upload.levels.iter().zip(gen.blocks().unwrap()) {
+    assert_eq!(data[level.range.clone()], blocks[..]);
+  }
+
+  // rows padded for buffer copies
+  let aligned = TextureUpload::new(&header, false, COPY_ROW_ALIGNMENT).unwrap();
+  assert!(aligned.levels.iter().all(|l| l.bytes_per_row == 256 && l.range.start % 256 == 0));
+  assert_eq!(aligned.levels[1].range, 6 * 4 * 256..6 * 4 * 256 + 6 * 2 * 256);
+  let data = aligned.unpack(&header, &tables, &input).unwrap();
+  assert_eq!(data[256..][..64], gen.blocks().unwrap()[0][64..128]);
+
+  // expanded, e.g. for devices without BC
+  let rgba = TextureUpload::rgba(&header, true, 1).unwrap();
+  assert_eq!((rgba.format, rgba.mip_level_count), (TextureFormat::Rgba8UnormSrgb, 6));
+  assert_eq!(rgba.levels[5], LevelUpload { mip_level: 5, width: 1, height: 1, layers: 6, bytes_per_row: 4, rows_per_image: 1, range: 6 * 4 * (512 + 128 + 32 + 8 + 2)..6 * 4 * (512 + 128 + 32 + 8 + 2 + 1) });
+  let data = rgba.unpack(&header, &tables, &input).unwrap();
+  let blocks = &gen.blocks().unwrap()[1];
+  assert_eq!(data[rgba.levels[1].range.clone()][3 * 512..][..512], crate::rgba::decode_image(Format::Dxt1, &blocks[3 * 64..][..64], 16, 8).unwrap()[..]);
+  let aligned = TextureUpload::rgba(&header, false, COPY_ROW_ALIGNMENT).unwrap();
+  let data = aligned.unpack(&header, &tables, &input).unwrap();
+  assert_eq!(data[aligned.levels[1].range.start + 256..][..64], crate::rgba::decode_image(Format::Dxt1, &blocks[..64], 16, 8).unwrap()[64..128]);
+}
+
+#[cfg(feature = "wgpu")]
+#[test]
+fn test_wgpu() {
+  use crate::testgen::TestCrn;
+  let (_, header, _) = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 20, 10) }.parsed().unwrap();
+  let upload = TextureUpload::new(&header, true, COPY_ROW_ALIGNMENT).unwrap();
+  let descriptor = upload.into_descriptor(Some("crn"), wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING);
+  assert_eq!((descriptor.label, descriptor.format, descriptor.mip_level_count), (Some("crn"), wgpu::TextureFormat::Bc3RgbaUnormSrgb, 5));
+  assert_eq!(descriptor.size, wgpu::Extent3d { width: 20, height: 12, depth_or_array_layers: 6 });
+  assert_eq!(descriptor.size.max_mips(wgpu::TextureDimension::D2), 5);
+  for level in &upload.levels {
+    // what wgpu validates the copy against
+    let format = descriptor.format;
+    let size = descriptor.size.mip_level_size(level.mip_level, wgpu::TextureDimension::D2).physical_size(format);
+    let extent = wgpu::Extent3d::from(level);
+    assert_eq!(extent, size);
+    let layout = wgpu::ImageDataLayout::from(level);
+    assert_eq!(layout.offset % wgpu::COPY_BUFFER_ALIGNMENT, 0);
+    assert!(layout.bytes_per_row.unwrap() >= extent.width / 4 * format.block_copy_size(None).unwrap());
+    assert_eq!(layout.offset as usize + layout.bytes_per_row.unwrap() as usize * (layout.rows_per_image.unwrap() * extent.depth_or_array_layers) as usize, level.range.end);
+  }
+  let rgba = TextureUpload::rgba(&header, false, 1).unwrap();
+  let descriptor = rgba.into_descriptor(None, wgpu::TextureUsages::COPY_DST);
+  assert_eq!((descriptor.format, descriptor.size.width, descriptor.size.height), (wgpu::TextureFormat::Rgba8Unorm, 20, 10));
+  assert_eq!(wgpu::TextureFormat::from(TextureFormat::Bc4RUnorm).block_copy_size(None), Some(8));
+}
@@ -0,0 +1,99 @@
+//! A summary of a crn file in the shape of crunch's `crnd_texture_info`, which stays the same
+//! while `Header` follows the file layout.
+
+use crate::error::CrnError as Error;
+use crate::{Format, Header};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct TextureInfo {
+  pub width: u32,
+  pub height: u32,
+  pub levels: u32,
+  /// 1, or 6 for a cubemap
+  pub faces: u32,
+  pub format: Format,
+  /// [`Format::block_size`] of `format`
+  pub bytes_per_block: u32,
+  pub userdata: [u32; 2],
+}
+
+impl TextureInfo {
+  /// The header of `input` without checking the CRCs or the level data, like
+  /// `crnd_get_texture_info`.
+  pub fn parse(input: &[u8]) -> Result<Self, Error> {
+    Ok(Header::parse(input)?.texture_info())
+  }
+}
+
+impl From<&Header> for TextureInfo {
+  fn from(header: &Header) -> Self {
+    Self {
+      width: header.width as u32,
+      height: header.height as u32,
+      levels: header.level_count as u32,
+      faces: header.face_count as u32,
+      format: header.format,
+      bytes_per_block: header.block_size() as u32,
+      userdata: header.userdata,
+    }
+  }
+}
+
+/// [`TextureInfo`] for the C and JS bindings, `capi::CrnTextureInfo` and the `TextureInfo`
+/// object of `wasm::texture_info`: `format` is its `crn_format` value, the userdata is left out.
+#[cfg(any(feature = "capi", feature = "wasm"))]
+#[repr(C)]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen(js_name = TextureInfo))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CrnTextureInfo {
+  pub width: u32,
+  pub height: u32,
+  pub levels: u32,
+  pub faces: u32,
+  /// the values of `crn_format`
+  pub format: u32,
+  pub bytes_per_block: u32,
+}
+
+#[cfg(any(feature = "capi", feature = "wasm"))]
+impl From<&TextureInfo> for CrnTextureInfo {
+  fn from(info: &TextureInfo) -> Self {
+    Self {
+      width: info.width, height: info.height, levels: info.levels, faces: info.faces,
+      format: info.format as u32, bytes_per_block: info.bytes_per_block,
+    }
+  }
+}
+
+#[cfg(feature = "capi")]
+impl From<&TextureInfo> for crate::capi::crn_texture_info {
+  fn from(info: &TextureInfo) -> Self {
+    Self {
+      m_struct_size: std::mem::size_of::<Self>() as u32,
+      m_width: info.width, m_height: info.height, m_levels: info.levels, m_faces: info.faces,
+      m_format: info.format as u32, m_bytes_per_block: info.bytes_per_block,
+      m_userdata0: info.userdata[0], m_userdata1: info.userdata[1],
+    }
+  }
+}
+
+impl Header {
+  pub fn texture_info(&self) -> TextureInfo {
+    self.into()
+  }
+}
+
+#[test]
+fn test_texture_info() {
+  let gen = crate::testgen::TestCrn { faces: 6, ..crate::testgen::TestCrn::new(Format::Dxt5A, 16, 8) };
+  let mut input = gen.build().unwrap();
+  let info = TextureInfo::parse(&input).unwrap();
+  assert_eq!(info, TextureInfo { width: 16, height: 8, levels: 5, faces: 6, format: Format::Dxt5A, bytes_per_block: 8, userdata: [0; 2] });
+  assert_eq!(Header::parse(&input).unwrap().texture_info(), info);
+  // like crnd, a damaged level doesn't matter
+  *input.last_mut().unwrap() ^= 1;
+  assert_eq!(TextureInfo::parse(&input).unwrap(), info);
+  assert!(TextureInfo::parse(&input[..20]).is_err());
+}
@@ -0,0 +1,171 @@
+//! Mipmap chains from an RGBA image, for encoding single images with all their levels or for
+//! filling in the tail levels a truncated file lost from the last level it still holds.
+//!
+//! Every level is resampled from the one above with a separable filter, clamping at the edges.
+//! sRGB images are averaged in linear light so dark and bright texels mix as they look, alpha is
+//! always linear.
+
+use crate::error::{CrnError as Error, bail};
+
+/// how a level is resampled from the one above
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+  /// the average of the texels covered, the cheapest
+  #[default]
+  Box,
+  /// a windowed sinc reaching 3 texels of the smaller level, sharper with a little ringing
+  Kaiser,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MipOptions {
+  pub filter: Filter,
+  /// the color channels are sRGB encoded
+  pub srgb: bool,
+}
+
+/// radius of `Filter::Kaiser` in texels of the smaller level, and the shape of its window
+const KAISER_SUPPORT: f32 = 3.0;
+const KAISER_ALPHA: f32 = 4.0;
+
+/// levels of a full chain down to 1x1, the base level included
+pub fn level_count(width: u16, height: u16) -> usize {
+  16 - (width | height | 1).leading_zeros() as usize
+}
+
+/// modified Bessel function of the first kind of order 0
+fn bessel_i0(x: f32) -> f32 {
+  let (mut sum, mut term) = (1.0, 1.0);
+  for k in 1..32 {
+    term *= (x / (2.0 * k as f32)).powi(2);
+    sum += term;
+    if term < sum * 1e-7 { break }
+  }
+  sum
+}
+
+fn kaiser(t: f32) -> f32 {
+  if t.abs() >= KAISER_SUPPORT { return 0.0 }
+  let sinc = if t == 0.0 { 1.0 } else { (std::f32::consts::PI * t).sin() / (std::f32::consts::PI * t) };
+  let window = bessel_i0(KAISER_ALPHA * (1.0 - (t / KAISER_SUPPORT).powi(2)).sqrt()) / bessel_i0(KAISER_ALPHA);
+  sinc * window
+}
+
+/// source texels and their weights for every texel of an axis resampled from `size` to `out`
+fn weights(size: usize, out: usize, filter: Filter) -> Vec<Vec<(usize, f32)>> {
+  let scale = size as f32 / out as f32;
+  (0..out).map(|i| {
+    let center = (i as f32 + 0.5) * scale;
+    let radius = match filter { Filter::Box => scale / 2.0, Filter::Kaiser => KAISER_SUPPORT * scale };
+    let (first, last) = ((center - radius).floor() as isize, (center + radius).ceil() as isize);
+    let mut taps: Vec<(usize, f32)> = vec![];
+    for j in first..last {
+      let weight = match filter {
+        Filter::Box => ((j + 1) as f32).min(center + radius) - (j as f32).max(center - radius),
+        Filter::Kaiser => kaiser((j as f32 + 0.5 - center) / scale),
+      };
+      if weight == 0.0 { continue }
+      let j = j.clamp(0, size as isize - 1) as usize;
+      match taps.iter_mut().find(|(k, _)| *k == j) {
+        Some((_, w)) => *w += weight,
+        None => taps.push((j, weight)),
+      }
+    }
+    let total = taps.iter().map(|t| t.1).sum::<f32>();
+    for (_, w) in &mut taps { *w /= total }
+    taps
+  }).collect()
+}
+
+fn to_linear(v: u8) -> f32 {
+  let v = v as f32 / 255.0;
+  if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn from_linear(v: f32) -> f32 {
+  let v = v.clamp(0.0, 1.0);
+  255.0 * if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Resample `width * height * 4` bytes of RGBA to the next level, `max(1, width / 2)` by
+/// `max(1, height / 2)` texels.
+pub fn downsample(rgba: &[u8], width: u16, height: u16, options: &MipOptions) -> Result<Vec<u8>, Error> {
+  let (width, height) = (width as usize, height as usize);
+  if width == 0 || height == 0 || rgba.len() < width * height * 4 {
+    bail!("image data {} bytes for {}x{}", rgba.len(), width, height);
+  }
+  let (out_x, out_y) = (1.max(width / 2), 1.max(height / 2));
+  let table: Vec<f32> = (0..=255).map(|v| if options.srgb { to_linear(v) } else { v as f32 / 255.0 }).collect();
+  let texels: Vec<f32> = rgba[..width * height * 4].chunks_exact(4)
+    .flat_map(|t| [table[t[0] as usize], table[t[1] as usize], table[t[2] as usize], t[3] as f32 / 255.0]).collect();
+
+  let mut rows = vec![0.0; out_x * height * 4];
+  for (x, taps) in weights(width, out_x, options.filter).iter().enumerate() {
+    for y in 0..height {
+      for &(j, w) in taps {
+        for c in 0..4 { rows[(y * out_x + x) * 4 + c] += w * texels[(y * width + j) * 4 + c] }
+      }
+    }
+  }
+  let mut result = vec![0.0; out_x * out_y * 4];
+  for (y, taps) in weights(height, out_y, options.filter).iter().enumerate() {
+    for &(j, w) in taps {
+      for (r, &v) in result[y * out_x * 4..][..out_x * 4].iter_mut().zip(&rows[j * out_x * 4..][..out_x * 4]) { *r += w * v }
+    }
+  }
+  Ok(result.chunks_exact(4).flat_map(|t| {
+    let color = |v: f32| if options.srgb { from_linear(v) } else { v.clamp(0.0, 1.0) * 255.0 };
+    [color(t[0]), color(t[1]), color(t[2]), t[3].clamp(0.0, 1.0) * 255.0].map(|v| v.round() as u8)
+  }).collect())
+}
+
+/// The `count` levels following `rgba` of `width` by `height`, each resampled from the one
+/// before and stopping at 1x1.
+pub fn mip_chain(rgba: &[u8], width: u16, height: u16, count: usize, options: &MipOptions) -> Result<Vec<Vec<u8>>, Error> {
+  let mut result: Vec<Vec<u8>> = vec![];
+  let (mut width, mut height) = (width, height);
+  for _ in 0..count.min(level_count(width, height) - 1) {
+    let level = downsample(result.last().map_or(rgba, Vec::as_slice), width, height, options)?;
+    (width, height) = (1.max(width / 2), 1.max(height / 2));
+    result.push(level);
+  }
+  Ok(result)
+}
+
+#[test]
+fn test_mipmap() {
+  assert_eq!([(1, 1), (16, 8), (17, 3), (1024, 1)].map(|(w, h)| level_count(w, h)), [1, 5, 5, 11]);
+  let options = MipOptions::default();
+  // a 2x2 box average per texel
+  let image: Vec<u8> = (0..16u8).flat_map(|i| [i * 16, 255 - i * 16, if i % 2 == 0 { 0 } else { 200 }, 255]).collect();
+  let level = downsample(&image, 4, 4, &options).unwrap();
+  assert_eq!(&level[..4], &[40, 215, 100, 255]);
+  assert_eq!(level.len(), 16);
+  // black and white average to middle gray in linear light, brighter than 128 in sRGB
+  let checker: Vec<u8> = (0..4).flat_map(|i| if i % 3 == 0 { [0, 0, 0, 255] } else { [255, 255, 255, 255] }).collect();
+  assert_eq!(downsample(&checker, 2, 2, &options).unwrap(), [128, 128, 128, 255]);
+  assert_eq!(downsample(&checker, 2, 2, &MipOptions { srgb: true, ..options }).unwrap(), [188, 188, 188, 255]);
+
+  // solid images stay solid and a smooth gradient stays close with either filter
+  let solid = [10, 20, 30, 40].repeat(16 * 8);
+  let gradient: Vec<u8> = (0..16 * 8).flat_map(|i| [(i % 16 * 16) as u8, (i / 16 * 32) as u8, 0, 255]).collect();
+  for filter in [Filter::Box, Filter::Kaiser] {
+    for srgb in [false, true] {
+      let options = MipOptions { filter, srgb };
+      let chain = mip_chain(&solid, 16, 8, 10, &options).unwrap();
+      assert_eq!(chain.iter().map(Vec::len).collect::<Vec<_>>(), [8 * 4 * 4, 4 * 2 * 4, 2 * 4, 4]);
+      assert!(chain.iter().all(|level| level.chunks(4).all(|t| t == [10, 20, 30, 40])), "{:?}", options);
+      let boxed = downsample(&gradient, 16, 8, &MipOptions { filter: Filter::Box, srgb }).unwrap();
+      let level = downsample(&gradient, 16, 8, &options).unwrap();
+      // away from the clamped edges
+      for y in 1..3 {
+        for x in 1..7 {
+          let i = (y * 8 + x) * 4;
+          assert!(level[i..i + 4].iter().zip(&boxed[i..i + 4]).all(|(&a, &b)| a.abs_diff(b) <= 2), "{:?} {} {}", options, x, y);
+        }
+      }
+    }
+  }
+  assert_eq!(mip_chain(&solid, 16, 8, 2, &options).unwrap().len(), 2);
+  assert!(downsample(&solid[1..], 16, 8, &options).is_err());
+}
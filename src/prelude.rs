@@ -0,0 +1,3 @@
+//! `use crnlib::prelude::*;` for decoding without the format internals, see `texture`.
+
+pub use crate::{CrnError, DecodedTexture, Format, TextureInfo, decode, decode_with_progress, texture::DecodeProgress};
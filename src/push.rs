@@ -0,0 +1,155 @@
+//! A push parser for input arriving in pieces, e.g. a streaming fetch or a network protocol: the
+//! header comes out as soon as its bytes are in, and every level as soon as its stream is.
+//!
+//! Levels follow the palettes and tables in the file, smallest offsets first as crunch writes
+//! them, so they come out in order. The input is kept until the end for the data CRC.
+
+use crate::error::{CrnError as Error, bail, ensure};
+use crate::{DecodeLimits, Header, ParseOptions, Tables};
+
+/// what `PushDecoder::feed` got to
+#[derive(Debug, Clone)]
+pub enum Status {
+  /// nothing more until further input
+  NeedMore,
+  Header(Header),
+  /// level `idx` unpacked as `Header::unpack_level` returns it
+  Level(usize, Vec<u8>),
+  /// every level is out and the data CRC checked, further input is an error
+  Done,
+}
+
+#[derive(Debug, Default)]
+pub struct PushDecoder {
+  options: ParseOptions,
+  limits: DecodeLimits,
+  input: Vec<u8>,
+  header: Option<Header>,
+  tables: Option<Tables>,
+  next_level: usize,
+  done: bool,
+}
+
+impl PushDecoder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// `new` parsing with `options` and rejecting headers beyond `limits`
+  pub fn with_options(options: &ParseOptions, limits: &DecodeLimits) -> Self {
+    Self { options: *options, limits: *limits, ..Self::default() }
+  }
+
+  pub fn header(&self) -> Option<&Header> {
+    self.header.as_ref()
+  }
+
+  /// bytes fed so far
+  pub fn len(&self) -> usize {
+    self.input.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.input.is_empty()
+  }
+
+  /// Append `data` and return the next step it completes. A piece may complete several, so keep
+  /// calling with an empty slice until `NeedMore` or `Done`.
+  pub fn feed(&mut self, data: &[u8]) -> Result<Status, Error> {
+    if self.done {
+      ensure!(data.is_empty() || self.options.allow_size_mismatch, Error::SizeMismatch { expected: self.input.len(), actual: self.input.len() + data.len() });
+      return Ok(Status::Done)
+    }
+    self.input.extend_from_slice(data);
+    let Some(header) = &self.header else { return self.parse_header() };
+    if self.tables.is_none() {
+      let palettes = [header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors];
+      let end = palettes.iter().filter(|p| p.count != 0).map(|p| p.offset as usize + p.size as usize)
+        .fold(header.table_offset as usize + header.table_size as usize, usize::max);
+      if self.input.len() < end { return Ok(Status::NeedMore) }
+      self.tables = Some(header.get_table(&self.input)?);
+    }
+    let tables = self.tables.as_ref().unwrap();
+    if self.next_level < header.level_count as usize {
+      let idx = self.next_level;
+      let end = header.level_offset.get(idx + 1).map_or(header.file_size, |&o| o) as usize;
+      if self.input.len() < end { return Ok(Status::NeedMore) }
+      let level = header.unpack_level(tables, &self.input, idx)?;
+      self.next_level += 1;
+      return Ok(Status::Level(idx, level))
+    }
+    let file_size = header.file_size as usize;
+    if self.input.len() < file_size { return Ok(Status::NeedMore) }
+    if self.input.len() > file_size && !self.options.allow_size_mismatch {
+      bail!(Error::SizeMismatch { expected: file_size, actual: self.input.len() })
+    }
+    if self.options.verify_crc && header.data_crc16 != !Header::crc16(0, &self.input[header.header_size as usize..file_size]) {
+      bail!(Error::CrcMismatch)
+    }
+    self.done = true;
+    Ok(Status::Done)
+  }
+
+  fn parse_header(&mut self) -> Result<Status, Error> {
+    let fixed_size = Header::fixed_size();
+    if self.input.len() < fixed_size { return Ok(Status::NeedMore) }
+    let header_size = fixed_size + 4 * Header::parse_fixed(&self.input)?.level_count as usize;
+    if self.input.len() < header_size { return Ok(Status::NeedMore) }
+    let options = ParseOptions { verify_crc: false, allow_size_mismatch: true, ..self.options };
+    let header = Header::parse_with(&self.input[..header_size], &options)?;
+    ensure!(header.flags & Header::SEGMENTED == 0, "levels of a segmented file are elsewhere");
+    if self.options.verify_crc && header.header_crc16 != !Header::crc16(0, &self.input[6..header_size]) {
+      bail!(Error::CrcMismatch)
+    }
+    header.check_limits(&self.limits)?;
+    self.header = Some(header.clone());
+    Ok(Status::Header(header))
+  }
+}
+
+#[test]
+fn test_push() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 32, 16) };
+  let input = gen.build().unwrap();
+  let levels = gen.blocks().unwrap();
+  for chunk in [1, 7, 100, input.len()] {
+    let mut decoder = PushDecoder::new();
+    let mut events = vec![];
+    for piece in input.chunks(chunk) {
+      let mut status = decoder.feed(piece).unwrap();
+      loop {
+        match status {
+          Status::NeedMore => break,
+          Status::Done => { events.push(Status::Done); break }
+          status => events.push(status),
+        }
+        status = decoder.feed(&[]).unwrap();
+      }
+    }
+    assert_eq!(events.len(), 2 + levels.len(), "chunk {}", chunk);
+    assert!(matches!(&events[0], Status::Header(header) if header.level_offset == Header::parse(&input).unwrap().level_offset));
+    for (idx, level) in levels.iter().enumerate() {
+      assert!(matches!(&events[1 + idx], Status::Level(i, data) if *i == idx && data == level), "chunk {} level {}", chunk, idx);
+    }
+    assert!(matches!(events.last(), Some(Status::Done)));
+    assert!(decoder.feed(&[0]).is_err());
+  }
+
+  // the header is out before any level data
+  let header_size = Header::parse(&input).unwrap().header_size as usize;
+  let mut decoder = PushDecoder::new();
+  assert!(matches!(decoder.feed(&input[..header_size - 1]).unwrap(), Status::NeedMore));
+  assert!(matches!(decoder.feed(&input[header_size - 1..header_size]).unwrap(), Status::Header(_)));
+  assert!(matches!(decoder.feed(&[]).unwrap(), Status::NeedMore));
+
+  // data CRC checked at the end
+  let mut corrupt = input.clone();
+  *corrupt.last_mut().unwrap() ^= 1;
+  let mut decoder = PushDecoder::new();
+  let mut status = decoder.feed(&corrupt);
+  while let Ok(Status::Header(_) | Status::Level(..)) = status { status = decoder.feed(&[]) }
+  assert!(status.is_err());
+  assert!(matches!(PushDecoder::new().feed(b"Hy"), Ok(Status::NeedMore)));
+  assert!(PushDecoder::new().feed(&[0; 80]).is_err());
+}
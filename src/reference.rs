@@ -0,0 +1,72 @@
+//! Differential testing against crunch's own decoder: `crn_decomp.h` linked through a small C++
+//! shim, see build.rs. Levels are compared byte for byte and the first block that differs is
+//! reported, e.g. over a corpus with `examples/difftest.rs`.
+
+use crate::error::{CrnError as Error, bail, ensure};
+use crate::DecodeSession;
+
+extern "C" {
+  fn crnlib_reference_level_size(data: *const u8, size: u32, level: u32, out_size: *mut u32) -> bool;
+  fn crnlib_reference_unpack_level(data: *const u8, size: u32, level: u32, out: *mut u8, out_size: u32) -> bool;
+}
+
+/// level `idx` of `input` as crnd unpacks it, laid out as `Header::unpack_level` returns it
+pub fn reference_unpack_level(input: &[u8], idx: usize) -> Result<Vec<u8>, Error> {
+  ensure!(input.len() <= u32::MAX as usize, "input of {} bytes", input.len());
+  let mut size = 0;
+  // SAFETY: crnd reads at most `input.len()` bytes and writes at most `size` bytes of `out`
+  unsafe {
+    if !crnlib_reference_level_size(input.as_ptr(), input.len() as u32, idx as u32, &mut size) { bail!(Error::level(idx)) }
+    let mut out = vec![0; size as usize];
+    if !crnlib_reference_unpack_level(input.as_ptr(), input.len() as u32, idx as u32, out.as_mut_ptr(), size) {
+      bail!("crnd failed to unpack level {}", idx)
+    }
+    Ok(out)
+  }
+}
+
+/// the first block of a level that isn't the same in both decoders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+  pub level: usize,
+  pub face: usize,
+  /// in blocks
+  pub x: usize,
+  pub y: usize,
+  pub ours: Vec<u8>,
+  pub reference: Vec<u8>,
+}
+
+/// Unpack every level of `input` with both decoders. With `skip_edges` the blocks of chunks on an
+/// odd right or bottom edge, which this crate leaves zeroed, aren't compared.
+pub fn compare(input: &[u8], skip_edges: bool) -> Result<Option<Divergence>, Error> {
+  let session = DecodeSession::new(input)?;
+  let header = session.header();
+  let block_size = header.block_size();
+  for level in 0..session.level_count() {
+    let (ours, reference) = (session.unpack_level(level)?, reference_unpack_level(input, level)?);
+    ensure!(ours.len() == reference.len(), "level {} is {} bytes here and {} in crnd", level, ours.len(), reference.len());
+    let (width, height) = header.get_level_info(level).ok_or_else(|| Error::level(level))?;
+    let (block_x, block_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+    let skipped = |x: usize, y: usize| skip_edges && ((block_x & 1 == 1 && x == block_x - 1) || (block_y & 1 == 1 && y == block_y - 1));
+    let blocks = ours.chunks_exact(block_size).zip(reference.chunks_exact(block_size)).enumerate();
+    if let Some((b, (ours, reference))) = blocks.filter(|&(b, _)| !skipped(b % block_x, b / block_x % block_y)).find(|(_, (a, b))| a != b) {
+      return Ok(Some(Divergence {
+        level, face: b / (block_x * block_y), x: b % block_x, y: b / block_x % block_y,
+        ours: ours.to_vec(), reference: reference.to_vec(),
+      }))
+    }
+  }
+  Ok(None)
+}
+
+#[test]
+fn test_reference() {
+  use crate::{Format, testgen::TestCrn};
+  for format in [Format::Dxt1, Format::Dxt5, Format::Dxt5A, Format::DxnXY] {
+    for (width, height) in [(32, 16), (40, 24), (13, 7)] {
+      let input = TestCrn { faces: 6, ..TestCrn::new(format, width, height) }.build().unwrap();
+      assert_eq!(compare(&input, true).unwrap(), None, "{:?} {}x{}", format, width, height);
+    }
+  }
+}
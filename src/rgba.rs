@@ -0,0 +1,191 @@
+//! software expansion of BCn blocks (as produced by `unpack`) into RGBA8 texels
+
+use crate::error::{CrnError as Error, bail};
+use crate::{Format, unpack::BlockKind};
+
+/// expand RGB565 into RGB888, replicating high bits into low bits
+#[inline]
+pub fn rgb565(c: u16) -> [u8; 3] {
+  let r = (c >> 11 & 0x1f) as u8;
+  let g = (c >> 5 & 0x3f) as u8;
+  let b = (c & 0x1f) as u8;
+  [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// `OutOfInput` unless `block` holds `size` bytes
+fn check_block(block: &[u8], size: usize, what: &str) -> Result<(), Error> {
+  if block.len() < size {
+    bail!(Error::OutOfInput { what: what.to_string(), start: 0, end: size, len: block.len() });
+  }
+  Ok(())
+}
+
+/// decode a 8-byte color block, `dxt1` enables the 3-color + transparent mode when `c0 <= c1`
+pub fn decode_color_block(block: &[u8], dxt1: bool, out: &mut [[u8; 4]; 16]) -> Result<(), Error> {
+  check_block(block, 8, "color block")?;
+  color_block(block, dxt1, out);
+  Ok(())
+}
+
+/// decode a 8-byte alpha (BC4) block into 16 channel values
+pub fn decode_alpha_block(block: &[u8], out: &mut [u8; 16]) -> Result<(), Error> {
+  check_block(block, 8, "alpha block")?;
+  alpha_block(block, out);
+  Ok(())
+}
+
+/// `decode_color_block` of a block known to hold 8 bytes
+pub(crate) fn color_block(block: &[u8], dxt1: bool, out: &mut [[u8; 4]; 16]) {
+  let c0 = u16::from_le_bytes([block[0], block[1]]);
+  let c1 = u16::from_le_bytes([block[2], block[3]]);
+  let (a, b) = (rgb565(c0), rgb565(c1));
+  let mut palette = [[a[0], a[1], a[2], 255], [b[0], b[1], b[2], 255], [0; 4], [0; 4]];
+  for i in 0..3 {
+    let (a, b) = (a[i] as u16, b[i] as u16);
+    if c0 > c1 || !dxt1 {
+      palette[2][i] = ((2 * a + b) / 3) as u8;
+      palette[3][i] = ((a + 2 * b) / 3) as u8;
+    } else {
+      palette[2][i] = ((a + b) / 2) as u8;
+    }
+  }
+  palette[2][3] = 255;
+  palette[3][3] = if c0 > c1 || !dxt1 { 255 } else { 0 };
+  let selector = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+  for (i, texel) in out.iter_mut().enumerate() {
+    *texel = palette[(selector >> (2 * i) & 3) as usize];
+  }
+}
+
+/// `decode_alpha_block` of a block known to hold 8 bytes
+pub(crate) fn alpha_block(block: &[u8], out: &mut [u8; 16]) {
+  let (a0, a1) = (block[0] as usize, block[1] as usize);
+  let mut palette = [a0 as u8, a1 as u8, 0, 0, 0, 0, 0, 255];
+  let steps = if a0 > a1 { 7 } else { 5 };
+  for (i, value) in palette[2..=steps].iter_mut().enumerate() {
+    let i = i + 1;
+    *value = (((steps - i) * a0 + i * a1) / steps) as u8;
+  }
+  let selector = block[2..8].iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64);
+  for (i, value) in out.iter_mut().enumerate() {
+    *value = palette[(selector >> (3 * i) & 7) as usize];
+  }
+}
+
+/// decode a single block of `format` into 16 RGBA texels in row-major order.
+///
+/// `Dxt5A` is expanded to grey, `Dxn*` puts X into red and Y into green.
+pub fn decode_block(format: Format, block: &[u8], out: &mut [[u8; 4]; 16]) -> Result<(), Error> {
+  let mut alpha = [0; 16];
+  let mut alpha1 = [0; 16];
+  let kind = BlockKind::of(format)?;
+  check_block(block, format.block_size(), &format!("{:?} block", format))?;
+  match kind {
+    BlockKind::Dxt1 => color_block(block, true, out),
+    BlockKind::Dxt3 => {
+      color_block(&block[8..], false, out);
+      for (i, texel) in out.iter_mut().enumerate() {
+        let a = block[i / 2] >> (4 * (i & 1)) & 0xf;
+        texel[3] = a << 4 | a;
+      }
+    }
+    BlockKind::Dxt5 => {
+      color_block(&block[8..], false, out);
+      alpha_block(block, &mut alpha);
+      for (texel, &a) in out.iter_mut().zip(&alpha) {
+        texel[3] = a;
+      }
+    }
+    BlockKind::Dxt5A => {
+      alpha_block(block, &mut alpha);
+      for (texel, &a) in out.iter_mut().zip(&alpha) {
+        *texel = [a, a, a, 255];
+      }
+    }
+    BlockKind::Dxn => {
+      alpha_block(block, &mut alpha);
+      alpha_block(&block[8..], &mut alpha1);
+      if let Format::DxnYX = format { std::mem::swap(&mut alpha, &mut alpha1) }
+      for (texel, (&x, &y)) in out.iter_mut().zip(alpha.iter().zip(&alpha1)) {
+        *texel = [x, y, 0, 255];
+      }
+    }
+  }
+  Ok(())
+}
+
+/// copy the texels of block (`bx`, `by`) into an RGBA image, clipping at its edges
+fn put_block(image: &mut [u8], width: usize, height: usize, bx: usize, by: usize, texels: &[[u8; 4]; 16]) {
+  for (i, texel) in texels.iter().enumerate() {
+    let (x, y) = (bx * 4 + i % 4, by * 4 + i / 4);
+    if x < width && y < height {
+      let pos = (y * width + x) * 4;
+      image[pos..pos + 4].copy_from_slice(texel);
+    }
+  }
+}
+
+/// expand the blocks of row `by` 4 at a time, returning how many blocks were done
+#[cfg(feature = "simd")]
+fn put_row_simd(format: Format, row: &[u8], image: &mut [u8], width: usize, height: usize, by: usize) -> usize {
+  if !crate::simd::supports(format) { return 0 }
+  let block_size = format.block_size();
+  let quads = width.div_ceil(4) / 4;
+  for q in 0..quads {
+    let mut quad = [[[0u8; 4]; 16]; 4];
+    crate::simd::decode_blocks(format, &row[q * 4 * block_size..], &mut quad);
+    for (i, texels) in quad.iter().enumerate() {
+      put_block(image, width, height, q * 4 + i, by, texels);
+    }
+  }
+  quads * 4
+}
+
+/// expand one face of tightly packed blocks into `width * height * 4` bytes of RGBA,
+/// 4 blocks at a time with SSE2 or NEON for BC1 and BC3 with the `simd` feature
+pub fn decode_image(format: Format, blocks: &[u8], width: u16, height: u16) -> Result<Vec<u8>, Error> {
+  let block_size = format.block_size();
+  let size = crate::unpack::level_size(width, height, 1, block_size)?;
+  let (width, height) = (width as usize, height as usize);
+  let block_x = width.div_ceil(4);
+  let block_y = height.div_ceil(4);
+  if blocks.len() < size {
+    bail!("block data too short {} < {}", blocks.len(), size);
+  }
+  let texels = width.checked_mul(height).and_then(|n| n.checked_mul(4)).ok_or(Error::Overflow("image size"))?;
+  let mut result = vec![0u8; texels];
+  let mut texels = [[0u8; 4]; 16];
+  for by in 0..block_y {
+    #[cfg(feature = "simd")]
+    let bx = put_row_simd(format, &blocks[by * block_x * block_size..], &mut result, width, height, by);
+    #[cfg(not(feature = "simd"))]
+    let bx = 0;
+    for bx in bx..block_x {
+      let offset = (by * block_x + bx) * block_size;
+      decode_block(format, &blocks[offset..offset + block_size], &mut texels)?;
+      put_block(&mut result, width, height, bx, by, &texels);
+    }
+  }
+  Ok(result)
+}
+
+#[test]
+fn test_decode_block() {
+  let mut out = [[0; 4]; 16];
+  // c0 = white, c1 = black, selectors 0, 1, 2, 3 repeated
+  decode_color_block(&[0xff, 0xff, 0, 0, 0xe4, 0xe4, 0xe4, 0xe4], true, &mut out).unwrap();
+  assert_eq!(&out[..4], &[[255; 4], [0, 0, 0, 255], [170, 170, 170, 255], [85, 85, 85, 255]]);
+  // c0 <= c1 selects transparent black for index 3
+  decode_color_block(&[0, 0, 0xff, 0xff, 0xff, 0, 0, 0], true, &mut out).unwrap();
+  assert_eq!(out[0], [0; 4]);
+  assert_eq!(out[4], [0, 0, 0, 255]);
+
+  let mut alpha = [0; 16];
+  decode_alpha_block(&[0xff, 0, 0b1000_1000, 0, 0, 0, 0, 0], &mut alpha).unwrap();
+  assert_eq!(&alpha[..4], &[255, 0, 218, 255]);
+
+  // short blocks are errors, not panics
+  assert!(matches!(decode_color_block(&[0; 7], true, &mut out), Err(Error::OutOfInput { end: 8, len: 7, .. })));
+  assert!(matches!(decode_alpha_block(&[], &mut alpha), Err(Error::OutOfInput { end: 8, len: 0, .. })));
+  assert!(matches!(decode_block(Format::Dxt5, &[0; 8], &mut out), Err(Error::OutOfInput { end: 16, len: 8, .. })));
+}
@@ -0,0 +1,69 @@
+//! Segmented crn files as crunch writes them for engines paging level data themselves: a header
+//! blob with the palettes and tables, flagged `Header::SEGMENTED`, and the stream of every level
+//! in a blob of its own.
+//!
+//! The header blob keeps the level offsets of the complete file, its `file_size` and data CRC
+//! only cover the blob itself. Levels are decoded with `Header::unpack_level_segmented`.
+
+use crate::error::{CrnError as Error, ensure};
+use crate::{Header, builder::finish};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Segmented {
+  /// header, palettes and tables
+  pub header: Vec<u8>,
+  pub levels: Vec<Vec<u8>>,
+}
+
+impl Segmented {
+  /// split a complete crn file, e.g. what `encode::encode` returns
+  pub fn split(input: &[u8]) -> Result<Self, Error> {
+    let mut header = Header::parse(input)?;
+    ensure!(header.check_crc(input), "crc mismatch");
+    ensure!(header.flags & Header::SEGMENTED == 0, "already segmented");
+    let first = header.level_offset.first().map_or(header.file_size, |&o| o) as usize;
+    ensure!(first >= header.header_size as usize && first <= input.len(), "level 0 at {}", first);
+    let levels = (0..header.level_count as usize).map(|idx| Ok(header.get_level_data(input, idx)?.to_vec())).collect::<Result<Vec<_>, Error>>()?;
+    header.flags |= Header::SEGMENTED;
+    Ok(Self { header: finish(&header, input[..first].to_vec())?, levels })
+  }
+
+  /// the complete crn file of the segments, with the level offsets following the level blobs
+  pub fn join(&self) -> Result<Vec<u8>, Error> {
+    let mut header = Header::parse(&self.header)?;
+    ensure!(header.flags & Header::SEGMENTED != 0, "not segmented");
+    ensure!(self.levels.len() == header.level_count as usize, "{} levels instead of {}", self.levels.len(), header.level_count);
+    header.flags &= !Header::SEGMENTED;
+    let mut offset = self.header.len();
+    header.level_offset = self.levels.iter().map(|level| {
+      let start = offset;
+      offset += level.len();
+      start as u32
+    }).collect();
+    ensure!(offset <= u32::MAX as usize, "file too large {}", offset);
+    let data = self.levels.iter().fold(self.header.clone(), |mut data, level| { data.extend_from_slice(level); data });
+    finish(&header, data)
+  }
+}
+
+#[test]
+fn test_segmented() {
+  use crate::{Format, encode::encode};
+  let level0: Vec<u8> = (0..16 * 16).map(|i| (i * 37 % 256) as u8).collect();
+  let level1: Vec<u8> = (0..4 * 16).map(|i| (i * 11 % 256) as u8).collect();
+  let input = encode(Format::Dxt5, 16, 16, 1, &[&level0, &level1]).unwrap();
+  let segmented = Segmented::split(&input).unwrap();
+  assert_eq!(segmented.levels.len(), 2);
+  let header = Header::parse(&segmented.header).unwrap();
+  assert!(header.check_crc(&segmented.header));
+  assert_eq!((header.flags, header.file_size as usize), (Header::SEGMENTED, segmented.header.len()));
+  assert!(header.validate(&segmented.header).is_valid(), "{:?}", header.validate(&segmented.header));
+  let tables = header.get_table(&segmented.header).unwrap();
+  let original = Header::parse(&input).unwrap();
+  for (idx, level) in segmented.levels.iter().enumerate() {
+    assert_eq!(header.unpack_level_segmented(&tables, level, idx).unwrap(), original.unpack_level(&tables, &input, idx).unwrap());
+  }
+  assert_eq!(segmented.join().unwrap(), input);
+  assert!(Segmented::split(&segmented.header).is_err());
+  assert!(Segmented { levels: vec![], ..segmented.clone() }.join().is_err());
+}
@@ -0,0 +1,129 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use crate::error::{Context, CrnError as Error};
+use crate::{DecodeLimits, Header, ParseOptions, Tables, stats::{CompressionBreakdown, DecodeStats, LevelEntropy}};
+
+/// A parsed crn file: the input buffer together with its `Header` and `Tables`,
+/// so levels can't be unpacked with tables built from another file.
+pub struct DecodeSession<'a> {
+  input: Cow<'a, [u8]>,
+  header: Header,
+  tables: Tables,
+}
+
+impl std::fmt::Debug for DecodeSession<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("DecodeSession")
+      .field("input_len", &self.input.len())
+      .field("header", &self.header)
+      .field("tables", &self.tables)
+      .finish()
+  }
+}
+
+impl<'a> DecodeSession<'a> {
+  /// parse header and tables of `input`, which could be borrowed (`&[u8]`) or owned (`Vec<u8>`),
+  /// headers beyond the default `DecodeLimits` are rejected
+  pub fn new(input: impl Into<Cow<'a, [u8]>>) -> Result<Self, Error> {
+    Self::with_limits(input, &DecodeLimits::default())
+  }
+
+  pub fn with_limits(input: impl Into<Cow<'a, [u8]>>, limits: &DecodeLimits) -> Result<Self, Error> {
+    Self::with_options(input, &ParseOptions::default(), limits)
+  }
+
+  /// `new` parsing with `options` and rejecting headers beyond `limits`
+  pub fn with_options(input: impl Into<Cow<'a, [u8]>>, options: &ParseOptions, limits: &DecodeLimits) -> Result<Self, Error> {
+    let input = input.into();
+    let header = Header::parse_with(&input, options).context("parse header")?;
+    header.check_limits(limits)?;
+    let tables = header.get_table(&input).context("read table")?;
+    Ok(Self { input, header, tables })
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  pub fn tables(&self) -> &Tables {
+    &self.tables
+  }
+
+  pub fn input(&self) -> &[u8] {
+    &self.input
+  }
+
+  pub fn into_input(self) -> Cow<'a, [u8]> {
+    self.input
+  }
+
+  pub fn level_count(&self) -> usize {
+    self.header.level_count as usize
+  }
+
+  /// unpack level `idx` into BCn blocks, see [`Header::unpack_level`]
+  pub fn unpack_level(&self, idx: usize) -> Result<Vec<u8>, Error> {
+    self.header.unpack_level(&self.tables, &self.input, idx)
+  }
+
+  /// count symbols and bits of every table, see [`DecodeStats::collect`]
+  pub fn stats(&self) -> Result<DecodeStats, Error> {
+    DecodeStats::collect(&self.header, &self.tables, &self.input)
+  }
+
+  /// bits of every part of the file and every level stream, see [`CompressionBreakdown`]
+  pub fn breakdown(&self) -> Result<CompressionBreakdown, Error> {
+    CompressionBreakdown::collect(&self.header, &self.tables, &self.input)
+  }
+
+  /// palette deltas of level `idx` against their entropy, see [`LevelEntropy::collect`]
+  pub fn entropy(&self, idx: usize) -> Result<LevelEntropy, Error> {
+    LevelEntropy::collect(&self.header, &self.tables, &self.input, idx)
+  }
+
+  /// unpack level `idx` and expand it into RGBA8 texels, faces are concatenated
+  pub fn to_rgba(&self, idx: usize) -> Result<Vec<u8>, Error> {
+    let (width, height) = self.header.get_level_info(idx).ok_or(Error::level(idx))?;
+    let blocks = self.unpack_level(idx)?;
+    let face_size = crate::unpack::level_size(width, height, 1, self.header.block_size())?;
+    let mut result = Vec::with_capacity(blocks.len() / face_size * width as usize * height as usize * 4);
+    for face in blocks.chunks_exact(face_size) {
+      result.extend(crate::rgba::decode_image(self.header.format, face, width, height)?);
+    }
+    Ok(result)
+  }
+}
+
+impl DecodeSession<'static> {
+  /// `new` reading the whole file from `reader` first
+  pub fn read_from<R: std::io::Read>(mut reader: R) -> Result<Self, Error> {
+    let mut input = vec![];
+    reader.read_to_end(&mut input)?;
+    Self::new(input)
+  }
+
+  /// A job per level unpacking it wherever it's run, e.g. on `tokio::task::spawn_blocking` to keep
+  /// the decode off async executors; the jobs share the session. With the `async` feature
+  /// `tokio::spawn_levels` does just that.
+  pub fn level_jobs(self: &Arc<Self>) -> impl Iterator<Item = impl FnOnce() -> Result<Vec<u8>, Error> + Send + 'static> {
+    let session = self.clone();
+    (0..self.level_count()).map(move |idx| {
+      let session = session.clone();
+      move || session.unpack_level(idx)
+    })
+  }
+}
+
+#[test]
+fn test_level_jobs() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 32, 16) };
+  let input = gen.build().unwrap();
+  let session = Arc::new(DecodeSession::read_from(&input[..]).unwrap());
+  let handles: Vec<_> = session.level_jobs().map(std::thread::spawn).collect();
+  assert_eq!(handles.len(), 6);
+  for (handle, level) in handles.into_iter().zip(gen.blocks().unwrap()) {
+    assert_eq!(handle.join().unwrap().unwrap(), level);
+  }
+  assert!(DecodeSession::read_from(&input[1..]).is_err());
+}
@@ -0,0 +1,193 @@
+//! SSE2/NEON expansion of BC1 and BC3 blocks four at a time, the same texels as `rgba::decode_block`.
+//!
+//! Palettes are interpolated for two blocks per vector in 16-bit lanes, texels are then picked
+//! by comparing selectors (SSE2) or with a table lookup (NEON). BC3 alpha stays scalar.
+
+use crate::{Format, rgba::{alpha_block, rgb565}, unpack::BlockKind};
+
+/// formats `decode_blocks` handles
+pub(crate) fn supports(format: Format) -> bool {
+  matches!(BlockKind::of(format), Ok(BlockKind::Dxt1 | BlockKind::Dxt5))
+}
+
+/// decode the 4 consecutive blocks of `format` at the start of `input`
+pub(crate) fn decode_blocks(format: Format, input: &[u8], out: &mut [[[u8; 4]; 16]; 4]) {
+  let block_size = format.block_size();
+  let color = if format == Format::Dxt1 { 0 } else { 8 };
+  let blocks = [0, 1, 2, 3].map(|i| &input[i * block_size + color..i * block_size + color + 8]);
+  decode_color_blocks(blocks, format == Format::Dxt1, out);
+  if format != Format::Dxt1 {
+    let mut alpha = [0; 16];
+    for (i, texels) in out.iter_mut().enumerate() {
+      alpha_block(&input[i * block_size..], &mut alpha);
+      for (texel, &a) in texels.iter_mut().zip(&alpha) { texel[3] = a }
+    }
+  }
+}
+
+/// `rgba::decode_color_block` of 4 blocks, one by one without SSE2 or NEON
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) fn decode_color_blocks(blocks: [&[u8]; 4], dxt1: bool, out: &mut [[[u8; 4]; 16]; 4]) {
+  for (block, out) in blocks.iter().zip(out) {
+    crate::rgba::color_block(block, dxt1, out);
+  }
+}
+
+/// `rgba::decode_color_block` of 4 blocks
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub(crate) fn decode_color_blocks(blocks: [&[u8]; 4], dxt1: bool, out: &mut [[[u8; 4]; 16]; 4]) {
+  // endpoints as [r, g, b, 255] in 16-bit lanes, blocks 0 and 1 share a vector as do 2 and 3
+  let mut e0 = [[0u16; 8]; 2];
+  let mut e1 = [[0u16; 8]; 2];
+  // all ones for blocks in the 3-color mode
+  let mut punch = [[0u16; 8]; 2];
+  for (i, block) in blocks.iter().enumerate() {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let lanes = i % 2 * 4..i % 2 * 4 + 4;
+    for (e, c) in [(&mut e0[i / 2], c0), (&mut e1[i / 2], c1)] {
+      let [r, g, b] = rgb565(c);
+      e[lanes.clone()].copy_from_slice(&[r as u16, g as u16, b as u16, 255]);
+    }
+    if dxt1 && c0 <= c1 { punch[i / 2][lanes].copy_from_slice(&[0xffff; 4]) }
+  }
+  let selectors = blocks.map(|block| [block[4], block[5], block[6], block[7]]);
+  for pair in 0..2 {
+    let (a, b) = out[pair * 2..pair * 2 + 2].split_at_mut(1);
+    arch::decode_pair(&e0[pair], &e1[pair], &punch[pair], [&selectors[pair * 2], &selectors[pair * 2 + 1]], [&mut a[0], &mut b[0]]);
+  }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+  use std::arch::x86_64::*;
+
+  /// lanes of `x` in the order 0, 2, 1, 3
+  const EVEN_ODD: i32 = 2 << 2 | 1 << 4 | 3 << 6;
+
+  pub(super) fn decode_pair(e0: &[u16; 8], e1: &[u16; 8], punch: &[u16; 8], selectors: [&[u8; 4]; 2], out: [&mut [[u8; 4]; 16]; 2]) {
+    unsafe {
+      let e0 = _mm_loadu_si128(e0.as_ptr() as *const __m128i);
+      let e1 = _mm_loadu_si128(e1.as_ptr() as *const __m128i);
+      let punch = _mm_loadu_si128(punch.as_ptr() as *const __m128i);
+      // x / 3 == (x * 0xaaab) >> 17 for any x below 2^16
+      let third = _mm_set1_epi16(0xaaabu16 as i16);
+      let p2 = _mm_srli_epi16::<1>(_mm_mulhi_epu16(_mm_add_epi16(_mm_add_epi16(e0, e0), e1), third));
+      let p3 = _mm_srli_epi16::<1>(_mm_mulhi_epu16(_mm_add_epi16(_mm_add_epi16(e1, e1), e0), third));
+      let half = _mm_srli_epi16::<1>(_mm_add_epi16(e0, e1));
+      let p2 = _mm_or_si128(_mm_and_si128(punch, half), _mm_andnot_si128(punch, p2));
+      let p3 = _mm_andnot_si128(punch, p3);
+      // [p0, p1] and [p2, p3] of both blocks interleaved, then one palette per block
+      let x = _mm_shuffle_epi32::<EVEN_ODD>(_mm_packus_epi16(e0, e1));
+      let y = _mm_shuffle_epi32::<EVEN_ODD>(_mm_packus_epi16(p2, p3));
+      let [out0, out1] = out;
+      select(_mm_unpacklo_epi64(x, y), selectors[0], out0);
+      select(_mm_unpackhi_epi64(x, y), selectors[1], out1);
+    }
+  }
+
+  unsafe fn select(palette: __m128i, selector: &[u8; 4], out: &mut [[u8; 4]; 16]) {
+    let entries = [
+      _mm_shuffle_epi32::<0x00>(palette), _mm_shuffle_epi32::<0x55>(palette),
+      _mm_shuffle_epi32::<0xaa>(palette), _mm_shuffle_epi32::<0xff>(palette),
+    ];
+    for (row, &s) in out.chunks_exact_mut(4).zip(selector) {
+      let s = s as i32;
+      let index = _mm_setr_epi32(s & 3, s >> 2 & 3, s >> 4 & 3, s >> 6 & 3);
+      let mut texels = _mm_setzero_si128();
+      for (k, &entry) in entries.iter().enumerate() {
+        texels = _mm_or_si128(texels, _mm_and_si128(entry, _mm_cmpeq_epi32(index, _mm_set1_epi32(k as i32))));
+      }
+      _mm_storeu_si128(row.as_mut_ptr() as *mut __m128i, texels);
+    }
+  }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+  use std::arch::aarch64::*;
+
+  pub(super) fn decode_pair(e0: &[u16; 8], e1: &[u16; 8], punch: &[u16; 8], selectors: [&[u8; 4]; 2], out: [&mut [[u8; 4]; 16]; 2]) {
+    unsafe {
+      let e0 = vld1q_u16(e0.as_ptr());
+      let e1 = vld1q_u16(e1.as_ptr());
+      let punch = vld1q_u16(punch.as_ptr());
+      // x / 3 == (x * 0xaaab) >> 17 for any x below 2^16
+      let third = |x: uint16x8_t| {
+        let lo = vshrn_n_u32::<16>(vmull_u16(vget_low_u16(x), vdup_n_u16(0xaaab)));
+        let hi = vshrn_n_u32::<16>(vmull_high_u16(x, vdupq_n_u16(0xaaab)));
+        vshrq_n_u16::<1>(vcombine_u16(lo, hi))
+      };
+      let p2 = third(vaddq_u16(vaddq_u16(e0, e0), e1));
+      let p3 = third(vaddq_u16(vaddq_u16(e1, e1), e0));
+      let p2 = vbslq_u16(punch, vhaddq_u16(e0, e1), p2);
+      let p3 = vbicq_u16(p3, punch);
+      // [p0, p1] and [p2, p3] of both blocks interleaved, then one palette per block
+      let x = vreinterpretq_u32_u8(vcombine_u8(vqmovn_u16(e0), vqmovn_u16(e1)));
+      let y = vreinterpretq_u32_u8(vcombine_u8(vqmovn_u16(p2), vqmovn_u16(p3)));
+      let [out0, out1] = out;
+      select(vreinterpretq_u8_u32(vuzp1q_u32(x, y)), selectors[0], out0);
+      select(vreinterpretq_u8_u32(vuzp2q_u32(x, y)), selectors[1], out1);
+    }
+  }
+
+  unsafe fn select(palette: uint8x16_t, selector: &[u8; 4], out: &mut [[u8; 4]; 16]) {
+    for (row, &s) in out.chunks_exact_mut(4).zip(selector) {
+      // byte c of texel with index k comes from byte 4k+c of the palette
+      let index = [0, 2, 4, 6].map(|shift| (s as u32 >> shift & 3) * 0x0404_0404 + 0x0302_0100);
+      let texels = vqtbl1q_u8(palette, vreinterpretq_u8_u32(vld1q_u32(index.as_ptr())));
+      vst1q_u8(row.as_mut_ptr() as *mut u8, texels);
+    }
+  }
+}
+
+#[test]
+fn test_decode_blocks() {
+  use crate::rgba::decode_block;
+  let mut seed = 0x2545_f491_4f6c_dd1du64;
+  let mut input = vec![0u8; 16 * 4];
+  for round in 0..256 {
+    for byte in &mut input {
+      seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17;
+      *byte = seed as u8;
+    }
+    for format in [Format::Dxt1, Format::Dxt5] {
+      assert!(supports(format));
+      let block_size = format.block_size();
+      let mut input = input.clone();
+      // equal and swapped endpoints for the 3-color mode, extremes to check the division
+      for i in 0..4 {
+        let color = &mut input[(i + 1) * block_size - 8..(i + 1) * block_size - 4];
+        match (round + i) % 4 {
+          1 => { color[2] = color[0]; color[3] = color[1] }
+          2 => color.copy_from_slice(&[0xff, 0xff, 0, 0]),
+          3 => color.copy_from_slice(&[0, 0, 0xff, 0xff]),
+          _ => {}
+        }
+      }
+      let mut simd = [[[0; 4]; 16]; 4];
+      decode_blocks(format, &input, &mut simd);
+      for (i, simd) in simd.iter().enumerate() {
+        let mut scalar = [[0; 4]; 16];
+        decode_block(format, &input[i * block_size..(i + 1) * block_size], &mut scalar).unwrap();
+        assert_eq!(simd, &scalar, "{:?} block {} of {:02x?}", format, i, input);
+      }
+    }
+  }
+}
+
+#[test]
+fn test_decode_image() {
+  use crate::rgba::{decode_block, decode_image};
+  // a row of 5 blocks, 4 expanded together and the last one alone, cut at 18 texels wide
+  let blocks = (0..5 * 16u32).map(|i| (i.wrapping_mul(2654435761) >> 11) as u8).collect::<Vec<_>>();
+  let image = decode_image(Format::Dxt5, &blocks, 18, 3).unwrap();
+  for (b, block) in blocks.chunks(16).enumerate() {
+    let mut texels = [[0; 4]; 16];
+    decode_block(Format::Dxt5, block, &mut texels).unwrap();
+    for (i, texel) in texels.iter().enumerate() {
+      let (x, y) = (b * 4 + i % 4, i / 4);
+      if x < 18 && y < 3 { assert_eq!(&image[(y * 18 + x) * 4..][..4], texel) }
+    }
+  }
+}
@@ -0,0 +1,446 @@
+//! Opt-in decode telemetry: walks the level streams without assembling blocks and counts
+//! how many symbols and bits every table accounts for.
+
+use crate::error::{Context, CrnError as Error};
+use crate::{Header, Palette, Tables, codec::Symbol, symbols::{components, read_level}, unpack::{Dxt1, Kind, Unpack}};
+
+/// symbols and bits read from one table
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+  pub symbols: usize,
+  pub bits: usize,
+}
+
+impl Counter {
+  pub(crate) fn add(&mut self, bits: usize) {
+    self.symbols += 1;
+    self.bits += bits;
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteStats {
+  pub entries: usize,
+  /// entries referenced by any level, the rest only cost space
+  pub used: usize,
+  /// size of the palette stream, which is always consumed completely
+  pub bits: usize,
+}
+
+impl From<Palette> for PaletteStats {
+  fn from(palette: Palette) -> Self {
+    Self { entries: palette.count as usize, used: 0, bits: palette.size as usize * 8 }
+  }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LevelStats {
+  pub width: u16,
+  pub height: u16,
+  pub chunks: usize,
+  /// how many chunks used each of the 8 tile layouts of `Unpack::TILES`
+  pub tile_layouts: [usize; 8],
+  /// bits consumed by the level stream
+  pub bits: usize,
+  pub chunk_encoding: Counter,
+  pub color_endpoint: Counter,
+  pub color_selector: Counter,
+  pub alpha_endpoint: Counter,
+  pub alpha_selector: Counter,
+}
+
+impl LevelStats {
+  /// average number of distinct endpoints per chunk, 1.0 means every chunk shares a single endpoint
+  pub fn mean_tile_count(&self) -> f64 {
+    if self.chunks == 0 { return 0.0 }
+    let tiles: usize = self.tile_layouts.iter().zip(&Dxt1::COUNT_TILES).map(|(n, c)| n * c).sum();
+    tiles as f64 / self.chunks as f64
+  }
+
+  pub fn symbols(&self) -> usize {
+    self.chunk_encoding.symbols +
+    self.color_endpoint.symbols + self.color_selector.symbols +
+    self.alpha_endpoint.symbols + self.alpha_selector.symbols
+  }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DecodeStats {
+  pub table_bits: usize,
+  pub color_endpoints: PaletteStats,
+  pub color_selectors: PaletteStats,
+  pub alpha_endpoints: PaletteStats,
+  pub alpha_selectors: PaletteStats,
+  pub levels: Vec<LevelStats>,
+}
+
+impl DecodeStats {
+  /// walk every level of `input` and count symbols and bits per table
+  pub fn collect<S: Symbol>(header: &Header, tables: &Tables<S>, input: &[u8]) -> Result<Self, Error> {
+    let palettes = [header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors];
+    let mut used = palettes.map(|p| vec![false; p.count as usize]);
+    let (endpoint_kinds, selector_kinds) = components(header.format)?;
+    let levels = (0..header.level_count as usize).map(|idx| {
+      let symbols = read_level(header, tables, input, idx).with_context(|| format!("read level {}", idx))?;
+      let kinds = endpoint_kinds.iter().zip(&symbols.endpoints).chain(selector_kinds.iter().zip(&symbols.selectors));
+      for (kind, indices) in kinds {
+        let used = &mut used[kind.index()];
+        for &i in indices {
+          if let Some(u) = used.get_mut(i) { *u = true }
+        }
+      }
+      let (width, height) = header.get_level_info(idx).ok_or(Error::level(idx))?;
+      let mut tile_layouts = [0; 8];
+      for &tile in &symbols.tiles { tile_layouts[tile as usize] += 1 }
+      Ok(LevelStats {
+        width, height,
+        chunks: symbols.tiles.len(),
+        tile_layouts,
+        bits: symbols.bits,
+        chunk_encoding: symbols.chunk_encoding,
+        color_endpoint: symbols.deltas[Kind::ColorEndpoint.index()],
+        color_selector: symbols.deltas[Kind::ColorSelector.index()],
+        alpha_endpoint: symbols.deltas[Kind::AlphaEndpoint.index()],
+        alpha_selector: symbols.deltas[Kind::AlphaSelector.index()],
+      })
+    }).collect::<Result<Vec<_>, Error>>()?;
+    let [color_endpoints, color_selectors, alpha_endpoints, alpha_selectors] = std::array::from_fn(|i| PaletteStats {
+      used: used[i].iter().filter(|&&u| u).count(),
+      ..palettes[i].into()
+    });
+    Ok(Self {
+      table_bits: header.table_size as usize * 8,
+      color_endpoints, color_selectors, alpha_endpoints, alpha_selectors,
+      levels,
+    })
+  }
+
+  pub fn symbols(&self) -> usize {
+    self.levels.iter().map(LevelStats::symbols).sum()
+  }
+
+  /// tile layout histogram over all levels
+  pub fn tile_layouts(&self) -> [usize; 8] {
+    let mut result = [0; 8];
+    for level in &self.levels {
+      for (r, n) in result.iter_mut().zip(&level.tile_layouts) { *r += n }
+    }
+    result
+  }
+}
+
+/// Bits every stream of a level takes up, split at the codec positions `read_level` records.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamBits {
+  pub chunk_encoding: usize,
+  pub color_endpoint: usize,
+  pub color_selector: usize,
+  pub alpha_endpoint: usize,
+  pub alpha_selector: usize,
+  /// after the last symbol up to the end of the level data
+  pub padding: usize,
+}
+
+impl StreamBits {
+  pub fn total(&self) -> usize {
+    self.chunk_encoding +
+    self.color_endpoint + self.color_selector +
+    self.alpha_endpoint + self.alpha_selector +
+    self.padding
+  }
+}
+
+/// Where the bits of a file go: header, palettes and tables once, then every level stream by
+/// component. Adds up to the file size for files laid out the way crunch writes them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompressionBreakdown {
+  /// the header with the level offsets
+  pub header: usize,
+  pub color_endpoints: usize,
+  pub color_selectors: usize,
+  pub alpha_endpoints: usize,
+  pub alpha_selectors: usize,
+  pub table: usize,
+  pub levels: Vec<StreamBits>,
+}
+
+impl CompressionBreakdown {
+  pub fn collect<S: Symbol>(header: &Header, tables: &Tables<S>, input: &[u8]) -> Result<Self, Error> {
+    Self::from_stats(header, input, &DecodeStats::collect(header, tables, input)?)
+  }
+
+  /// the breakdown of `stats` collected from `input`
+  pub fn from_stats(header: &Header, input: &[u8], stats: &DecodeStats) -> Result<Self, Error> {
+    let levels = stats.levels.iter().enumerate().map(|(idx, level)| Ok(StreamBits {
+      chunk_encoding: level.chunk_encoding.bits,
+      color_endpoint: level.color_endpoint.bits,
+      color_selector: level.color_selector.bits,
+      alpha_endpoint: level.alpha_endpoint.bits,
+      alpha_selector: level.alpha_selector.bits,
+      padding: (header.get_level_data(input, idx)?.len() * 8).saturating_sub(level.bits),
+    })).collect::<Result<Vec<_>, Error>>()?;
+    Ok(Self {
+      header: header.header_size as usize * 8,
+      color_endpoints: stats.color_endpoints.bits,
+      color_selectors: stats.color_selectors.bits,
+      alpha_endpoints: stats.alpha_endpoints.bits,
+      alpha_selectors: stats.alpha_selectors.bits,
+      table: stats.table_bits,
+      levels,
+    })
+  }
+
+  /// every level stream added up by component
+  pub fn streams(&self) -> StreamBits {
+    self.levels.iter().fold(StreamBits::default(), |sum, l| StreamBits {
+      chunk_encoding: sum.chunk_encoding + l.chunk_encoding,
+      color_endpoint: sum.color_endpoint + l.color_endpoint,
+      color_selector: sum.color_selector + l.color_selector,
+      alpha_endpoint: sum.alpha_endpoint + l.alpha_endpoint,
+      alpha_selector: sum.alpha_selector + l.alpha_selector,
+      padding: sum.padding + l.padding,
+    })
+  }
+
+  pub fn total(&self) -> usize {
+    self.header +
+    self.color_endpoints + self.color_selectors +
+    self.alpha_endpoints + self.alpha_selectors +
+    self.table + self.streams().total()
+  }
+}
+
+/// How often every palette delta of one kind occurs in a level and the bits spent on them, to
+/// compare against the entropy of the level's own distribution.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeltaHistogram {
+  /// occurrences of every delta, indexed by the delta, as long as the palette
+  pub counts: Vec<usize>,
+  /// bits the level stream spent on the deltas
+  pub bits: usize,
+}
+
+impl DeltaHistogram {
+  pub fn symbols(&self) -> usize {
+    self.counts.iter().sum()
+  }
+
+  /// Shannon entropy of the deltas in bits per symbol
+  pub fn entropy(&self) -> f64 {
+    let total = self.symbols() as f64;
+    self.counts.iter().filter(|&&n| n != 0).map(|&n| n as f64 / total * (total / n as f64).log2()).sum()
+  }
+
+  /// `symbols * entropy`, no code of single deltas spends less, not even one fitted to this
+  /// level alone
+  pub fn bound(&self) -> f64 {
+    self.symbols() as f64 * self.entropy()
+  }
+
+  /// `bits` over `bound`, 1.0 at the bound, higher as the shared tables fit this level worse
+  pub fn overhead(&self) -> f64 {
+    let bound = self.bound();
+    if bound == 0.0 { return if self.bits == 0 { 1.0 } else { f64::INFINITY } }
+    self.bits as f64 / bound
+  }
+}
+
+/// The deltas of every palette a level references, see [`DeltaHistogram`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LevelEntropy {
+  pub color_endpoint: DeltaHistogram,
+  pub color_selector: DeltaHistogram,
+  pub alpha_endpoint: DeltaHistogram,
+  pub alpha_selector: DeltaHistogram,
+}
+
+impl LevelEntropy {
+  /// walk level `idx` and recover the delta of every palette index from the one before it
+  pub fn collect<S: Symbol>(header: &Header, tables: &Tables<S>, input: &[u8], idx: usize) -> Result<Self, Error> {
+    let symbols = read_level(header, tables, input, idx).with_context(|| format!("read level {}", idx))?;
+    let (endpoint_kinds, selector_kinds) = components(header.format)?;
+    let palettes = [header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors];
+    let mut histograms: [DeltaHistogram; 4] = std::array::from_fn(|i| DeltaHistogram {
+      counts: vec![0; palettes[i].count as usize],
+      bits: symbols.deltas[i].bits,
+    });
+    let kinds = endpoint_kinds.iter().zip(&symbols.endpoints).chain(selector_kinds.iter().zip(&symbols.selectors));
+    for (kind, indices) in kinds {
+      let counts = &mut histograms[kind.index()].counts;
+      let len = counts.len();
+      // every component runs its own index from 0
+      let mut last = 0;
+      for &i in indices {
+        counts[(i + len - last) % len] += 1;
+        last = i;
+      }
+    }
+    let [color_endpoint, color_selector, alpha_endpoint, alpha_selector] = histograms;
+    Ok(Self { color_endpoint, color_selector, alpha_endpoint, alpha_selector })
+  }
+}
+
+/// colors of the 8 tile layouts of `Unpack::TILES` in a [`TileOverlay`]
+pub const TILE_COLORS: [[u8; 4]; 8] = [
+  [128, 128, 128, 255],
+  [255, 0, 0, 255], [0, 255, 0, 255],
+  [0, 0, 255, 255], [255, 255, 0, 255],
+  [0, 255, 255, 255], [255, 0, 255, 255],
+  [255, 255, 255, 255],
+];
+
+/// RGBA image of the tile layout every chunk of a level used, one pixel per texel with the
+/// chunk grid padded to whole chunks and faces stacked vertically.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TileOverlay {
+  pub width: usize,
+  pub height: usize,
+  pub rgba: Vec<u8>,
+}
+
+impl TileOverlay {
+  pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+    if x >= self.width || y >= self.height { return None }
+    let i = (y * self.width + x) * 4;
+    let mut result = [0; 4];
+    result.copy_from_slice(&self.rgba[i..i + 4]);
+    Some(result)
+  }
+
+  /// render the chunk encoding of level `idx`, chunks are colored by their layout in `TILE_COLORS`
+  /// and the blocks of a chunk get darker with the index of the endpoint they use
+  pub fn render<S: Symbol>(header: &Header, tables: &Tables<S>, input: &[u8], idx: usize) -> Result<Self, Error> {
+    let symbols = read_level(header, tables, input, idx).with_context(|| format!("read level {}", idx))?;
+    let (width, height) = header.get_level_info(idx).ok_or(Error::level(idx))?;
+    let trunk = Dxt1::TRUNK_SIZE;
+    let chunk_x = (width.div_ceil(4) as usize).div_ceil(trunk);
+    let chunk_y = (height.div_ceil(4) as usize).div_ceil(trunk);
+    let chunk_size = trunk * 4;
+    let mut result = Self { width: chunk_x * chunk_size, height: chunk_y * chunk_size * header.face_count as usize, rgba: vec![] };
+    result.rgba = vec![0; result.width * result.height * 4];
+    for (n, &tile) in symbols.tiles.iter().enumerate() {
+      // chunk rows run in zig-zag, odd rows of each face right to left
+      let y = n / chunk_x;
+      let face_row = y % chunk_y;
+      let x = if face_row & 1 == 1 { chunk_x - 1 - n % chunk_x } else { n % chunk_x };
+      for (i, &endpoint) in Dxt1::TILES[tile as usize].iter().enumerate() {
+        let mut color = TILE_COLORS[tile as usize];
+        for c in &mut color[..3] { *c = (*c as usize * (4 - endpoint) / 4) as u8 }
+        let (bx, by) = ((x * trunk + i % trunk) * 4, (y * trunk + i / trunk) * 4);
+        for py in by..by + 4 {
+          for px in bx..bx + 4 {
+            let p = (py * result.width + px) * 4;
+            result.rgba[p..p + 4].copy_from_slice(&color);
+          }
+        }
+      }
+    }
+    Ok(result)
+  }
+}
+
+#[test]
+fn test_decode_stats() {
+  let (input, header, tables) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  let stats = DecodeStats::collect(&header, &tables, &input).unwrap();
+  assert_eq!(stats.levels.iter().map(|l| l.chunks).collect::<Vec<_>>(), vec![2, 1]);
+  // one chunk encoding for the pair of chunks, a selector for every block of both
+  assert_eq!(stats.levels[0].chunk_encoding.symbols, 1);
+  assert_eq!(stats.levels[0].color_selector.symbols, 8);
+  assert_eq!(stats.levels[0].color_endpoint.symbols, read_level(&header, &tables, &input, 0).unwrap().endpoints[0].len());
+  assert_eq!(stats.symbols(), stats.levels[0].symbols() + stats.levels[1].symbols());
+  assert_eq!(stats.color_endpoints.entries, header.color_endpoints.count as usize);
+}
+
+#[test]
+fn test_tile_layouts() {
+  let (input, header, tables) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  let stats = DecodeStats::collect(&header, &tables, &input).unwrap();
+  let mut total = [0; 8];
+  for (l, level) in stats.levels.iter().enumerate() {
+    let mut layouts = [0; 8];
+    for &tile in &read_level(&header, &tables, &input, l).unwrap().tiles { layouts[tile as usize] += 1 }
+    assert_eq!(level.tile_layouts, layouts);
+    // every tile takes one endpoint
+    assert_eq!(level.mean_tile_count(), level.color_endpoint.symbols as f64 / level.chunks as f64);
+    for (t, n) in total.iter_mut().zip(&layouts) { *t += n }
+  }
+  assert_eq!(stats.tile_layouts(), total);
+}
+
+#[test]
+fn test_tile_overlay() {
+  use crate::{Format, testgen::TestCrn};
+  let (input, header, tables) = TestCrn::sample().parsed().unwrap();
+  let overlay = TileOverlay::render(&header, &tables, &input, 0).unwrap();
+  // 3x2 blocks padded to 2x1 chunks
+  assert_eq!((overlay.width, overlay.height, overlay.rgba.len()), (16, 8, 16 * 8 * 4));
+  assert_eq!(overlay.pixel(16, 0), None);
+
+  // 2x3 chunks per face: the zig-zag starts over left to right on every face
+  let crn = TestCrn { faces: 6, levels: Some(1), ..TestCrn::new(Format::Dxt1, 16, 24) };
+  let mut blocks = crn.blocks().unwrap().remove(0);
+  // the top left chunk of faces 0 and 1 is a single block repeated, which takes layout 0
+  for face in [0, 4 * 6 * 8] {
+    for (x, y) in [(1, 0), (0, 1), (1, 1)] {
+      blocks.copy_within(face..face + 8, face + (y * 4 + x) * 8);
+    }
+  }
+  let input = crate::encode::encode(Format::Dxt1, 16, 24, 6, &[&blocks]).unwrap();
+  let header = Header::parse(&input).unwrap();
+  let tables = header.get_table(&input).unwrap();
+  let overlay = TileOverlay::render(&header, &tables, &input, 0).unwrap();
+  assert_eq!((overlay.width, overlay.height), (16, 24 * 6));
+  let layout0 = |x0: usize, y0: usize| (y0..y0 + 8).all(|y| (x0..x0 + 8).all(|x| overlay.pixel(x, y) == Some(TILE_COLORS[0])));
+  assert!(layout0(0, 0) && layout0(0, 24));
+  assert!(!layout0(8, 0) && !layout0(8, 24));
+}
+
+#[test]
+fn test_palette_used() {
+  let (input, header, tables) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  let stats = DecodeStats::collect(&header, &tables, &input).unwrap();
+  // the entries compaction keeps
+  let compacted = crate::compact::compact_palettes(&input).unwrap();
+  let used = [stats.color_endpoints, stats.color_selectors, stats.alpha_endpoints, stats.alpha_selectors].map(|p| (p.entries, p.used));
+  assert_eq!(used, compacted.counts.map(|(n, used)| (n as usize, used as usize)));
+}
+
+#[test]
+fn test_entropy() {
+  use crate::{Format, testgen::TestCrn};
+  let (input, header, tables) = TestCrn { faces: 2, palette_size: 7, ..TestCrn::new(Format::DxnXY, 32, 16) }.parsed().unwrap();
+  let stats = DecodeStats::collect(&header, &tables, &input).unwrap();
+  for idx in 0..header.level_count as usize {
+    let entropy = LevelEntropy::collect(&header, &tables, &input, idx).unwrap();
+    assert_eq!((entropy.color_endpoint.symbols(), entropy.color_selector.bits), (0, 0));
+    for (histogram, counter, delta) in [
+      (&entropy.alpha_endpoint, stats.levels[idx].alpha_endpoint, &tables.alpha_endpoint.as_ref().unwrap().delta),
+      (&entropy.alpha_selector, stats.levels[idx].alpha_selector, &tables.alpha_selector.as_ref().unwrap().delta),
+    ] {
+      assert_eq!((histogram.symbols(), histogram.bits), (counter.symbols, counter.bits));
+      // the bits spent are the code lengths of the deltas found
+      let bits: usize = histogram.counts.iter().enumerate().map(|(d, &n)| if n == 0 { 0 } else { n * delta.code(d as u32).unwrap().1 }).sum();
+      assert_eq!(bits, histogram.bits, "level {}", idx);
+      assert!(histogram.entropy() <= (histogram.counts.len() as f64).log2() + 1e-9);
+      assert!(histogram.overhead() >= 1.0 - 1e-9, "{:?}", histogram);
+    }
+  }
+  let skewed = DeltaHistogram { counts: vec![2, 1, 1, 0], bits: 6 };
+  assert_eq!((skewed.entropy(), skewed.bound(), skewed.overhead()), (1.5, 6.0, 1.0));
+  assert_eq!(DeltaHistogram::default().overhead(), 1.0);
+}
+
+#[test]
+fn test_breakdown() {
+  use crate::{Format, testgen::TestCrn};
+  let (input, header, tables) = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 24, 20) }.parsed().unwrap();
+  let stats = DecodeStats::collect(&header, &tables, &input).unwrap();
+  let breakdown = CompressionBreakdown::from_stats(&header, &input, &stats).unwrap();
+  assert_eq!(breakdown, CompressionBreakdown::collect(&header, &tables, &input).unwrap());
+  assert_eq!(breakdown.total(), input.len() * 8);
+  assert_eq!((breakdown.header, breakdown.table, breakdown.alpha_selectors), (header.header_size as usize * 8, header.table_size as usize * 8, header.alpha_selectors.size as usize * 8));
+  let streams = breakdown.streams();
+  assert_eq!((streams.color_endpoint, streams.alpha_selector), (stats.levels.iter().map(|l| l.color_endpoint.bits).sum(), stats.levels.iter().map(|l| l.alpha_selector.bits).sum()));
+  assert!(breakdown.levels.iter().all(|l| l.padding < 8), "{:?}", breakdown.levels);
+}
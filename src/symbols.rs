@@ -0,0 +1,126 @@
+//! Symbol level view of the level streams: which tile layout and palette indices every chunk uses,
+//! without assembling any blocks.
+
+use crate::error::{Context, CrnError as Error, bail, ensure};
+use crate::{Format, Header, Tables, codec::{Codec, Symbol}, stats::Counter, unpack::{Blocks, Kind, Unpack}};
+
+/// palettes used by (endpoints, selectors) of each block, in the order they appear in the stream
+pub(crate) fn components(format: Format) -> Result<(&'static [Kind], &'static [Kind]), Error> {
+  Ok(crate::unpack::dispatch!(format, B => (B::ENDPOINTS, B::SELECTORS)))
+}
+
+/// symbols of a level in stream order, with palette indices resolved
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LevelSymbols {
+  /// tile layout index (into `Unpack::TILES`) of every chunk
+  pub tiles: Vec<u8>,
+  /// palette index per endpoint component, `COUNT_TILES[tile]` entries per chunk
+  pub endpoints: Vec<Vec<usize>>,
+  /// palette index per selector component, 4 entries per chunk
+  pub selectors: Vec<Vec<usize>>,
+  pub chunk_encoding: Counter,
+  /// indexed by `Kind::index`
+  pub deltas: [Counter; 4],
+  /// bits consumed by the whole level
+  pub bits: usize,
+}
+
+pub(crate) fn chunk_count(header: &Header, idx: usize) -> Result<usize, Error> {
+  let (width, height) = header.get_level_info(idx).ok_or(Error::level(idx))?;
+  let chunk_x = width.div_ceil(4).div_ceil(2) as usize;
+  let chunk_y = height.div_ceil(4).div_ceil(2) as usize;
+  Ok(chunk_x * chunk_y * header.face_count as usize)
+}
+
+pub(crate) fn read_level<S: Symbol>(header: &Header, tables: &Tables<S>, input: &[u8], idx: usize) -> Result<LevelSymbols, Error> {
+  type U = crate::unpack::Dxt1;
+  let (endpoint_kinds, selector_kinds) = components(header.format)?;
+  let mut codec = Codec::new(header.get_level_data(input, idx)?);
+  let mut result = LevelSymbols {
+    endpoints: vec![vec![]; endpoint_kinds.len()],
+    selectors: vec![vec![]; selector_kinds.len()],
+    ..Default::default()
+  };
+  let mut endpoint_index = vec![0; endpoint_kinds.len()];
+  let mut selector_index = vec![0; selector_kinds.len()];
+  let mut tile_bits = 1u32;
+  for _ in 0..chunk_count(header, idx)? {
+    if tile_bits == 1 {
+      let start = codec.current();
+      tile_bits = tables.chunk_encoding.next(&mut codec).context("read chunk encoding bits")?.into() | 512;
+      result.chunk_encoding.add(codec.current() - start);
+    }
+    let tile = tile_bits as usize & 7;
+    tile_bits >>= 3;
+    result.tiles.push(tile as u8);
+    for (c, &kind) in endpoint_kinds.iter().enumerate() {
+      for _ in 0..U::COUNT_TILES[tile] {
+        let start = codec.current();
+        tables.next_index(kind, &mut codec, &mut endpoint_index[c]).context("read endpoint delta")?;
+        result.deltas[kind.index()].add(codec.current() - start);
+        result.endpoints[c].push(endpoint_index[c]);
+      }
+    }
+    for _ in 0..U::TRUNK_SIZE * U::TRUNK_SIZE {
+      for (c, &kind) in selector_kinds.iter().enumerate() {
+        let start = codec.current();
+        tables.next_index(kind, &mut codec, &mut selector_index[c]).context("read selector delta")?;
+        result.deltas[kind.index()].add(codec.current() - start);
+        result.selectors[c].push(selector_index[c]);
+      }
+    }
+  }
+  if !codec.is_complete() { bail!(Error::TrailingData) }
+  result.bits = codec.current();
+  Ok(result)
+}
+
+/// Second pass of a two-phase decode: materialize the blocks of a level from the palette indices
+/// `read_level` resolved. Chunk rows are independent, so with the `parallel` feature they are
+/// assembled on all cores. Blocks of chunks crossing the right or bottom edge are left default,
+/// the same as `Unpack::unpack_rows`.
+pub(crate) fn assemble<U: Unpack, S: Symbol>(tables: &Tables<S>, symbols: &LevelSymbols, width: u16, height: u16, faces: usize) -> Result<Blocks<U>, Error> {
+  let block_x = width.div_ceil(4) as usize;
+  let block_y = height.div_ceil(4) as usize;
+  let chunk_x = block_x.div_ceil(U::TRUNK_SIZE);
+  let chunk_y = block_y.div_ceil(U::TRUNK_SIZE);
+  ensure!(symbols.tiles.len() == chunk_x * chunk_y * faces, "chunk count {} != {}", symbols.tiles.len(), chunk_x * chunk_y * faces);
+  // index of the first endpoint of every chunk
+  let mut endpoint_offsets = Vec::with_capacity(symbols.tiles.len());
+  let mut offset = 0;
+  for &tile in &symbols.tiles {
+    endpoint_offsets.push(offset);
+    offset += U::COUNT_TILES[tile as usize];
+  }
+
+  let mut result = Blocks::new(block_x, block_y, faces);
+  let chunk_rows: Vec<(usize, usize, &mut [U])> = result.blocks.chunks_mut((block_x * block_y).max(1)).enumerate()
+    .flat_map(|(f, face)| face.chunks_mut((block_x * U::TRUNK_SIZE).max(1)).enumerate().map(move |(y, row)| (f, y, row)))
+    .collect();
+  let fill = |(f, y, row): (usize, usize, &mut [U])| -> Result<(), Error> {
+    if block_y & 1 == 1 && y == chunk_y - 1 { return Ok(()) }
+    for step in 0..chunk_x {
+      let x = if y & 1 == 1 { chunk_x - 1 - step } else { step };
+      if block_x & 1 == 1 && x == chunk_x - 1 { continue }
+      let chunk = (f * chunk_y + y) * chunk_x + step;
+      let tile = &U::TILES[symbols.tiles[chunk] as usize];
+      for (i, &t) in tile.iter().enumerate() {
+        let mut endpoints = [0; 2];
+        let mut selectors = [0; 2];
+        for (e, indices) in endpoints.iter_mut().zip(&symbols.endpoints) { *e = indices[endpoint_offsets[chunk] + t] }
+        for (s, indices) in selectors.iter_mut().zip(&symbols.selectors) { *s = indices[chunk * tile.len() + i] }
+        let (endpoints, selectors) = (&endpoints[..symbols.endpoints.len()], &selectors[..symbols.selectors.len()]);
+        row[i / U::TRUNK_SIZE * block_x + x * U::TRUNK_SIZE + i % U::TRUNK_SIZE] = U::from_indices(tables, endpoints, selectors)?;
+      }
+    }
+    Ok(())
+  };
+  #[cfg(feature = "parallel")]
+  {
+    use rayon::prelude::*;
+    chunk_rows.into_par_iter().try_for_each(fill)?;
+  }
+  #[cfg(not(feature = "parallel"))]
+  chunk_rows.into_iter().try_for_each(fill)?;
+  Ok(result)
+}
@@ -0,0 +1,121 @@
+//! Small valid crn files built from seeded random palettes, for unit tests, fuzz seeds and
+//! benchmarks that shouldn't depend on sample assets.
+//!
+//! Every palette gets `palette_size` random entries and every block picks its parts from those,
+//! so chunks end up with all kinds of tile layouts. The same `TestCrn` always gives the same file.
+
+use crate::error::{CrnError as Error, bail};
+use crate::{Format, Header, Tables, encode::encode, mipmap::level_count, unpack::{Block, BlockKind, Dxn, Dxt1, Dxt5, Dxt5A}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestCrn {
+  pub format: Format,
+  pub width: u16,
+  pub height: u16,
+  pub faces: u8,
+  /// levels from the base down, `None` for the full chain to 1x1
+  pub levels: Option<usize>,
+  /// random entries of each palette the blocks pick from
+  pub palette_size: usize,
+  pub seed: u64,
+}
+
+/// xorshift64, enough to scatter palette entries
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+  fn below(&mut self, n: usize) -> usize {
+    (self.next() % n as u64) as usize
+  }
+  fn bytes<const N: usize>(&mut self) -> [u8; N] {
+    std::array::from_fn(|_| self.next() as u8)
+  }
+}
+
+impl TestCrn {
+  /// a single face with the full mip chain and 16 entries per palette
+  pub fn new(format: Format, width: u16, height: u16) -> Self {
+    Self { format, width, height, faces: 1, levels: None, palette_size: 16, seed: 1 }
+  }
+
+  /// 12x8 with two levels and 4 entries per palette, the common fixture of the crate's tests:
+  /// level 0 is 3x2 blocks in 2x1 chunks, the right one over the odd column, level 1 one chunk
+  pub fn sample() -> Self {
+    Self { levels: Some(2), palette_size: 4, ..Self::new(Format::Dxt1, 12, 8) }
+  }
+
+  pub fn level_count(&self) -> usize {
+    self.levels.unwrap_or_else(|| level_count(self.width, self.height))
+  }
+
+  /// The blocks of every level as `Header::unpack_level` returns them from the built file, the
+  /// blocks of chunks on an odd right or bottom edge, which the decoder skips, left zeroed.
+  pub fn blocks(&self) -> Result<Vec<Vec<u8>>, Error> {
+    let block_size = self.format.block_size();
+    let mut rng = Rng(self.seed ^ 0x9e37_79b9_7f4a_7c15);
+    let size = self.palette_size.max(1);
+    let colors: Vec<((u16, u16), [u8; 4])> = (0..size).map(|_| ((rng.next() as u16, rng.next() as u16), rng.bytes())).collect();
+    let alphas: Vec<((u8, u8), [u8; 6])> = (0..size).map(|_| ((rng.next() as u8, rng.next() as u8), rng.bytes())).collect();
+    let pick = |rng: &mut Rng| (colors[rng.below(size)].0, colors[rng.below(size)].1, alphas[rng.below(size)], alphas[rng.below(size)]);
+    (0..self.level_count()).map(|l| {
+      let (width, height) = (1.max(self.width >> l.min(15)), 1.max(self.height >> l.min(15)));
+      let (block_x, block_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+      let mut level = vec![0; crate::unpack::level_size(width, height, self.faces, block_size)?];
+      for (b, out) in level.chunks_exact_mut(block_size).enumerate() {
+        let (x, y) = (b % block_x, b / block_x % block_y);
+        if (block_x & 1 == 1 && x == block_x - 1) || (block_y & 1 == 1 && y == block_y - 1) { continue }
+        let (color_endpoint, color_selector, alpha, alpha1) = pick(&mut rng);
+        match BlockKind::of(self.format)? {
+          BlockKind::Dxt1 => Dxt1 { color_endpoint, color_selector }.write_bytes(out),
+          BlockKind::Dxt5 => Dxt5 { alpha_endpoint: alpha.0, alpha_selector: alpha.1, color_endpoint, color_selector }.write_bytes(out),
+          BlockKind::Dxt5A => Dxt5A { alpha_endpoint: alpha.0, alpha_selector: alpha.1 }.write_bytes(out),
+          BlockKind::Dxn =>
+            Dxn { alpha0_endpoint: alpha.0, alpha0_selector: alpha.1, alpha1_endpoint: alpha1.0, alpha1_selector: alpha1.1 }.write_bytes(out),
+          BlockKind::Dxt3 => bail!(Error::UnsupportedFormat { format: self.format }),
+        }
+      }
+      Ok(level)
+    }).collect()
+  }
+
+  /// the crn file of `blocks`
+  pub fn build(&self) -> Result<Vec<u8>, Error> {
+    let levels = self.blocks()?;
+    encode(self.format, self.width, self.height, self.faces, &levels.iter().map(Vec::as_slice).collect::<Vec<_>>())
+  }
+
+  /// the built file with its header and tables
+  pub fn parsed(&self) -> Result<(Vec<u8>, Header, Tables), Error> {
+    let input = self.build()?;
+    let header = Header::parse(&input)?;
+    let tables = header.get_table(&input)?;
+    Ok((input, header, tables))
+  }
+}
+
+#[test]
+fn test_testgen() {
+  for format in [Format::Dxt1, Format::Dxt5, Format::Dxt5A, Format::DxnXY] {
+    let gen = TestCrn { faces: 6, palette_size: 5, ..TestCrn::new(format, 40, 24) };
+    let input = gen.build().unwrap();
+    assert_eq!(input, gen.build().unwrap());
+    assert_ne!(input, TestCrn { seed: 2, ..gen }.build().unwrap());
+    let header = Header::parse(&input).unwrap();
+    assert!(header.validate(&input).is_valid(), "{:?}", header.validate(&input));
+    assert_eq!((header.width, header.height, header.face_count, header.level_count), (40, 24, 6, 6));
+    for palette in [header.color_endpoints, header.color_selectors, header.alpha_endpoints, header.alpha_selectors] {
+      assert!(palette.count <= 5, "{:?} {:?}", format, palette);
+    }
+    let tables = header.get_table(&input).unwrap();
+    for (l, level) in gen.blocks().unwrap().iter().enumerate() {
+      assert_eq!(&header.unpack_level(&tables, &input, l).unwrap(), level, "{:?} level {}", format, l);
+    }
+  }
+  assert!(TestCrn::new(Format::Dxt3, 8, 8).build().is_err());
+}
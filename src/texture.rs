@@ -0,0 +1,123 @@
+//! The small API meant to stay stable, also in `prelude`: `decode` a whole file into a
+//! `DecodedTexture`. `Header`, `Tables` and `codec` follow the file format and the decoder and
+//! may change between minor versions.
+
+use std::time::{Duration, Instant};
+use crate::error::CrnError as Error;
+use crate::{DecodeSession, TextureInfo, rgba::decode_image};
+
+/// every level of a texture unpacked into BCn blocks
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DecodedTexture {
+  pub info: TextureInfo,
+  /// blocks of every level, the faces one after another
+  pub levels: Vec<Vec<u8>>,
+}
+
+impl DecodedTexture {
+  /// width and height of `level`
+  pub fn dimensions(&self, level: usize) -> Option<(u32, u32)> {
+    if level >= self.levels.len() { return None }
+    let shift = level.min(31) as u32;
+    Some((1.max(self.info.width >> shift), 1.max(self.info.height >> shift)))
+  }
+
+  /// the blocks of one face of `level`
+  pub fn face(&self, level: usize, face: usize) -> Option<&[u8]> {
+    let blocks = self.levels.get(level)?;
+    let size = blocks.len() / self.info.faces.max(1) as usize;
+    blocks.get(face * size..)?.get(..size)
+  }
+
+  /// one face of `level` expanded into RGBA8 texels
+  pub fn to_rgba(&self, level: usize, face: usize) -> Result<Vec<u8>, Error> {
+    let (width, height) = self.dimensions(level).ok_or(Error::level(level))?;
+    let blocks = self.face(level, face).ok_or(Error::OutOfBounds { what: "face", index: face })?;
+    decode_image(self.info.format, blocks, width as u16, height as u16)
+  }
+}
+
+/// How far `decode_with_progress` got, counted in blocks over all levels and faces.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DecodeProgress {
+  /// the level being unpacked
+  pub level: usize,
+  pub levels: usize,
+  pub blocks: usize,
+  pub total_blocks: usize,
+  /// since the decode started, parsing the header and tables included
+  pub elapsed: Duration,
+}
+
+impl DecodeProgress {
+  pub fn fraction(&self) -> f64 {
+    if self.total_blocks == 0 { return 1.0 }
+    self.blocks as f64 / self.total_blocks as f64
+  }
+
+  /// the time left at the rate so far, `None` before the first block
+  pub fn eta(&self) -> Option<Duration> {
+    if self.blocks == 0 { return None }
+    Some(self.elapsed.mul_f64((self.total_blocks - self.blocks) as f64 / self.blocks as f64))
+  }
+}
+
+/// Check the CRCs of a crn file and unpack every level, with the default `ParseOptions` and
+/// `DecodeLimits`.
+pub fn decode(input: &[u8]) -> Result<DecodedTexture, Error> {
+  decode_with_progress(input, |_| Ok(()))
+}
+
+/// `decode` calling `progress` after every row of blocks, an error returned by `progress`, e.g.
+/// `CrnError::Cancelled`, aborts the decode.
+pub fn decode_with_progress<P>(input: &[u8], mut progress: P) -> Result<DecodedTexture, Error>
+  where P: FnMut(DecodeProgress) -> Result<(), Error> {
+  let start = Instant::now();
+  let session = DecodeSession::new(input)?;
+  let header = session.header();
+  let row_blocks = |idx| header.get_level_info(idx).map_or(0, |(width, _)| width.div_ceil(4) as usize);
+  let level_blocks = |idx| header.get_level_info(idx).map_or(0, |(width, height)| {
+    width.div_ceil(4) as usize * height.div_ceil(4) as usize * header.face_count as usize
+  });
+  let levels = session.level_count();
+  let total_blocks = (0..levels).map(level_blocks).sum();
+  let mut done = 0;
+  let levels = (0..levels).map(|idx| {
+    let level = header.unpack_level_with_progress(session.tables(), session.input(), idx, |p| progress(DecodeProgress {
+      level: idx, levels, blocks: done + p.rows * row_blocks(idx), total_blocks, elapsed: start.elapsed(),
+    }))?;
+    done += level_blocks(idx);
+    Ok(level)
+  }).collect::<Result<Vec<_>, Error>>()?;
+  Ok(DecodedTexture { info: header.texture_info(), levels })
+}
+
+#[test]
+fn test_decode() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt1, 16, 8) };
+  let input = gen.build().unwrap();
+  let texture = decode(&input).unwrap();
+  let blocks = gen.blocks().unwrap();
+  assert_eq!((texture.info.levels, texture.info.faces, &texture.levels), (5, 6, &blocks));
+  assert_eq!([0, 1, 4, 5].map(|l| texture.dimensions(l)), [Some((16, 8)), Some((8, 4)), Some((1, 1)), None]);
+  assert_eq!(texture.face(1, 5).unwrap(), &blocks[1][5 * 16..]);
+  assert_eq!(texture.face(1, 6), None);
+  assert!(texture.to_rgba(0, 6).is_err());
+  assert_eq!(texture.to_rgba(0, 2).unwrap(), decode_image(Format::Dxt1, &blocks[0][2 * 64..][..64], 16, 8).unwrap());
+  assert!(texture.to_rgba(5, 0).is_err());
+  assert!(decode(&input[1..]).is_err());
+
+  let mut reports = vec![];
+  assert_eq!(decode_with_progress(&input, |p| { reports.push(p); Ok(()) }).unwrap(), texture);
+  // a report per row of blocks of every face
+  assert_eq!(reports.len(), 6 * (2 + 1 + 1 + 1 + 1));
+  assert!(reports.windows(2).all(|w| w[0].blocks < w[1].blocks && w[0].level <= w[1].level));
+  let last = reports.last().unwrap();
+  assert_eq!((last.level, last.levels, last.blocks, last.total_blocks, last.fraction()), (4, 5, 6 * (8 + 2 + 1 + 1 + 1), 78, 1.0));
+  assert_eq!(last.eta(), Some(Duration::ZERO));
+  assert_eq!(DecodeProgress::default().eta(), None);
+  assert!(matches!(decode_with_progress(&input, |_| Err(Error::Cancelled)), Err(Error::Cancelled)));
+}
@@ -0,0 +1,77 @@
+//! Decoding under tokio: the file comes from an `AsyncRead`, with the header parsed as soon as its
+//! bytes are in, and every level is unpacked on `spawn_blocking` behind a future of its own, so
+//! the executor only ever waits on the reader.
+
+use std::future::Future;
+use std::sync::Arc;
+use ::tokio::io::{AsyncRead, AsyncReadExt};
+use crate::error::CrnError as Error;
+use crate::{DecodeLimits, DecodeSession, Header, ParseOptions};
+
+/// Read just the header `reader` starts with and parse it, along with the bytes read so far. The
+/// CRCs and `file_size` need the whole file, `read_session` checks them.
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R, options: &ParseOptions) -> Result<(Header, Vec<u8>), Error> {
+  let mut input = vec![0; Header::fixed_size()];
+  reader.read_exact(&mut input).await?;
+  let header_size = u16::from_be_bytes([input[2], input[3]]) as usize;
+  if header_size > input.len() {
+    let start = input.len();
+    input.resize(header_size, 0);
+    reader.read_exact(&mut input[start..]).await?;
+  }
+  let header = Header::parse_with(&input, &ParseOptions { verify_crc: false, allow_size_mismatch: true, ..*options })?;
+  Ok((header, input))
+}
+
+/// Read the whole file and parse it like `DecodeSession::with_options`; headers beyond `limits` are
+/// rejected before reading past them.
+pub async fn read_session<R: AsyncRead + Unpin>(mut reader: R, options: &ParseOptions, limits: &DecodeLimits) -> Result<Arc<DecodeSession<'static>>, Error> {
+  let (header, mut input) = read_header(&mut reader, options).await?;
+  header.check_limits(limits)?;
+  reader.read_to_end(&mut input).await?;
+  Ok(Arc::new(DecodeSession::with_options(input, options, limits)?))
+}
+
+/// A future per level of `session`, the jobs of `DecodeSession::level_jobs` are spawned on
+/// `spawn_blocking` right away and run whether or not the futures are awaited. Must be called
+/// within a tokio runtime; a panicking job panics the future awaiting it.
+pub fn spawn_levels(session: &Arc<DecodeSession<'static>>) -> Vec<impl Future<Output = Result<Vec<u8>, Error>> + Send + 'static> {
+  session.level_jobs().map(|job| {
+    let handle = ::tokio::task::spawn_blocking(job);
+    async move {
+      match handle.await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(Error::Cancelled),
+      }
+    }
+  }).collect()
+}
+
+#[test]
+fn test_tokio() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 6, ..TestCrn::new(Format::Dxt5, 32, 16) };
+  let input = gen.build().unwrap();
+  let runtime = ::tokio::runtime::Builder::new_current_thread().build().unwrap();
+  runtime.block_on(async {
+    let (header, prefix) = read_header(&mut &input[..], &ParseOptions::default()).await.unwrap();
+    let parsed = Header::parse(&input).unwrap();
+    assert_eq!((header.texture_info(), &header.level_offset), (parsed.texture_info(), &parsed.level_offset));
+    assert_eq!(prefix, input[..header.header_size as usize]);
+
+    let session = read_session(&input[..], &ParseOptions::default(), &DecodeLimits::default()).await.unwrap();
+    let levels = spawn_levels(&session);
+    assert_eq!(levels.len(), 6);
+    for (level, expected) in levels.into_iter().zip(gen.blocks().unwrap()) {
+      assert_eq!(level.await.unwrap(), expected);
+    }
+
+    assert!(read_header(&mut &input[..10], &ParseOptions::default()).await.is_err());
+    assert!(read_session(&input[1..], &ParseOptions::default(), &DecodeLimits::default()).await.is_err());
+    assert!(read_session(&input[..input.len() - 1], &ParseOptions::default(), &DecodeLimits::default()).await.is_err());
+    // the header alone is enough to reject the file
+    let limits = DecodeLimits { max_output_bytes: 64, ..DecodeLimits::default() };
+    assert!(matches!(read_session(&prefix[..], &ParseOptions::default(), &limits).await, Err(Error::LimitExceeded { .. })));
+  });
+}
@@ -0,0 +1,323 @@
+//! Turn existing assets into crn files, and crn files back into DDS.
+//!
+//! RGBA images get their mipmaps generated and compressed level by level. A small DDS reader covers the legacy header with a DXT1/DXT5/ATI1/ATI2/BC4U/BC5U/A2XY FourCC
+//! and the DX10 extension with BC1/BC3/BC4/BC5, with mipmaps and cubemaps. The blocks go to
+//! `encode::encode_with` unchanged, only reordered from faces of levels into levels of faces.
+//! The writer always uses the legacy header with the FourCC crunch writes for the format.
+//! `Sidecar` describes a converted texture as JSON for asset databases to ingest.
+
+use crate::error::{CrnError as Error, bail, ensure, format_err};
+use std::convert::TryFrom;
+use crate::{DecodeSession, Format, compress::{Fit, compress_image}, encode::{EncodeOptions, encode_with}, mipmap::{MipOptions, level_count, mip_chain}, stats::DecodeStats, unpack::{BlockKind, level_size}};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+/// caps, height, width, pixel format and linear size
+const DDSD_REQUIRED: u32 = 0x1 | 0x2 | 0x4 | 0x1000 | 0x80000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+/// the cubemap flag with all six faces
+const DDSCAPS2_CUBEMAP_ALL: u32 = 0xfe00;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// the blocks of a DDS file in the layout `encode::encode` takes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Dds {
+  pub format: Format,
+  pub width: u16,
+  pub height: u16,
+  pub faces: u8,
+  /// every level with all faces one after another, as `Header::unpack_level` returns them
+  pub levels: Vec<Vec<u8>>,
+}
+
+fn read_u32(input: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes([input[offset], input[offset + 1], input[offset + 2], input[offset + 3]])
+}
+
+fn fourcc_format(fourcc: &[u8; 4]) -> Option<Format> {
+  Some(match fourcc {
+    b"DXT1" => Format::Dxt1,
+    b"DXT3" => Format::Dxt3,
+    b"DXT5" => Format::Dxt5,
+    b"ATI1" | b"BC4U" => Format::Dxt5A,
+    b"ATI2" | b"BC5U" => Format::DxnYX,
+    b"A2XY" => Format::DxnXY,
+    _ => return None,
+  })
+}
+
+/// the FourCC crunch writes for `format`, the swizzled DXT5 variants are plain DXT5
+fn format_fourcc(format: Format) -> Option<&'static [u8; 4]> {
+  Some(match BlockKind::of(format).ok()? {
+    BlockKind::Dxt1 => b"DXT1",
+    BlockKind::Dxt3 => b"DXT3",
+    BlockKind::Dxt5 => b"DXT5",
+    BlockKind::Dxt5A => b"ATI1",
+    BlockKind::Dxn if format == Format::DxnYX => b"ATI2",
+    BlockKind::Dxn => b"A2XY",
+  })
+}
+
+/// BCn formats of `DXGI_FORMAT`, typeless and sRGB variants included
+fn dxgi_format(dxgi: u32) -> Option<Format> {
+  Some(match dxgi {
+    70..=72 => Format::Dxt1,
+    73..=75 => Format::Dxt3,
+    76..=78 => Format::Dxt5,
+    79 | 80 => Format::Dxt5A,
+    82 | 83 => Format::DxnYX,
+    _ => return None,
+  })
+}
+
+impl Dds {
+  pub fn parse(input: &[u8]) -> Result<Self, Error> {
+    ensure!(input.len() >= 4 + HEADER_SIZE && &input[..4] == MAGIC, "not a dds file");
+    let header = &input[4..4 + HEADER_SIZE];
+    ensure!(read_u32(header, 0) as usize == HEADER_SIZE, "dds header size {}", read_u32(header, 0));
+    let (flags, height, width) = (read_u32(header, 4), read_u32(header, 8), read_u32(header, 12));
+    let mip_count = if flags & DDSD_MIPMAPCOUNT != 0 { read_u32(header, 24).max(1) } else { 1 };
+    let (pixel_flags, fourcc) = (read_u32(header, 76), [header[80], header[81], header[82], header[83]]);
+    let caps2 = read_u32(header, 108);
+    ensure!(pixel_flags & DDPF_FOURCC != 0, "dds without a compressed format");
+    let mut offset = 4 + HEADER_SIZE;
+    let (format, cubemap) = if &fourcc == b"DX10" {
+      ensure!(input.len() >= offset + 20, "dds dx10 header truncated");
+      let (dxgi, misc, array_size) = (read_u32(input, offset), read_u32(input, offset + 8), read_u32(input, offset + 12));
+      ensure!(array_size <= 1, "dds texture array of {}", array_size);
+      offset += 20;
+      (dxgi_format(dxgi).ok_or_else(|| format_err!("dds dxgi format {}", dxgi))?, misc & DDS_RESOURCE_MISC_TEXTURECUBE != 0)
+    } else {
+      let format = fourcc_format(&fourcc).ok_or_else(|| format_err!("dds fourcc {:?}", String::from_utf8_lossy(&fourcc)))?;
+      (format, caps2 & DDSCAPS2_CUBEMAP != 0)
+    };
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+      (Ok(w), Ok(h)) if w != 0 && h != 0 => (w, h),
+      _ => bail!("dds size {}x{}", width, height),
+    };
+    // levels past 1x1 don't exist in crn
+    let level_count = (mip_count as usize).min(level_count(width, height));
+    let faces = if cubemap { 6 } else { 1 };
+
+    // faces each hold their whole mip chain, crn wants the faces of a level together
+    let dims = |l: usize| (1.max(width >> l), 1.max(height >> l));
+    let mut levels = vec![vec![]; level_count];
+    for _ in 0..faces {
+      for l in 0..mip_count as usize {
+        let (w, h) = dims(l.min(15));
+        let size = level_size(w, h, 1, format.block_size())?;
+        ensure!(input.len() >= offset + size, "dds truncated at level {}", l);
+        if let Some(level) = levels.get_mut(l) { level.extend_from_slice(&input[offset..offset + size]) }
+        offset += size;
+      }
+    }
+    Ok(Self { format, width, height, faces, levels })
+  }
+
+  /// the DDS file of `self`, faces each with their mip chain
+  pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+    let fourcc = format_fourcc(self.format).ok_or(Error::UnsupportedFormat { format: self.format })?;
+    ensure!(self.faces == 1 || self.faces == 6, "{} faces", self.faces);
+    let faces = self.faces as usize;
+    let face_sizes = self.levels.iter().enumerate().map(|(l, level)| {
+      let size = level_size(1.max(self.width >> l.min(15)), 1.max(self.height >> l.min(15)), 1, self.format.block_size())?;
+      ensure!(level.len() == size * faces, "level {} of {} bytes instead of {}", l, level.len(), size * faces);
+      Ok(size)
+    }).collect::<Result<Vec<_>, Error>>()?;
+    let mipmaps = self.levels.len() > 1;
+    let mut header = [0u32; HEADER_SIZE / 4];
+    header[0] = HEADER_SIZE as u32;
+    header[1] = DDSD_REQUIRED | if mipmaps { DDSD_MIPMAPCOUNT } else { 0 };
+    (header[2], header[3], header[4], header[6]) = (self.height as u32, self.width as u32, face_sizes.first().map_or(0, |&s| s as u32), self.levels.len() as u32);
+    (header[18], header[19], header[20]) = (32, DDPF_FOURCC, u32::from_le_bytes(*fourcc));
+    header[26] = DDSCAPS_TEXTURE | if mipmaps { DDSCAPS_COMPLEX | DDSCAPS_MIPMAP } else { 0 } | if faces == 6 { DDSCAPS_COMPLEX } else { 0 };
+    header[27] = if faces == 6 { DDSCAPS2_CUBEMAP_ALL } else { 0 };
+    let mut out = MAGIC.to_vec();
+    out.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+    for f in 0..faces {
+      for (level, &size) in self.levels.iter().zip(&face_sizes) {
+        out.extend_from_slice(&level[f * size..][..size]);
+      }
+    }
+    Ok(out)
+  }
+}
+
+/// Unpack every level of a crn file into a DDS file with the same format, mipmaps and faces.
+pub fn crn_to_dds(input: &[u8]) -> Result<Vec<u8>, Error> {
+  let session = DecodeSession::new(input)?;
+  let header = session.header();
+  let levels = (0..session.level_count()).map(|idx| session.unpack_level(idx)).collect::<Result<Vec<_>, Error>>()?;
+  Dds { format: header.format, width: header.width, height: header.height, faces: header.face_count, levels }.to_bytes()
+}
+
+/// metadata of a crn file and its decode stats, see `to_json`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+  /// 64-bit FNV-1a of the whole crn file
+  pub source_hash: u64,
+  pub source_size: usize,
+  pub format: Format,
+  pub width: u16,
+  pub height: u16,
+  pub faces: u8,
+  pub levels: u8,
+  pub stats: DecodeStats,
+}
+
+fn fnv1a64(input: &[u8]) -> u64 {
+  input.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+impl Sidecar {
+  /// hash and walk the level streams of `input` without assembling blocks
+  pub fn new(input: &[u8]) -> Result<Self, Error> {
+    let session = DecodeSession::new(input)?;
+    let header = session.header();
+    Ok(Self {
+      source_hash: fnv1a64(input),
+      source_size: input.len(),
+      format: header.format,
+      width: header.width,
+      height: header.height,
+      faces: header.face_count,
+      levels: header.level_count,
+      stats: session.stats()?,
+    })
+  }
+
+  /// a single line of JSON, the hash as `"fnv1a64:<16 hex digits>"` and palette sizes in bytes
+  pub fn to_json(&self) -> String {
+    let stats = &self.stats;
+    let palettes = [
+      ("color_endpoints", stats.color_endpoints), ("color_selectors", stats.color_selectors),
+      ("alpha_endpoints", stats.alpha_endpoints), ("alpha_selectors", stats.alpha_selectors),
+    ].iter().map(|(name, p)| format!("\"{}\":{{\"entries\":{},\"used\":{},\"bytes\":{}}}", name, p.entries, p.used, p.bits / 8)).collect::<Vec<_>>();
+    let levels = stats.levels.iter().map(|l| format!(
+      "{{\"width\":{},\"height\":{},\"bytes\":{},\"chunks\":{},\"mean_tile_count\":{}}}",
+      l.width, l.height, l.bits.div_ceil(8), l.chunks, l.mean_tile_count(),
+    )).collect::<Vec<_>>();
+    format!(
+      "{{\"source_hash\":\"fnv1a64:{:016x}\",\"source_size\":{},\"format\":\"{:?}\",\"width\":{},\"height\":{},\"faces\":{},\"levels\":{},\
+       \"stats\":{{\"table_bytes\":{},\"palettes\":{{{}}},\"levels\":[{}],\"tile_layouts\":{:?}}}}}",
+      self.source_hash, self.source_size, self.format, self.width, self.height, self.faces, self.levels,
+      stats.table_bits / 8, palettes.join(","), levels.join(","), stats.tile_layouts(),
+    )
+  }
+}
+
+/// `crn_to_dds` with the `Sidecar` of `input`
+pub fn crn_to_dds_with_sidecar(input: &[u8]) -> Result<(Vec<u8>, Sidecar), Error> {
+  Ok((crn_to_dds(input)?, Sidecar::new(input)?))
+}
+
+/// Encode the blocks of a DDS file into a crn file with `options`, keeping the format, mipmaps
+/// and cubemap faces.
+pub fn dds_to_crn(dds: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+  let dds = Dds::parse(dds)?;
+  let levels: Vec<&[u8]> = dds.levels.iter().map(Vec::as_slice).collect();
+  encode_with(dds.format, dds.width, dds.height, dds.faces, &levels, options)
+}
+
+/// Encode `width * height * 4` bytes of RGBA into a crn file of `format` with the full mip chain
+/// down to 1x1, every level compressed with `fit`.
+pub fn rgba_to_crn(format: Format, rgba: &[u8], width: u16, height: u16, fit: Fit, mips: &MipOptions, options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+  let tail = mip_chain(rgba, width, height, usize::MAX, mips)?;
+  let levels = std::iter::once(rgba).chain(tail.iter().map(Vec::as_slice)).enumerate().map(|(l, level)| {
+    compress_image(format, level, 1.max(width >> l), 1.max(height >> l), fit)
+  }).collect::<Result<Vec<_>, Error>>()?;
+  let levels: Vec<&[u8]> = levels.iter().map(Vec::as_slice).collect();
+  encode_with(format, width, height, 1, &levels, options)
+}
+
+#[test]
+fn test_rgba_to_crn() {
+  use crate::{Header, mipmap::downsample};
+  let image: Vec<u8> = (0..32 * 16u32).flat_map(|i| [(i % 32 * 8) as u8, (i / 32 * 16) as u8, (i * 7) as u8, 255]).collect();
+  let mips = MipOptions { srgb: true, ..Default::default() };
+  let crn = rgba_to_crn(Format::Dxt1, &image, 32, 16, Fit::Range, &mips, &EncodeOptions::default()).unwrap();
+  let header = Header::parse(&crn).unwrap();
+  let tables = header.get_table(&crn).unwrap();
+  assert_eq!((header.width, header.height, header.level_count), (32, 16, 6));
+  let level1 = compress_image(Format::Dxt1, &downsample(&image, 32, 16, &mips).unwrap(), 16, 8, Fit::Range).unwrap();
+  assert_eq!(header.unpack_level(&tables, &crn, 1).unwrap(), level1);
+  assert!(rgba_to_crn(Format::Dxt1, &image[1..], 32, 16, Fit::Range, &mips, &EncodeOptions::default()).is_err());
+}
+
+#[test]
+fn test_dds_to_crn() {
+  use crate::{Header, compress::{Fit, compress_image}};
+  let image: Vec<u8> = (0..16 * 16u32).flat_map(|i| [(i % 16 * 16) as u8, (i / 16 * 16) as u8, (i * 7) as u8, 255]).collect();
+  let dds = |fourcc: &[u8; 4], dxgi: Option<u32>, faces: u8, levels: &[Vec<u8>]| {
+    let mut out = MAGIC.to_vec();
+    let mut header = [0u32; 31];
+    header[0] = HEADER_SIZE as u32;
+    (header[1], header[2], header[3], header[6]) = (0x1007 | DDSD_MIPMAPCOUNT, 16, 16, levels.len() as u32);
+    (header[18], header[19]) = (32, DDPF_FOURCC);
+    header[20] = u32::from_le_bytes(*fourcc);
+    header[26] = 0x1000 | if faces == 6 { 0x8 | 0x400000 } else { 0 };
+    header[27] = if faces == 6 && dxgi.is_none() { DDSCAPS2_CUBEMAP | 0xfc00 } else { 0 };
+    out.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+    if let Some(dxgi) = dxgi {
+      let misc = if faces == 6 { DDS_RESOURCE_MISC_TEXTURECUBE } else { 0 };
+      out.extend([dxgi, 3, misc, 1, 0].iter().flat_map(|v| v.to_le_bytes()));
+    }
+    for _ in 0..faces {
+      for level in levels { out.extend_from_slice(level) }
+    }
+    out
+  };
+  for (format, fourcc, dxgi) in [(Format::Dxt1, b"DXT1", None), (Format::Dxt5, b"DX10", Some(77)), (Format::DxnYX, b"ATI2", None)] {
+    // the 8x8 level of the same picture, so both levels decode exactly
+    let levels = [16, 8].map(|size| compress_image(format, &image, size, size, Fit::Range).unwrap());
+    for faces in [1, 6] {
+      let input = dds(fourcc, dxgi, faces, &levels);
+      let parsed = Dds::parse(&input).unwrap();
+      assert_eq!((parsed.format, parsed.width, parsed.height, parsed.faces, parsed.levels.len()), (format, 16, 16, faces, 2));
+      let crn = dds_to_crn(&input, &EncodeOptions::default()).unwrap();
+      let header = Header::parse(&crn).unwrap();
+      let tables = header.get_table(&crn).unwrap();
+      assert_eq!((header.format, header.face_count, header.level_count), (format, faces, 2));
+      for (l, level) in levels.iter().enumerate() {
+        assert_eq!(header.unpack_level(&tables, &crn, l).unwrap(), level.repeat(faces as usize), "{:?} {} {}", format, faces, l);
+      }
+      assert!(Dds::parse(&input[..input.len() - 1]).is_err());
+    }
+  }
+  assert!(Dds::parse(b"DDS").is_err());
+
+  // back out of crn, faces of levels again
+  for (format, faces) in [(Format::Dxt1, 6), (Format::Dxt5A, 1), (Format::DxnXY, 1)] {
+    let gen = crate::testgen::TestCrn { faces, ..crate::testgen::TestCrn::new(format, 32, 16) };
+    let input = gen.build().unwrap();
+    let output = crn_to_dds(&input).unwrap();
+    let levels = gen.blocks().unwrap();
+    assert_eq!(Dds::parse(&output).unwrap(), Dds { format, width: 32, height: 16, faces, levels: levels.clone() });
+    assert_eq!(output.len(), 4 + HEADER_SIZE + levels.iter().map(Vec::len).sum::<usize>());
+    assert_eq!(output[4 + HEADER_SIZE..][..levels[0].len() / faces as usize], levels[0][..levels[0].len() / faces as usize]);
+    assert_eq!(read_u32(&output, 4 + 108) & DDSCAPS2_CUBEMAP != 0, faces == 6);
+  }
+  assert!(Dds { format: Format::Dxt1, width: 4, height: 4, faces: 1, levels: vec![vec![0; 7]] }.to_bytes().is_err());
+  assert!(Dds { format: Format::Etc1, width: 4, height: 4, faces: 1, levels: vec![vec![0; 8]] }.to_bytes().is_err());
+  assert!(dds_to_crn(&dds(b"DXT3", None, 1, &[vec![0; 256]]), &EncodeOptions::default()).is_err());
+  assert!(Dds::parse(&dds(b"RGBA", None, 1, &[vec![0; 256]])).is_err());
+}
+
+#[test]
+fn test_sidecar() {
+  assert_eq!((fnv1a64(b""), fnv1a64(b"a")), (0xcbf2_9ce4_8422_2325, 0xaf63_dc4c_8601_ec8c));
+  let input = crate::testgen::TestCrn { palette_size: 5, ..crate::testgen::TestCrn::new(Format::Dxt1, 16, 8) }.build().unwrap();
+  let (dds, sidecar) = crn_to_dds_with_sidecar(&input).unwrap();
+  assert_eq!(dds, crn_to_dds(&input).unwrap());
+  assert_eq!((sidecar.source_hash, sidecar.source_size, sidecar.levels, sidecar.stats.levels.len()), (fnv1a64(&input), input.len(), 5, 5));
+  let json = sidecar.to_json();
+  assert!(json.starts_with(&format!(r#"{{"source_hash":"fnv1a64:{:016x}","source_size":{},"format":"Dxt1","width":16,"height":8,"faces":1,"levels":5,"stats":{{"#, sidecar.source_hash, input.len())), "{}", json);
+  assert!(json.contains(r#""alpha_endpoints":{"entries":0,"used":0,"bytes":0}"#) && json.ends_with("]}}"), "{}", json);
+  assert_eq!(json.matches('{').count(), json.matches('}').count());
+  assert!(Sidecar::new(&input[1..]).is_err());
+}
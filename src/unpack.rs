@@ -1,258 +1,675 @@
-use std::io::prelude::*;
-use anyhow::{Error, Context, bail};
+use std::{io::Write, ops::ControlFlow};
+use crate::error::{CrnError as Error, Context, bail};
+#[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
-use crate::{Tables, Huffman, codec::Codec};
+use crate::{DecodeContext, Format, Tables, Huffman, codec::{BitRead, Symbol}};
 
-pub trait Block: Serialize {
+pub trait Block {
   const BLOCK_SIZE: usize;
+  /// value of a single decoded texel, RGBA for color blocks and channel values otherwise
+  type Texel: Copy + Default;
+  /// expand the block to its 4x4 texels in row-major order
+  fn decode_texels(&self) -> [Self::Texel; 16];
+  /// store the little-endian BCn encoding into `out[..BLOCK_SIZE]`
+  fn write_bytes(&self, out: &mut [u8]);
+  /// a block of a single color, channel blocks take red (and green)
+  fn solid(rgba: [u8; 4]) -> Self;
+  /// parse the little-endian BCn encoding from `input[..BLOCK_SIZE]`, the inverse of `write_bytes`
+  fn read_bytes(input: &[u8]) -> Self;
   fn write_to<W: Write>(&self, mut w: W) -> std::io::Result<()> {
-    use bincode::Options;
-    let bin = bincode::config::DefaultOptions::new()
-      .with_fixint_encoding()
-      .with_little_endian()
-      .serialize(self)
-      .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-    w.write(&bin)?;
-    Ok(())
+    let mut buffer = [0; 16];
+    self.write_bytes(&mut buffer[..Self::BLOCK_SIZE]);
+    w.write_all(&buffer[..Self::BLOCK_SIZE])
   }
 }
 
-pub trait Unpack {
-  fn unpack(tables: &Tables, codec: &mut Codec, width: u16, height: u16, face: u8) -> Result<Vec<u8>, Error>;
-  fn next_tile_idx(codec: &mut Codec, encoding: &Huffman, tile_bits: &mut u32) -> Result<(usize, [usize; 4]), Error> {
-    if *tile_bits == 1 {
-      *tile_bits = encoding.next(codec).context("read chunk encoding bits")? | 512;
-    }
+fn pack_565([r, g, b, _]: [u8; 4]) -> u16 {
+  (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+}
 
-    let tile_index = *tile_bits as usize & 7;
-    *tile_bits >>= 3;
-    Ok((Self::COUNT_TILES[tile_index], Self::TILES[tile_index]))
+fn expand_565(c: u16) -> [u32; 3] {
+  let (r, g, b) = ((c >> 11) as u32 & 31, (c >> 5) as u32 & 63, c as u32 & 31);
+  [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// texels of a BC1 color block, `punchthrough` enables the 3-color mode with transparent black
+/// when `c0 <= c1`, which BC2/BC3 color blocks never use
+fn decode_color((c0, c1): (u16, u16), selector: [u8; 4], punchthrough: bool) -> [[u8; 4]; 16] {
+  let (e0, e1) = (expand_565(c0), expand_565(c1));
+  let mix = |w0: u32, w1: u32| {
+    let mut c = [0, 0, 0, 255];
+    for i in 0..3 { c[i] = ((e0[i] * w0 + e1[i] * w1) / (w0 + w1)) as u8 }
+    c
+  };
+  let palette = if c0 > c1 || !punchthrough {
+    [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+  } else {
+    [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+  };
+  let mut result = [[0; 4]; 16];
+  for (i, texel) in result.iter_mut().enumerate() {
+    *texel = palette[(selector[i / 4] >> (i % 4 * 2) & 3) as usize];
   }
+  result
+}
 
-  const TRUNK_SIZE: usize = 2;
+/// texels of a BC4 channel block
+fn decode_alpha((a0, a1): (u8, u8), selector: [u8; 6]) -> [u8; 16] {
+  let (a0, a1) = (a0 as u32, a1 as u32);
+  let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+  if a0 > a1 {
+    for i in 1..7 { palette[i + 1] = ((7 - i) as u32 * a0 + i as u32 * a1) / 7 }
+  } else {
+    for i in 1..5 { palette[i + 1] = ((5 - i) as u32 * a0 + i as u32 * a1) / 5 }
+  }
+  let mut bits = [0; 8];
+  bits[..6].copy_from_slice(&selector);
+  let bits = u64::from_le_bytes(bits);
+  let mut result = [0; 16];
+  for (i, texel) in result.iter_mut().enumerate() {
+    *texel = palette[(bits >> (i * 3) & 7) as usize] as u8;
+  }
+  result
+}
 
-  const COUNT_TILES: [usize; 8] = [ 1, 2, 2, 3, 3, 3, 3, 4 ];
-  const TILES: [[usize; 4]; 8] = [
-    [ 0, 0, 0, 0 ],
-    [ 0, 0, 1, 1 ], [ 0, 1, 0, 1 ],
-    [ 0, 0, 1, 2 ], [ 1, 2, 0, 0 ],
-    [ 0, 1, 0, 2 ], [ 1, 0, 2, 0 ],
-    [ 0, 1, 2, 3 ]
-  ];
+fn check_block_size<T: Block>(input: &[u8]) -> Result<(), Error> {
+  if input.len() != T::BLOCK_SIZE {
+    bail!("{} takes {} bytes, got {}", std::any::type_name::<T>(), T::BLOCK_SIZE, input.len())
+  }
+  Ok(())
 }
 
+fn u16_at(input: &[u8], i: usize) -> u16 {
+  u16::from_le_bytes([input[i], input[i + 1]])
+}
 
+/// The palettes (and their delta tables) a level stream refers to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+  ColorEndpoint, ColorSelector, AlphaEndpoint, AlphaSelector,
+}
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Dxt1 {
-  pub color_endpoint: (u16, u16),
-  pub color_selector: [u8; 4],
+impl Kind {
+  pub const ALL: [Kind; 4] = [Kind::ColorEndpoint, Kind::ColorSelector, Kind::AlphaEndpoint, Kind::AlphaSelector];
+  pub fn index(self) -> usize {
+    self as usize
+  }
 }
 
-impl Block for Dxt1 {
-  const BLOCK_SIZE: usize = 8;
+/// bytes of `face` faces of `width`x`height` texels in blocks of `block_size` bytes
+pub fn level_size(width: u16, height: u16, face: u8, block_size: usize) -> Result<usize, Error> {
+  (width.div_ceil(4) as usize).checked_mul(height.div_ceil(4) as usize)
+    .and_then(|n| n.checked_mul(face as usize))
+    .and_then(|n| n.checked_mul(block_size))
+    .ok_or(Error::Overflow("level size"))
 }
-impl Unpack for Dxt1 {
-  fn unpack(tables: &Tables, codec: &mut Codec, width: u16, height: u16, face: u8) -> Result<Vec<u8>, Error> {
-    let block_x = (width + 3) / 4;
-    let block_y = (height + 3) / 4;
-    let chunk_x = (block_x + 1) as usize / Self::TRUNK_SIZE;
-    let chunk_y = (block_y + 1) as usize / Self::TRUNK_SIZE;
 
-    let mut tile_bits = 1u32;
+/// Decoded blocks of a level in row-major order, faces one after another,
+/// `faces * block_y` rows of `block_x` blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blocks<T> {
+  pub block_x: usize,
+  pub block_y: usize,
+  pub faces: usize,
+  pub blocks: Vec<T>,
+}
 
-    let mut color_endpoint_index = 0;
-    let mut color_selector_index = 0;
+impl<T: Default + Clone> Blocks<T> {
+  pub fn new(block_x: usize, block_y: usize, faces: usize) -> Self {
+    Self { block_x, block_y, faces, blocks: vec![T::default(); block_x * block_y * faces] }
+  }
+}
 
-    let pitch = block_x as usize * Self::BLOCK_SIZE;
+impl<T> Blocks<T> {
+  /// block at column `x` of row `y`, rows of face `f` start at `f * block_y`
+  pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+    if x >= self.block_x { return None }
+    self.blocks.get(y * self.block_x + x)
+  }
+  pub fn face(&self, f: usize) -> Option<&[T]> {
+    let size = self.block_x * self.block_y;
+    self.blocks.get(f * size..(f + 1) * size)
+  }
+  pub fn rows(&self) -> std::slice::ChunksExact<'_, T> {
+    self.blocks.chunks_exact(self.block_x.max(1))
+  }
+}
 
-    let mut result = vec![0u8; block_y as usize * pitch];
-    let mut cursor = std::io::Cursor::new(&mut result[..]);
+impl<T: Block> Blocks<T> {
+  /// serialize into the little-endian BCn byte stream
+  pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+    let mut result = vec![0; self.blocks.len() * T::BLOCK_SIZE];
+    for (block, out) in self.blocks.iter().zip(result.chunks_exact_mut(T::BLOCK_SIZE)) {
+      block.write_bytes(out);
+    }
+    Ok(result)
+  }
 
-    for _f in 0..face {
-      // let mut row = Vec::new();
-      for y in 0..chunk_y {
-        let skip_y = y == (chunk_y - 1) && block_y & 1 == 1;
-        let xrange: Box<dyn Iterator<Item=_>> = if y & 1 == 1 { Box::new((0..chunk_x).rev()) } else { Box::new(0..chunk_x) };
-        for x in xrange {
-          let skip_x = block_x & 1 == 1 && x == (chunk_x - 1);
-          let mut color_endpoints = [(0, 0); 4];
+  /// write rows of blocks `pitch` bytes apart and faces `slice_pitch` bytes apart into `out`,
+  /// bytes in between are left untouched
+  pub fn write_to_slice(&self, out: &mut [u8], pitch: usize, slice_pitch: usize) -> Result<(), Error> {
+    let row_size = self.block_x.checked_mul(T::BLOCK_SIZE).ok_or(Error::Overflow("row size"))?;
+    if pitch < row_size { bail!("pitch {} < row size {}", pitch, row_size) }
+    let slice_size = self.block_y.checked_mul(pitch).ok_or(Error::Overflow("slice size"))?;
+    if self.faces > 1 && slice_pitch < slice_size {
+      bail!("slice pitch {} < slice size {}", slice_pitch, slice_size);
+    }
+    let required = if self.faces == 0 || self.block_y == 0 { 0 } else {
+      // the last row is only `row_size <= pitch` long
+      (self.faces - 1).checked_mul(slice_pitch)
+        .and_then(|n| n.checked_add(slice_size - pitch + row_size))
+        .ok_or(Error::Overflow("output size"))?
+    };
+    if out.len() < required { bail!("output too short {} < {}", out.len(), required) }
+    for (i, row) in self.rows().enumerate() {
+      let start = i / self.block_y * slice_pitch + i % self.block_y * pitch;
+      for (block, out) in row.iter().zip(out[start..start + row_size].chunks_exact_mut(T::BLOCK_SIZE)) {
+        block.write_bytes(out);
+      }
+    }
+    Ok(())
+  }
+}
 
-          let (tiles_count, tiles) = Self::next_tile_idx(codec, &tables.chunk_encoding, &mut tile_bits)?;
+/// How far an unpack got, reported after every row of blocks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+  /// rows of blocks decoded so far, over all faces
+  pub rows: usize,
+  pub total_rows: usize,
+}
 
-          for i in 0..tiles_count {
-            color_endpoints[i] = tables.color_endpoint()?.next(codec, &mut color_endpoint_index).context("read color_endpoint_delta")?;
-          }
-          for (i, &tile) in tiles.iter().enumerate() {
-            let color_selector = tables.color_selector()?.next(codec, &mut color_selector_index).context("read color_selector_delta")?;
+impl Progress {
+  pub fn fraction(&self) -> f64 {
+    if self.total_rows == 0 { return 1.0 }
+    self.rows as f64 / self.total_rows as f64
+  }
+}
 
-            // println!("{:x?}", (delta0, delta1, alpha_selector_index, color_selector_index, tables.color_selectors[color_selector_index]));
-            if !skip_x && !skip_y {
-              if i % Self::TRUNK_SIZE == 0 {
-                let pos = (y * Self::TRUNK_SIZE + i / Self::TRUNK_SIZE) * pitch + x * Self::BLOCK_SIZE * Self::TRUNK_SIZE;
-                // println!("seek {}x{} + {} => {:x}", x, y, i, pos);
-                cursor.seek(std::io::SeekFrom::Start(pos as _)).expect("seek");
-              }
-              Dxt1 {
-                color_endpoint: color_endpoints[tile],
-                color_selector,
-              }.write_to(&mut cursor).context("write block")?;
-            }
-          }
-        }
-      }
+/// A rectangle of pixels within a level.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+  pub x: u16,
+  pub y: u16,
+  pub width: u16,
+  pub height: u16,
+}
+
+impl Rect {
+  /// blocks covering the rectangle as `(x0, y0, x1, y1)`, the end is exclusive
+  pub fn blocks(&self) -> (usize, usize, usize, usize) {
+    let (x, y) = (self.x as usize, self.y as usize);
+    (x / 4, y / 4, (x + self.width as usize).div_ceil(4), (y + self.height as usize).div_ceil(4))
+  }
+}
+
+/// What a level decode did, see [`crate::Header::unpack_level_with_stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UnpackStats {
+  /// blocks in the output, over all faces
+  pub blocks: usize,
+  /// blocks on an odd right or bottom edge, which are left as default
+  pub skipped_blocks: usize,
+  pub chunks: usize,
+  /// bits consumed by the level stream
+  pub bits: usize,
+  /// bits left unread at the end of the stream, only padding of the last byte for a valid level
+  pub slack_bits: usize,
+}
+
+impl UnpackStats {
+  pub fn new(width: u16, height: u16, face: u8, bits: usize, slack_bits: usize) -> Self {
+    let trunk = Dxt1::TRUNK_SIZE;
+    let (block_x, block_y, face) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize, face as usize);
+    let blocks = block_x * block_y * face;
+    Self {
+      blocks,
+      skipped_blocks: blocks - (block_x - block_x % trunk) * (block_y - block_y % trunk) * face,
+      chunks: block_x.div_ceil(trunk) * block_y.div_ceil(trunk) * face,
+      bits, slack_bits,
     }
-    if !codec.is_complete() { bail!("extra bytes in codec") }
-    Ok(result)
   }
 }
 
+/// magenta, a fill that stands out
+pub const PLACEHOLDER: [u8; 4] = [255, 0, 255, 255];
+
+/// What blocks the stream doesn't cover are filled with, the skipped blocks on an odd
+/// right or bottom edge and undecodable blocks of a lenient decode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+  /// zeroed blocks, black in color formats
+  #[default]
+  Zero,
+  Solid([u8; 4]),
+  /// alternate two colors block by block
+  Checkerboard([u8; 4], [u8; 4]),
+}
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Dxt5 {
-  pub alpha_endpoint: (u8, u8),
-  pub alpha_selector: [u8; 6],
-  pub color_endpoint: (u16, u16),
-  pub color_selector: [u8; 4],
+impl Fill {
+  /// fill of the block at `(x, y)`, in blocks
+  pub fn block<T: Block + Default>(&self, x: usize, y: usize) -> T {
+    match *self {
+      Fill::Zero => T::default(),
+      Fill::Solid(rgba) => T::solid(rgba),
+      Fill::Checkerboard(a, b) => T::solid(if (x + y) & 1 == 0 { a } else { b }),
+    }
+  }
 }
 
-impl Block for Dxt5 {
-  const BLOCK_SIZE: usize = 16;
+/// The BCn block layout of a format, the one place mapping [`Format`]s to block types:
+/// the swizzled DXT5 variants are all `Dxt5` and both channel orders of DXN are `Dxn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+  Dxt1, Dxt3, Dxt5, Dxt5A, Dxn,
 }
-impl Unpack for Dxt5 {
-  fn unpack(tables: &Tables, codec: &mut Codec, width: u16, height: u16, face: u8) -> Result<Vec<u8>, Error> {
-    let block_x = (width + 3) / 4;
-    let block_y = (height + 3) / 4;
-    let chunk_x = (block_x + 1) as usize / Self::TRUNK_SIZE;
-    let chunk_y = (block_y + 1) as usize / Self::TRUNK_SIZE;
 
-    let mut tile_bits = 1u32;
+impl BlockKind {
+  /// `UnsupportedFormat` for formats without BCn blocks
+  pub fn of(format: Format) -> Result<Self, Error> {
+    Ok(match format {
+      Format::Dxt1 => BlockKind::Dxt1,
+      Format::Dxt3 => BlockKind::Dxt3,
+      Format::Dxt5 | Format::Dxt5CCxY | Format::Dxt5xGxR | Format::Dxt5xGBR | Format::Dxt5AGBR => BlockKind::Dxt5,
+      Format::Dxt5A => BlockKind::Dxt5A,
+      Format::DxnXY | Format::DxnYX => BlockKind::Dxn,
+      Format::Etc1 | Format::Invalid => bail!(Error::UnsupportedFormat { format }),
+    })
+  }
+}
 
-    let mut color_endpoint_index = 0;
-    let mut color_selector_index = 0;
-    let mut alpha_endpoint_index = 0;
-    let mut alpha_selector_index = 0;
+/// Evaluate `$body` with `$B` naming the [`Unpack`] block type of `$format`, returning
+/// `UnsupportedFormat` from the enclosing function for formats crn files can't hold.
+macro_rules! dispatch {
+  ($format:expr, $B:ident => $body:expr) => {{
+    let format: $crate::Format = $format;
+    match $crate::unpack::BlockKind::of(format)? {
+      $crate::unpack::BlockKind::Dxt1 => { type $B = $crate::unpack::Dxt1; $body }
+      $crate::unpack::BlockKind::Dxt5 => { type $B = $crate::unpack::Dxt5; $body }
+      $crate::unpack::BlockKind::Dxt5A => { type $B = $crate::unpack::Dxt5A; $body }
+      $crate::unpack::BlockKind::Dxn => { type $B = $crate::unpack::Dxn; $body }
+      $crate::unpack::BlockKind::Dxt3 => $crate::error::bail!($crate::error::CrnError::UnsupportedFormat { format }),
+    }
+  }};
+}
+pub(crate) use dispatch;
+
+pub trait Unpack: Block + Default + Clone + Send + Sync {
+  /// block layout of the formats whose levels decode into `Self`
+  const KIND: BlockKind;
+  /// build a block from palette indices of its endpoint and selector components,
+  /// in the order they appear in the stream
+  fn from_indices<S: Symbol>(tables: &Tables<S>, endpoints: &[usize], selectors: &[usize]) -> Result<Self, Error>;
+  /// palettes of the endpoint components of a block, in stream order
+  const ENDPOINTS: &'static [Kind];
+  /// palettes of the selector components of a block, in stream order
+  const SELECTORS: &'static [Kind];
+  /// the row buffer of `Self` in `context`
+  fn scratch(context: &mut DecodeContext) -> &mut Vec<Self>;
+
+  /// decode `face` faces, handing every row of blocks to `emit(face, y, row)` in order,
+  /// at most two rows are buffered
+  fn unpack_rows<S, C, F>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, mut emit: F) -> Result<(), Error>
+    where S: Symbol, C: BitRead, F: FnMut(usize, usize, &[Self]) -> Result<(), Error> {
+    Self::unpack_rows_until(tables, codec, width, height, face, &Fill::Zero, |f, y, row| emit(f, y, row).map(ControlFlow::Continue))
+  }
+  /// `unpack_rows` stopping as soon as `emit` breaks, the rest of the stream is left unread,
+  /// skipped blocks on an odd right or bottom edge are taken from `fill`
+  fn unpack_rows_until<S, C, F>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, fill: &Fill, emit: F) -> Result<(), Error>
+    where S: Symbol, C: BitRead, F: FnMut(usize, usize, &[Self]) -> Result<ControlFlow<()>, Error> {
+    Self::unpack_rows_in(tables, codec, width, height, face, fill, &mut Vec::new(), emit)
+  }
+  /// `unpack_rows_until` buffering rows in `scratch`, which is resized as needed
+  #[allow(clippy::too_many_arguments)]
+  fn unpack_rows_in<S, C, F>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, fill: &Fill, scratch: &mut Vec<Self>, mut emit: F) -> Result<(), Error>
+    where S: Symbol, C: BitRead, F: FnMut(usize, usize, &[Self]) -> Result<ControlFlow<()>, Error> {
+    let block_x = width.div_ceil(4) as usize;
+    let block_y = height.div_ceil(4) as usize;
+    let chunk_x = block_x.div_ceil(Self::TRUNK_SIZE);
+    let chunk_y = block_y.div_ceil(Self::TRUNK_SIZE);
 
-    let pitch = block_x as usize * Self::BLOCK_SIZE;
+    let mut tile_bits = 1u32;
 
-    let mut result = vec![0u8; block_y as usize * pitch];
-    let mut cursor = std::io::Cursor::new(&mut result[..]);
+    // running palette index of every component
+    let mut endpoint_index = [0; 2];
+    let mut selector_index = [0; 2];
+    let (endpoint_count, selector_count) = (Self::ENDPOINTS.len(), Self::SELECTORS.len());
 
-    for _f in 0..face {
-      // let mut row = Vec::new();
+    scratch.clear();
+    scratch.resize(block_x * Self::TRUNK_SIZE, Self::default());
+
+    for f in 0..face as usize {
       for y in 0..chunk_y {
+        for (i, block) in scratch.iter_mut().enumerate() {
+          *block = fill.block(i % block_x, y * Self::TRUNK_SIZE + i / block_x);
+        }
         let skip_y = y == (chunk_y - 1) && block_y & 1 == 1;
-        let xrange: Box<dyn Iterator<Item=_>> = if y & 1 == 1 { Box::new((0..chunk_x).rev()) } else { Box::new(0..chunk_x) };
-        for x in xrange {
+        for step in 0..chunk_x {
+          // odd rows run right to left
+          let x = if y & 1 == 1 { chunk_x - 1 - step } else { step };
           let skip_x = block_x & 1 == 1 && x == (chunk_x - 1);
-          let mut color_endpoints = [(0, 0); 4];
-          let mut alpha_endpoints = [(0, 0); 4];
+          let mut endpoints = [[0; 2]; 4];
 
           let (tiles_count, tiles) = Self::next_tile_idx(codec, &tables.chunk_encoding, &mut tile_bits)?;
 
-          for i in 0..tiles_count {
-            alpha_endpoints[i] = tables.alpha_endpoint()?.next(codec, &mut alpha_endpoint_index).context("read alpha_endpoint_delta")?;
-          }
-
-          for i in 0..tiles_count {
-            color_endpoints[i] = tables.color_endpoint()?.next(codec, &mut color_endpoint_index).context("read color_endpoint_delta")?;
+          for (c, &kind) in Self::ENDPOINTS.iter().enumerate() {
+            for endpoint in endpoints.iter_mut().take(tiles_count) {
+              tables.next_index(kind, codec, &mut endpoint_index[c]).with_context(|| format!("read {:?} delta", kind))?;
+              endpoint[c] = endpoint_index[c];
+            }
           }
-
-          // println!("tile: {:x?}", tiles);
           for (i, &tile) in tiles.iter().enumerate() {
-            let alpha_selector = tables.alpha_selector()?.next(codec, &mut alpha_selector_index).context("read alpha_selector_delta")?;
-            let color_selector = tables.color_selector()?.next(codec, &mut color_selector_index).context("read color_selector_delta")?;
+            for (c, &kind) in Self::SELECTORS.iter().enumerate() {
+              tables.next_index(kind, codec, &mut selector_index[c]).with_context(|| format!("read {:?} delta", kind))?;
+            }
 
-            // println!("{:x?}", (delta0, delta1, alpha_selector_index, color_selector_index, tables.color_selectors[color_selector_index]));
             if !skip_x && !skip_y {
-              if i % Self::TRUNK_SIZE == 0 {
-                let pos = (y * Self::TRUNK_SIZE + i / Self::TRUNK_SIZE) * pitch + x * Self::BLOCK_SIZE * Self::TRUNK_SIZE;
-                // println!("seek {}x{} + {} => {:x}", x, y, i, pos);
-                cursor.seek(std::io::SeekFrom::Start(pos as _)).expect("seek");
-              }
-              Dxt5 {
-                alpha_endpoint: alpha_endpoints[tile],
-                alpha_selector,
-                color_endpoint: color_endpoints[tile],
-                color_selector,
-              }.write_to(&mut cursor).context("write block")?;
+              let pos = i / Self::TRUNK_SIZE * block_x + x * Self::TRUNK_SIZE + i % Self::TRUNK_SIZE;
+              scratch[pos] = Self::from_indices(tables, &endpoints[tile][..endpoint_count], &selector_index[..selector_count])?;
             }
           }
         }
+        for (r, row) in scratch.chunks_exact(block_x.max(1)).enumerate().take(block_y - y * Self::TRUNK_SIZE) {
+          if emit(f, y * Self::TRUNK_SIZE + r, row)?.is_break() { return Ok(()) }
+        }
+      }
+    }
+    if !codec.is_complete() { bail!(Error::TrailingData) }
+    Ok(())
+  }
+  fn unpack_blocks<S: Symbol, C: BitRead>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8) -> Result<Blocks<Self>, Error> {
+    let block_x = width.div_ceil(4) as usize;
+    let block_y = height.div_ceil(4) as usize;
+    let mut result = Blocks { block_x, block_y, faces: face as usize, blocks: Vec::with_capacity(level_size(width, height, face, 1)?) };
+    Self::unpack_rows(tables, codec, width, height, face, |_, _, row| {
+      result.blocks.extend_from_slice(row);
+      Ok(())
+    })?;
+    Ok(result)
+  }
+  fn unpack<S: Symbol, C: BitRead>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8) -> Result<Vec<u8>, Error> {
+    Self::unpack_filled(tables, codec, width, height, face, &Fill::Zero)
+  }
+  /// `unpack` with the skipped edge blocks taken from `fill`
+  fn unpack_filled<S: Symbol, C: BitRead>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, fill: &Fill) -> Result<Vec<u8>, Error> {
+    let mut result = vec![0; level_size(width, height, face, Self::BLOCK_SIZE)?];
+    let mut blocks = result.chunks_exact_mut(Self::BLOCK_SIZE);
+    Self::unpack_rows_until(tables, codec, width, height, face, fill, |_, _, row| {
+      for (block, out) in row.iter().zip(&mut blocks) {
+        block.write_bytes(out);
+      }
+      Ok(ControlFlow::Continue(()))
+    })?;
+    Ok(result)
+  }
+  /// `unpack` into `context.output`, reusing its buffers
+  fn unpack_in<'c, S: Symbol, C: BitRead>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, context: &'c mut DecodeContext) -> Result<&'c [u8], Error> {
+    let mut output = std::mem::take(&mut context.output);
+    output.clear();
+    output.resize(level_size(width, height, face, Self::BLOCK_SIZE)?, 0);
+    let mut blocks = output.chunks_exact_mut(Self::BLOCK_SIZE);
+    let result = Self::unpack_rows_in(tables, codec, width, height, face, &Fill::Zero, Self::scratch(context), |_, _, row| {
+      for (block, out) in row.iter().zip(&mut blocks) {
+        block.write_bytes(out);
       }
+      Ok(ControlFlow::Continue(()))
+    });
+    context.output = output;
+    result?;
+    Ok(&context.output)
+  }
+  /// `unpack` calling `progress` after every row of blocks, an error returned by `progress`
+  /// aborts the decode
+  fn unpack_with_progress<S, C, P>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, mut progress: P) -> Result<Vec<u8>, Error>
+    where S: Symbol, C: BitRead, P: FnMut(Progress) -> Result<(), Error> {
+    let total_rows = height.div_ceil(4) as usize * face as usize;
+    let mut result = vec![0; level_size(width, height, face, Self::BLOCK_SIZE)?];
+    let mut blocks = result.chunks_exact_mut(Self::BLOCK_SIZE);
+    let mut rows = 0;
+    Self::unpack_rows(tables, codec, width, height, face, |_, _, row| {
+      for (block, out) in row.iter().zip(&mut blocks) {
+        block.write_bytes(out);
+      }
+      rows += 1;
+      progress(Progress { rows, total_rows })
+    })?;
+    Ok(result)
+  }
+  /// decode only the blocks of face `index` covering `rect`, row by row, the stream is still
+  /// walked up to the last row of `rect` but everything after it is skipped
+  fn unpack_region<S: Symbol, C: BitRead>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, index: usize, rect: Rect) -> Result<Vec<u8>, Error> {
+    let (x0, y0, x1, y1) = rect.blocks();
+    if index >= face as usize { bail!("face {} out of {}", index, face) }
+    if rect.width == 0 || rect.height == 0 || x1 > width.div_ceil(4) as usize || y1 > height.div_ceil(4) as usize {
+      bail!("region {:?} out of {}x{}", rect, width, height)
     }
-    if !codec.is_complete() { bail!("extra bytes in codec") }
+    let mut result = vec![0; (x1 - x0) * (y1 - y0) * Self::BLOCK_SIZE];
+    let mut blocks = result.chunks_exact_mut(Self::BLOCK_SIZE);
+    Self::unpack_rows_until(tables, codec, width, height, face, &Fill::Zero, |f, y, row| {
+      if f != index || y < y0 { return Ok(ControlFlow::Continue(())) }
+      for (block, out) in row[x0..x1].iter().zip(&mut blocks) {
+        block.write_bytes(out);
+      }
+      Ok(if y + 1 >= y1 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) })
+    })?;
     Ok(result)
   }
+  /// `unpack` that keeps going after a decode error, rows of blocks which were not decoded
+  /// completely and skipped edge blocks are taken from `fill`, the error is returned alongside the data
+  fn unpack_lenient<S: Symbol, C: BitRead>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, fill: &Fill) -> (Vec<u8>, Option<Error>) {
+    let (block_x, block_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+    let mut result = match level_size(width, height, face, Self::BLOCK_SIZE) {
+      Ok(size) => vec![0; size],
+      Err(e) => return (vec![], Some(e)),
+    };
+    for (i, out) in result.chunks_exact_mut(Self::BLOCK_SIZE).enumerate() {
+      fill.block::<Self>(i % block_x, i / block_x % block_y).write_bytes(out);
+    }
+    let mut blocks = result.chunks_exact_mut(Self::BLOCK_SIZE);
+    let error = Self::unpack_rows_until(tables, codec, width, height, face, fill, |_, _, row| {
+      for (block, out) in row.iter().zip(&mut blocks) {
+        block.write_bytes(out);
+      }
+      Ok(ControlFlow::Continue(()))
+    }).err();
+    (result, error)
+  }
+  /// decode and serialize blocks row by row into `w` without holding the level in memory
+  fn unpack_to_writer<S: Symbol, C: BitRead, W: Write>(tables: &Tables<S>, codec: &mut C, width: u16, height: u16, face: u8, mut w: W) -> Result<(), Error> {
+    Self::unpack_rows(tables, codec, width, height, face, |_, _, row| {
+      for block in row {
+        block.write_to(&mut w).context("write block")?;
+      }
+      Ok(())
+    })
+  }
+  fn next_tile_idx<S: Symbol, C: BitRead>(codec: &mut C, encoding: &Huffman<S>, tile_bits: &mut u32) -> Result<(usize, [usize; 4]), Error> {
+    if *tile_bits == 1 {
+      *tile_bits = encoding.next(codec).context("read chunk encoding bits")?.into() | 512;
+    }
+
+    let tile_index = *tile_bits as usize & 7;
+    *tile_bits >>= 3;
+    Ok((Self::COUNT_TILES[tile_index], Self::TILES[tile_index]))
+  }
+
+  const TRUNK_SIZE: usize = 2;
+
+  const COUNT_TILES: [usize; 8] = [ 1, 2, 2, 3, 3, 3, 3, 4 ];
+  const TILES: [[usize; 4]; 8] = [
+    [ 0, 0, 0, 0 ],
+    [ 0, 0, 1, 1 ], [ 0, 1, 0, 1 ],
+    [ 0, 0, 1, 2 ], [ 1, 2, 0, 0 ],
+    [ 0, 1, 0, 2 ], [ 1, 0, 2, 0 ],
+    [ 0, 1, 2, 3 ]
+  ];
 }
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Dxt5A {
-  pub alpha_endpoint: (u8, u8),
-  pub alpha_selector: [u8; 6],
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dxt1 {
+  pub color_endpoint: (u16, u16),
+  pub color_selector: [u8; 4],
 }
 
-impl Block for Dxt5A {
+impl Block for Dxt1 {
   const BLOCK_SIZE: usize = 8;
+  type Texel = [u8; 4];
+  fn decode_texels(&self) -> [[u8; 4]; 16] {
+    decode_color(self.color_endpoint, self.color_selector, true)
+  }
+  fn solid(rgba: [u8; 4]) -> Self {
+    let c = pack_565(rgba);
+    Dxt1 { color_endpoint: (c, c), color_selector: [0; 4] }
+  }
+  fn read_bytes(input: &[u8]) -> Self {
+    let mut color_selector = [0; 4];
+    color_selector.copy_from_slice(&input[4..8]);
+    Dxt1 { color_endpoint: (u16_at(input, 0), u16_at(input, 2)), color_selector }
+  }
+  fn write_bytes(&self, out: &mut [u8]) {
+    out[0..2].copy_from_slice(&self.color_endpoint.0.to_le_bytes());
+    out[2..4].copy_from_slice(&self.color_endpoint.1.to_le_bytes());
+    out[4..8].copy_from_slice(&self.color_selector);
+  }
+}
+impl std::convert::TryFrom<&[u8]> for Dxt1 {
+  type Error = Error;
+  fn try_from(input: &[u8]) -> Result<Self, Error> {
+    check_block_size::<Self>(input)?;
+    Ok(Self::read_bytes(input))
+  }
+}
+impl Unpack for Dxt1 {
+  const KIND: BlockKind = BlockKind::Dxt1;
+  const ENDPOINTS: &'static [Kind] = &[Kind::ColorEndpoint];
+  const SELECTORS: &'static [Kind] = &[Kind::ColorSelector];
+  fn scratch(context: &mut DecodeContext) -> &mut Vec<Self> {
+    &mut context.dxt1
+  }
+  fn from_indices<S: Symbol>(tables: &Tables<S>, endpoints: &[usize], selectors: &[usize]) -> Result<Self, Error> {
+    Ok(Dxt1 {
+      color_endpoint: tables.color_endpoint()?.entries[endpoints[0]],
+      color_selector: tables.color_selector()?.entries[selectors[0]],
+    })
+  }
 }
-impl Unpack for Dxt5A {
-  fn unpack(tables: &Tables, codec: &mut Codec, width: u16, height: u16, face: u8) -> Result<Vec<u8>, Error> {
-    let block_x = (width + 3) / 4;
-    let block_y = (height + 3) / 4;
-    let chunk_x = (block_x + 1) as usize / Self::TRUNK_SIZE;
-    let chunk_y = (block_y + 1) as usize / Self::TRUNK_SIZE;
-
-    let mut tile_bits = 1u32;
-
-    let mut alpha_endpoint_index = 0;
-    let mut alpha_selector_index = 0;
 
-    let pitch = block_x as usize * Self::BLOCK_SIZE;
 
-    let mut result = vec![0u8; block_y as usize * pitch];
-    let mut cursor = std::io::Cursor::new(&mut result[..]);
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dxt5 {
+  pub alpha_endpoint: (u8, u8),
+  pub alpha_selector: [u8; 6],
+  pub color_endpoint: (u16, u16),
+  pub color_selector: [u8; 4],
+}
 
-    for _f in 0..face {
-      // let mut row = Vec::new();
-      for y in 0..chunk_y {
-        let skip_y = y == (chunk_y - 1) && block_y & 1 == 1;
-        let xrange: Box<dyn Iterator<Item=_>> = if y & 1 == 1 { Box::new((0..chunk_x).rev()) } else { Box::new(0..chunk_x) };
-        for x in xrange {
-          let skip_x = block_x & 1 == 1 && x == (chunk_x - 1);
-          let mut alpha_endpoints = [(0, 0); 4];
+impl Block for Dxt5 {
+  const BLOCK_SIZE: usize = 16;
+  type Texel = [u8; 4];
+  fn decode_texels(&self) -> [[u8; 4]; 16] {
+    let mut result = decode_color(self.color_endpoint, self.color_selector, false);
+    for (texel, a) in result.iter_mut().zip(decode_alpha(self.alpha_endpoint, self.alpha_selector)) { texel[3] = a }
+    result
+  }
+  fn solid(rgba: [u8; 4]) -> Self {
+    let Dxt1 { color_endpoint, color_selector } = Dxt1::solid(rgba);
+    Dxt5 { alpha_endpoint: (rgba[3], rgba[3]), alpha_selector: [0; 6], color_endpoint, color_selector }
+  }
+  fn read_bytes(input: &[u8]) -> Self {
+    let Dxt5A { alpha_endpoint, alpha_selector } = Dxt5A::read_bytes(&input[..8]);
+    let Dxt1 { color_endpoint, color_selector } = Dxt1::read_bytes(&input[8..16]);
+    Dxt5 { alpha_endpoint, alpha_selector, color_endpoint, color_selector }
+  }
+  fn write_bytes(&self, out: &mut [u8]) {
+    out[0] = self.alpha_endpoint.0;
+    out[1] = self.alpha_endpoint.1;
+    out[2..8].copy_from_slice(&self.alpha_selector);
+    out[8..10].copy_from_slice(&self.color_endpoint.0.to_le_bytes());
+    out[10..12].copy_from_slice(&self.color_endpoint.1.to_le_bytes());
+    out[12..16].copy_from_slice(&self.color_selector);
+  }
+}
+impl std::convert::TryFrom<&[u8]> for Dxt5 {
+  type Error = Error;
+  fn try_from(input: &[u8]) -> Result<Self, Error> {
+    check_block_size::<Self>(input)?;
+    Ok(Self::read_bytes(input))
+  }
+}
+impl Unpack for Dxt5 {
+  const KIND: BlockKind = BlockKind::Dxt5;
+  const ENDPOINTS: &'static [Kind] = &[Kind::AlphaEndpoint, Kind::ColorEndpoint];
+  const SELECTORS: &'static [Kind] = &[Kind::AlphaSelector, Kind::ColorSelector];
+  fn scratch(context: &mut DecodeContext) -> &mut Vec<Self> {
+    &mut context.dxt5
+  }
+  fn from_indices<S: Symbol>(tables: &Tables<S>, endpoints: &[usize], selectors: &[usize]) -> Result<Self, Error> {
+    Ok(Dxt5 {
+      alpha_endpoint: tables.alpha_endpoint()?.entries[endpoints[0]],
+      alpha_selector: tables.alpha_selector()?.entries[selectors[0]],
+      color_endpoint: tables.color_endpoint()?.entries[endpoints[1]],
+      color_selector: tables.color_selector()?.entries[selectors[1]],
+    })
+  }
+}
 
-          let (tiles_count, tiles) = Self::next_tile_idx(codec, &tables.chunk_encoding, &mut tile_bits)?;
 
-          for i in 0..tiles_count {
-            alpha_endpoints[i] = tables.alpha_endpoint()?.next(codec, &mut alpha_endpoint_index).context("read alpha_endpoint_delta")?;
-          }
-          for (i, &tile) in tiles.iter().enumerate() {
-            let alpha_selector = tables.alpha_selector()?.next(codec, &mut alpha_selector_index).context("read alpha_selector_delta")?;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dxt5A {
+  pub alpha_endpoint: (u8, u8),
+  pub alpha_selector: [u8; 6],
+}
 
-            // println!("{:x?}", (delta0, delta1, alpha_selector_index, color_selector_index, tables.color_selectors[color_selector_index]));
-            if !skip_x && !skip_y {
-              if i % Self::TRUNK_SIZE == 0 {
-                let pos = (y * Self::TRUNK_SIZE + i / Self::TRUNK_SIZE) * pitch + x * Self::BLOCK_SIZE * Self::TRUNK_SIZE;
-                // println!("seek {}x{} + {} => {:x}", x, y, i, pos);
-                cursor.seek(std::io::SeekFrom::Start(pos as _)).expect("seek");
-              }
-              Dxt5A {
-                alpha_endpoint: alpha_endpoints[tile],
-                alpha_selector,
-              }.write_to(&mut cursor).context("write block")?;
-            }
-          }
-        }
-      }
-    }
-    if !codec.is_complete() { bail!("extra bytes in codec") }
-    Ok(result)
+impl Block for Dxt5A {
+  const BLOCK_SIZE: usize = 8;
+  type Texel = u8;
+  fn decode_texels(&self) -> [u8; 16] {
+    decode_alpha(self.alpha_endpoint, self.alpha_selector)
+  }
+  fn solid(rgba: [u8; 4]) -> Self {
+    Dxt5A { alpha_endpoint: (rgba[0], rgba[0]), alpha_selector: [0; 6] }
+  }
+  fn read_bytes(input: &[u8]) -> Self {
+    let mut alpha_selector = [0; 6];
+    alpha_selector.copy_from_slice(&input[2..8]);
+    Dxt5A { alpha_endpoint: (input[0], input[1]), alpha_selector }
+  }
+  fn write_bytes(&self, out: &mut [u8]) {
+    out[0] = self.alpha_endpoint.0;
+    out[1] = self.alpha_endpoint.1;
+    out[2..8].copy_from_slice(&self.alpha_selector);
+  }
+}
+impl std::convert::TryFrom<&[u8]> for Dxt5A {
+  type Error = Error;
+  fn try_from(input: &[u8]) -> Result<Self, Error> {
+    check_block_size::<Self>(input)?;
+    Ok(Self::read_bytes(input))
+  }
+}
+impl Unpack for Dxt5A {
+  const KIND: BlockKind = BlockKind::Dxt5A;
+  const ENDPOINTS: &'static [Kind] = &[Kind::AlphaEndpoint];
+  const SELECTORS: &'static [Kind] = &[Kind::AlphaSelector];
+  fn scratch(context: &mut DecodeContext) -> &mut Vec<Self> {
+    &mut context.dxt5a
+  }
+  fn from_indices<S: Symbol>(tables: &Tables<S>, endpoints: &[usize], selectors: &[usize]) -> Result<Self, Error> {
+    Ok(Dxt5A {
+      alpha_endpoint: tables.alpha_endpoint()?.entries[endpoints[0]],
+      alpha_selector: tables.alpha_selector()?.entries[selectors[0]],
+    })
   }
 }
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Dxn {
   pub alpha0_endpoint: (u8, u8),
   pub alpha0_selector: [u8; 6],
@@ -262,71 +679,55 @@ pub struct Dxn {
 
 impl Block for Dxn {
   const BLOCK_SIZE: usize = 16;
+  type Texel = [u8; 2];
+  fn decode_texels(&self) -> [[u8; 2]; 16] {
+    let (x, y) = (decode_alpha(self.alpha0_endpoint, self.alpha0_selector), decode_alpha(self.alpha1_endpoint, self.alpha1_selector));
+    let mut result = [[0; 2]; 16];
+    for (i, texel) in result.iter_mut().enumerate() { *texel = [x[i], y[i]] }
+    result
+  }
+  fn solid(rgba: [u8; 4]) -> Self {
+    Dxn { alpha0_endpoint: (rgba[0], rgba[0]), alpha0_selector: [0; 6], alpha1_endpoint: (rgba[1], rgba[1]), alpha1_selector: [0; 6] }
+  }
+  fn read_bytes(input: &[u8]) -> Self {
+    let x = Dxt5A::read_bytes(&input[..8]);
+    let y = Dxt5A::read_bytes(&input[8..16]);
+    Dxn { alpha0_endpoint: x.alpha_endpoint, alpha0_selector: x.alpha_selector, alpha1_endpoint: y.alpha_endpoint, alpha1_selector: y.alpha_selector }
+  }
+  fn write_bytes(&self, out: &mut [u8]) {
+    out[0] = self.alpha0_endpoint.0;
+    out[1] = self.alpha0_endpoint.1;
+    out[2..8].copy_from_slice(&self.alpha0_selector);
+    out[8] = self.alpha1_endpoint.0;
+    out[9] = self.alpha1_endpoint.1;
+    out[10..16].copy_from_slice(&self.alpha1_selector);
+  }
+}
+impl std::convert::TryFrom<&[u8]> for Dxn {
+  type Error = Error;
+  fn try_from(input: &[u8]) -> Result<Self, Error> {
+    check_block_size::<Self>(input)?;
+    Ok(Self::read_bytes(input))
+  }
 }
 impl Unpack for Dxn {
-  fn unpack(tables: &Tables, codec: &mut Codec, width: u16, height: u16, face: u8) -> Result<Vec<u8>, Error> {
-    let block_x = (width + 3) / 4;
-    let block_y = (height + 3) / 4;
-    let chunk_x = (block_x + 1) as usize / Self::TRUNK_SIZE;
-    let chunk_y = (block_y + 1) as usize / Self::TRUNK_SIZE;
-
-    let mut tile_bits = 1u32;
-
-    let mut alpha0_endpoint_index = 0;
-    let mut alpha0_selector_index = 0;
-    let mut alpha1_endpoint_index = 0;
-    let mut alpha1_selector_index = 0;
-
-    let pitch = block_x as usize * Self::BLOCK_SIZE;
-
-    let mut result = vec![0u8; block_y as usize * pitch];
-    let mut cursor = std::io::Cursor::new(&mut result[..]);
-
-    for _f in 0..face {
-      // let mut row = Vec::new();
-      for y in 0..chunk_y {
-        let skip_y = y == (chunk_y - 1) && block_y & 1 == 1;
-        let xrange: Box<dyn Iterator<Item=_>> = if y & 1 == 1 { Box::new((0..chunk_x).rev()) } else { Box::new(0..chunk_x) };
-        for x in xrange {
-          let skip_x = block_x & 1 == 1 && x == (chunk_x - 1);
-          let mut alpha0_endpoints = [(0, 0); 4];
-          let mut alpha1_endpoints = [(0, 0); 4];
-
-          let (tiles_count, tiles) = Self::next_tile_idx(codec, &tables.chunk_encoding, &mut tile_bits)?;
-
-          for i in 0..tiles_count {
-            alpha0_endpoints[i] = tables.alpha_endpoint()?.next(codec, &mut alpha0_endpoint_index).context("read alpha0_endpoint_delta")?;
-          }
-          for i in 0..tiles_count {
-            alpha1_endpoints[i] = tables.alpha_endpoint()?.next(codec, &mut alpha1_endpoint_index).context("read alpha1_endpoint_delta")?;
-          }
-          for (i, &tile) in tiles.iter().enumerate() {
-            let alpha0_selector = tables.alpha_selector()?.next(codec, &mut alpha0_selector_index).context("read alpha0_selector_delta")?;
-            let alpha1_selector = tables.alpha_selector()?.next(codec, &mut alpha1_selector_index).context("read alpha1_selector_delta")?;
-
-            // println!("{:x?}", (delta0, delta1, alpha_selector_index, color_selector_index, tables.color_selectors[color_selector_index]));
-            if !skip_x && !skip_y {
-              if i % Self::TRUNK_SIZE == 0 {
-                let pos = (y * Self::TRUNK_SIZE + i / Self::TRUNK_SIZE) * pitch + x * Self::BLOCK_SIZE * Self::TRUNK_SIZE;
-                // println!("seek {}x{} + {} => {:x}", x, y, i, pos);
-                cursor.seek(std::io::SeekFrom::Start(pos as _)).expect("seek");
-              }
-              Dxn {
-                alpha0_endpoint: alpha0_endpoints[tile],
-                alpha0_selector,
-                alpha1_endpoint: alpha1_endpoints[tile],
-                alpha1_selector,
-              }.write_to(&mut cursor).context("write block")?;
-            }
-          }
-        }
-      }
-    }
-    if !codec.is_complete() { bail!("extra bytes in codec") }
-    Ok(result)
+  const KIND: BlockKind = BlockKind::Dxn;
+  const ENDPOINTS: &'static [Kind] = &[Kind::AlphaEndpoint, Kind::AlphaEndpoint];
+  const SELECTORS: &'static [Kind] = &[Kind::AlphaSelector, Kind::AlphaSelector];
+  fn scratch(context: &mut DecodeContext) -> &mut Vec<Self> {
+    &mut context.dxn
+  }
+  fn from_indices<S: Symbol>(tables: &Tables<S>, endpoints: &[usize], selectors: &[usize]) -> Result<Self, Error> {
+    Ok(Dxn {
+      alpha0_endpoint: tables.alpha_endpoint()?.entries[endpoints[0]],
+      alpha0_selector: tables.alpha_selector()?.entries[selectors[0]],
+      alpha1_endpoint: tables.alpha_endpoint()?.entries[endpoints[1]],
+      alpha1_selector: tables.alpha_selector()?.entries[selectors[1]],
+    })
   }
 }
 
+#[cfg(feature = "serde")]
 #[test]
 fn test_constant() {
   assert_eq!(Dxt5::TILES.len(), Dxt5::COUNT_TILES.len());
@@ -351,4 +752,51 @@ fn test_constant() {
     0x20, 0x21, 0x22, 0x23, 0x24, 0x25,
     0x34, 0x32, 0x37, 0x35,
     0x49, 0x48, 0x47, 0x46]);
+
+  fn check<T>(block: T) where T: Block + Serialize + PartialEq + std::fmt::Debug + for<'a> std::convert::TryFrom<&'a [u8], Error = Error> {
+    let mut out = vec![0; T::BLOCK_SIZE];
+    block.write_bytes(&mut out);
+    assert_eq!(out, bincode::config::DefaultOptions::new().with_fixint_encoding().with_little_endian().serialize(&block).unwrap());
+    assert_eq!(T::try_from(&out[..]).unwrap(), block);
+    assert!(T::try_from(&out[1..]).is_err());
+  }
+  check(Dxt1 { color_endpoint: (0x1234, 0x5678), color_selector: [1, 2, 3, 4] });
+  check(Dxt5 {
+    alpha_endpoint: (0x17, 0x18),
+    alpha_selector: [0x20, 0x21, 0x22, 0x23, 0x24, 0x25],
+    color_endpoint: (0x3234, 0x3537),
+    color_selector: [0x49, 0x48, 0x47, 0x46],
+  });
+  check(Dxt5A { alpha_endpoint: (1, 2), alpha_selector: [3, 4, 5, 6, 7, 8] });
+  check(Dxn { alpha0_endpoint: (1, 2), alpha0_selector: [3, 4, 5, 6, 7, 8], alpha1_endpoint: (9, 10), alpha1_selector: [11, 12, 13, 14, 15, 16] });
+}
+
+#[test]
+fn test_decode_texels() {
+  // red to blue, first row picks all four colors, the rest stays red
+  let dxt1 = Dxt1 { color_endpoint: (0xf800, 0x001f), color_selector: [0b11_10_01_00, 0, 0, 0] };
+  let texels = dxt1.decode_texels();
+  assert_eq!(&texels[..5], &[[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255], [255, 0, 0, 255]]);
+  // c0 <= c1 switches to 3 colors and transparent black in DXT1 only
+  let dxt1 = Dxt1 { color_endpoint: (0x001f, 0xf800), color_selector: [0b11_10_01_00, 0, 0, 0] };
+  assert_eq!(&dxt1.decode_texels()[2..4], &[[127, 0, 127, 255], [0; 4]]);
+  // 3 bits per texel, little-endian
+  let pack = |codes: &[u64]| {
+    let bits = codes.iter().enumerate().fold(0u64, |bits, (i, c)| bits | c << (i * 3));
+    let mut result = [0; 6];
+    result.copy_from_slice(&bits.to_le_bytes()[..6]);
+    result
+  };
+  let dxt5 = Dxt5 { color_endpoint: dxt1.color_endpoint, color_selector: dxt1.color_selector, alpha_endpoint: (0, 255), alpha_selector: pack(&[0, 1, 5, 7]) };
+  let texels = dxt5.decode_texels();
+  assert_eq!(texels[3], [170, 0, 85, 255]);
+  assert_eq!(texels.map(|t| t[3])[..5], [0, 255, 204, 255, 0]);
+
+  let alpha = Dxt5A { alpha_endpoint: (255, 0), alpha_selector: pack(&[0, 1, 2, 3]) };
+  assert_eq!(alpha.decode_texels()[..4], [255, 0, 218, 182]);
+  assert_eq!(Dxt5::solid(PLACEHOLDER).decode_texels(), [PLACEHOLDER; 16]);
+  assert_eq!(Dxt1::solid([12, 34, 56, 0]).decode_texels(), [[8, 32, 57, 255]; 16]);
+  assert_eq!(Dxn::solid([1, 2, 3, 4]).decode_texels(), [[1, 2]; 16]);
+  let dxn = Dxn { alpha0_endpoint: (10, 10), alpha0_selector: [0; 6], alpha1_endpoint: (0, 0), alpha1_selector: [0xff; 6] };
+  assert_eq!(dxn.decode_texels(), [[10, 255]; 16]);
 }
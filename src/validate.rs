@@ -0,0 +1,134 @@
+//! Consistency checks of a crn file which report every problem found instead of failing on the first.
+
+use crate::{Header, Palette, symbols::components, unpack::Kind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  /// decodes fine but isn't what crunch writes
+  Warning,
+  /// decoding fails or gives garbage
+  Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+  pub severity: Severity,
+  pub message: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+  pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+  fn push(&mut self, severity: Severity, message: String) {
+    self.findings.push(Finding { severity, message })
+  }
+
+  /// no finding is an error
+  pub fn is_valid(&self) -> bool {
+    self.errors().next().is_none()
+  }
+
+  pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+    self.findings.iter().filter(|f| f.severity == Severity::Error)
+  }
+
+  pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+    self.findings.iter().filter(|f| f.severity == Severity::Warning)
+  }
+}
+
+impl Header {
+  /// run every consistency check on the header and `input` it was parsed from
+  pub fn validate(&self, input: &[u8]) -> ValidationReport {
+    use Severity::*;
+    let mut report = ValidationReport::default();
+    let header_size = self.header_size as usize;
+    let file_size = self.file_size as usize;
+
+    if self.magic != *b"Hx" { report.push(Error, format!("invalid magic {:02x?}", self.magic)) }
+    if header_size != Header::fixed_size() + 4*self.level_count as usize {
+      report.push(Error, format!("header size {} doesn't match {} levels", header_size, self.level_count));
+    }
+    if file_size != input.len() {
+      report.push(Error, format!("file size {} doesn't match input of {} bytes", file_size, input.len()));
+    }
+    match input.get(6..header_size) {
+      Some(header) if self.header_crc16 == !Header::crc16(0, header) => {},
+      _ => report.push(Error, "header crc mismatch".to_string()),
+    }
+    match input.get(header_size.min(input.len())..file_size.min(input.len())) {
+      Some(data) if self.data_crc16 == !Header::crc16(0, data) => {},
+      _ => report.push(Error, "data crc mismatch".to_string()),
+    }
+    if self.flags & !Header::KNOWN_FLAGS != 0 {
+      report.push(Warning, format!("unknown flags {:#x}", self.flags & !Header::KNOWN_FLAGS));
+    }
+    if self.width == 0 || self.height == 0 {
+      report.push(Error, format!("empty texture {}x{}", self.width, self.height));
+    }
+    if self.face_count != 1 && self.face_count != 6 {
+      report.push(Warning, format!("{} faces, neither a texture nor a cubemap", self.face_count));
+    }
+    if self.level_count == 0 { report.push(Error, "no levels".to_string()) }
+
+    let in_file = |offset: u32, size: usize| {
+      let offset = offset as usize;
+      offset >= header_size && offset.checked_add(size).is_some_and(|end| end <= file_size.min(input.len()))
+    };
+    if self.table_size == 0 || !in_file(self.table_offset, self.table_size as usize) {
+      report.push(Error, format!("table {}+{} out of file", self.table_offset, self.table_size));
+    }
+
+    let required = match components(self.format) {
+      Ok((endpoints, selectors)) => [endpoints, selectors].concat(),
+      Err(_) => {
+        report.push(Error, format!("unsupported format {:?}", self.format));
+        vec![]
+      }
+    };
+    for (kind, palette) in [
+      (Kind::ColorEndpoint, self.color_endpoints), (Kind::ColorSelector, self.color_selectors),
+      (Kind::AlphaEndpoint, self.alpha_endpoints), (Kind::AlphaSelector, self.alpha_selectors),
+    ] {
+      let Palette { offset, size, count } = palette;
+      match (count, required.contains(&kind)) {
+        (0, true) => report.push(Error, format!("{:?} palette is required by {:?} but empty", kind, self.format)),
+        (0, false) => {},
+        (_, false) => report.push(Warning, format!("{:?} palette isn't used by {:?}", kind, self.format)),
+        (_, true) if !in_file(offset, size as usize) => report.push(Error, format!("{:?} palette {}+{} out of file", kind, offset, size)),
+        _ => {},
+      }
+    }
+    // the levels of a segmented file are elsewhere
+    let levels = if self.flags & Header::SEGMENTED == 0 { &self.level_offset[..] } else { &[] };
+    for (idx, &offset) in levels.iter().enumerate() {
+      let end = self.level_offset.get(idx + 1).copied().unwrap_or(self.file_size);
+      if offset >= end {
+        report.push(Error, format!("level {} at {} doesn't end before {}", idx, offset, end));
+      } else if !in_file(offset, (end - offset) as usize) {
+        report.push(Error, format!("level {} {}..{} out of file", idx, offset, end));
+      }
+    }
+    report
+  }
+}
+
+#[test]
+fn test_validate() {
+  let (input, header, _) = crate::testgen::TestCrn::sample().parsed().unwrap();
+  assert_eq!(header.validate(&input).findings, vec![]);
+  // an unknown flag, which also breaks the header crc
+  let mut modified = input.clone();
+  modified[20] |= 0x80;
+  let report = Header::parse(&modified).unwrap().validate(&modified);
+  assert_eq!(report.warnings().map(|f| &f.message[..]).collect::<Vec<_>>(), vec!["unknown flags 0x80"]);
+  assert_eq!(report.errors().map(|f| &f.message[..]).collect::<Vec<_>>(), vec!["header crc mismatch"]);
+  let mut damaged = header.clone();
+  damaged.level_offset.swap(0, 1);
+  damaged.color_selectors.count = 0;
+  assert_eq!(damaged.validate(&input[..input.len() - 1]).errors().count(), 5);
+  assert!(!damaged.validate(&input).is_valid());
+}
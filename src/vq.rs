@@ -0,0 +1,277 @@
+//! Vector quantization of block endpoints and selectors into palettes of a target size, the
+//! clustering stage of crunch.
+//!
+//! Distinct entries are weighted by how many blocks use them and split top-down: the cluster with
+//! the largest squared error is halved by 2-means until there are enough clusters. Endpoints are
+//! clustered first, then every block picks its selectors again for its new endpoints against the
+//! texels it had, and those selectors are clustered in turn. Entries of different modes (BC1
+//! 3-color blocks, BC4 6-value blocks) never share a cluster.
+
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::Hash;
+use crate::compress::{color_palette, pick_alpha, pick_colors};
+use crate::unpack::{Block, Dxt5A, Kind};
+
+/// endpoint and selector of a color block
+pub(crate) type Color = ((u16, u16), [u8; 4]);
+/// endpoint and selector of a channel block
+pub(crate) type Alpha = ((u8, u8), [u8; 6]);
+
+/// the color and channel halves of a block, which go into separate palettes
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Parts {
+  pub color: Option<Color>,
+  pub alpha: [Option<Alpha>; 2],
+}
+
+/// distinct values in order of first appearance, how many times each appears, and the index of every value
+fn distinct<T: Copy + Eq + Hash>(values: impl Iterator<Item = T>) -> (Vec<T>, Vec<f32>, Vec<usize>) {
+  let mut index = HashMap::new();
+  let (mut entries, mut weights, mut ids) = (vec![], vec![], vec![]);
+  for value in values {
+    let id = *index.entry(value).or_insert_with(|| {
+      entries.push(value);
+      weights.push(0.0);
+      entries.len() - 1
+    });
+    weights[id] += 1.0;
+    ids.push(id);
+  }
+  (entries, weights, ids)
+}
+
+/// weighted centroid and squared error of `members`, rows of `dim` values in `points`
+fn centroid(points: &[f32], dim: usize, weights: &[f32], members: &[usize]) -> (Vec<f32>, f32) {
+  let total = members.iter().map(|&m| weights[m]).sum::<f32>();
+  let mut center = vec![0.0; dim];
+  for &m in members {
+    for (c, &x) in center.iter_mut().zip(&points[m * dim..(m + 1) * dim]) { *c += x * weights[m] / total }
+  }
+  let error = members.iter().map(|&m| weights[m] * distance(&points[m * dim..(m + 1) * dim], &center)).sum();
+  (center, error)
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+  a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// halve `members` by 2-means seeded with two far apart points, `None` if they are all the same
+fn split(points: &[f32], dim: usize, weights: &[f32], members: &[usize]) -> Option<(Vec<usize>, Vec<usize>)> {
+  let row = |m: usize| &points[m * dim..(m + 1) * dim];
+  let (center, _) = centroid(points, dim, weights, members);
+  let farthest = |from: &[f32]| members.iter().copied().max_by(|&a, &b| distance(row(a), from).total_cmp(&distance(row(b), from)));
+  let a = farthest(&center)?;
+  let b = farthest(row(a))?;
+  if distance(row(a), row(b)) == 0.0 { return None }
+  let (mut ca, mut cb) = (row(a).to_vec(), row(b).to_vec());
+  let mut halves = (vec![], vec![]);
+  for _ in 0..4 {
+    halves = members.iter().partition(|&&m| distance(row(m), &ca) <= distance(row(m), &cb));
+    if halves.0.is_empty() || halves.1.is_empty() { return None }
+    ca = centroid(points, dim, weights, &halves.0).0;
+    cb = centroid(points, dim, weights, &halves.1).0;
+  }
+  Some(halves)
+}
+
+/// Cluster `points` (rows of `dim` values) into at most `k` clusters, but at least one per group.
+/// Returns the centroids and the cluster of every point.
+fn tree_cluster(points: &[f32], dim: usize, weights: &[f32], groups: &[usize], k: usize) -> (Vec<Vec<f32>>, Vec<usize>) {
+  let mut by_group = BTreeMap::<usize, Vec<usize>>::new();
+  for (i, &g) in groups.iter().enumerate() { by_group.entry(g).or_default().push(i) }
+  let mut clusters: Vec<Vec<usize>> = by_group.into_values().collect();
+  // clusters by squared error, non-negative floats order the same as their bits
+  let mut heap: BinaryHeap<(u32, usize)> = clusters.iter().enumerate()
+    .map(|(i, members)| (centroid(points, dim, weights, members).1.to_bits(), i)).collect();
+  while clusters.len() < k {
+    let Some((error, i)) = heap.pop() else { break };
+    if error == 0 { break }
+    let Some((a, b)) = split(points, dim, weights, &clusters[i]) else { continue };
+    heap.push((centroid(points, dim, weights, &a).1.to_bits(), i));
+    heap.push((centroid(points, dim, weights, &b).1.to_bits(), clusters.len()));
+    clusters[i] = a;
+    clusters.push(b);
+  }
+  let mut assignment = vec![0; weights.len()];
+  let centroids = clusters.iter().enumerate().map(|(c, members)| {
+    for &m in members { assignment[m] = c }
+    centroid(points, dim, weights, members).0
+  }).collect();
+  (centroids, assignment)
+}
+
+/// cluster the distinct `entries` if there are more than `k`, giving the replacement of every entry
+fn quantize_entries<T: Copy, G: Fn(&T) -> usize, F: Fn(&T) -> Vec<f32>, B: Fn(&[f32], usize) -> T>(
+  entries: &[T], weights: &[f32], k: usize, group: G, features: F, back: B,
+) -> Option<Vec<T>> {
+  if entries.len() <= k { return None }
+  let points: Vec<f32> = entries.iter().flat_map(&features).collect();
+  let dim = points.len() / entries.len();
+  let groups: Vec<usize> = entries.iter().map(&group).collect();
+  let (centroids, assignment) = tree_cluster(&points, dim, weights, &groups, k);
+  Some(assignment.iter().zip(&groups).map(|(&c, &g)| back(&centroids[c], g)).collect())
+}
+
+fn round_ranks(center: &[f32], max: u8) -> impl Iterator<Item = u8> + '_ {
+  center.iter().map(move |&r| r.round().clamp(0.0, max as f32) as u8)
+}
+
+/// rank along the palette of every 2-bit color selector code, for 4-color and 3-color blocks
+const COLOR_RANK: [[u8; 4]; 2] = [[0, 3, 1, 2], [0, 2, 1, 3]];
+/// rank of every 3-bit channel selector code, for 8-value and 6-value blocks
+const ALPHA_RANK: [[u8; 8]; 2] = [[0, 7, 1, 2, 3, 4, 5, 6], [0, 5, 1, 2, 3, 4, 6, 7]];
+
+fn inverse<const N: usize>(rank: &[u8; N]) -> [u8; N] {
+  let mut result = [0; N];
+  for (code, &r) in rank.iter().enumerate() { result[r as usize] = code as u8 }
+  result
+}
+
+fn expand_565(c: u16) -> [f32; 3] {
+  crate::rgba::rgb565(c).map(|v| v as f32)
+}
+
+fn pack_565(rgb: &[f32]) -> u16 {
+  let q = |v: f32, max: f32| (v * max / 255.0).round().clamp(0.0, max) as u16;
+  q(rgb[0], 31.0) << 11 | q(rgb[1], 63.0) << 5 | q(rgb[2], 31.0)
+}
+
+/// what the parts of a block decode to: the color texels and the values of both channels
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Texels {
+  pub color: Option<[[u8; 4]; 16]>,
+  pub alpha: [Option<[u8; 16]>; 2],
+}
+
+impl Texels {
+  pub fn new(parts: &Parts, punchthrough: bool) -> Self {
+    let color = parts.color.map(|(endpoint, selector)| {
+      let palette = color_palette(endpoint, punchthrough);
+      let mut result = [[0; 4]; 16];
+      for (i, texel) in result.iter_mut().enumerate() { *texel = palette[(selector[i / 4] >> (i % 4 * 2) & 3) as usize] }
+      result
+    });
+    let alpha = parts.alpha.map(|a| a.map(|(alpha_endpoint, alpha_selector)| Dxt5A { alpha_endpoint, alpha_selector }.decode_texels()));
+    Self { color, alpha }
+  }
+}
+
+/// selectors of the color `endpoint` closest to `texels` and the squared error, `None` if
+/// `endpoint` has no transparent entry for texels that need one
+fn fit_color(endpoint: (u16, u16), texels: &[[u8; 4]; 16], punchthrough: bool) -> Option<([u8; 4], u32)> {
+  let transparent = texels.map(|t| punchthrough && t[3] < 128);
+  let three_color = punchthrough && endpoint.0 <= endpoint.1;
+  if transparent.contains(&true) && !three_color { return None }
+  let count = if three_color { 3 } else { 4 };
+  Some(pick_colors(texels, &color_palette(endpoint, punchthrough), count, &transparent))
+}
+
+/// `endpoints` with selectors picked again against `texels`, and the squared error
+pub(crate) fn refit(endpoints: &Parts, texels: &Texels, punchthrough: bool) -> Option<(Parts, u32)> {
+  let mut result = Parts::default();
+  let mut error = 0;
+  if let (Some((endpoint, _)), Some(texels)) = (endpoints.color, &texels.color) {
+    let (selector, e) = fit_color(endpoint, texels, punchthrough)?;
+    result.color = Some((endpoint, selector));
+    error += e;
+  }
+  for ((alpha, endpoints), values) in result.alpha.iter_mut().zip(&endpoints.alpha).zip(&texels.alpha) {
+    if let (Some((endpoint, _)), Some(values)) = (endpoints, values) {
+      let (selector, e) = pick_alpha(values, *endpoint);
+      *alpha = Some((*endpoint, selector));
+      error += e;
+    }
+  }
+  Some((result, error))
+}
+
+/// `items.iter().map(f)`, on all cores with the `parallel` feature; the order is kept either way
+/// so the output doesn't depend on the number of threads
+pub(crate) fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+  #[cfg(feature = "parallel")]
+  {
+    use rayon::prelude::*;
+    items.par_iter().map(f).collect()
+  }
+  #[cfg(not(feature = "parallel"))]
+  items.iter().map(f).collect()
+}
+
+/// Cluster the parts of `blocks` so at most `sizes[kind.index()]` distinct entries of each palette
+/// remain (a few more if blocks of different modes share a palette), picking selectors again
+/// against `texels`. `punchthrough` for BC1 color, where `c0 <= c1` selects the 3-color mode
+/// with transparent black.
+pub(crate) fn quantize(blocks: &mut [Parts], texels: &[Texels], punchthrough: bool, sizes: [usize; 4]) {
+  let size = |kind: Kind| sizes[kind.index()].max(1);
+  let three_color = |(c0, c1): (u16, u16)| punchthrough && c0 <= c1;
+  let six_value = |(a0, a1): (u8, u8)| a0 <= a1;
+
+  let (entries, weights, ids) = distinct(blocks.iter().filter_map(|b| b.color.map(|(e, _)| e)));
+  let replaced = quantize_entries(&entries, &weights, size(Kind::ColorEndpoint), |&e| three_color(e) as usize,
+    |&(c0, c1)| [expand_565(c0), expand_565(c1)].concat(),
+    |center, group| {
+      let (c0, c1) = (pack_565(&center[..3]), pack_565(&center[3..]));
+      // keep the mode of the blocks in the cluster
+      if (group == 1) == (c0 > c1) { (c1, c0) } else { (c0, c1) }
+    });
+  if let Some(replaced) = replaced {
+    let jobs: Vec<(usize, usize)> = (0..blocks.len()).filter(|&i| blocks[i].color.is_some()).zip(ids).collect();
+    let colors = par_map(&jobs, |&(i, id)| {
+      let (texels, endpoint) = (texels[i].color.unwrap_or_default(), replaced[id]);
+      // clusters keep the mode, so transparent texels always have their entry
+      (endpoint, fit_color(endpoint, &texels, punchthrough).unwrap_or_default().0)
+    });
+    for (&(i, _), color) in jobs.iter().zip(colors) { blocks[i].color = Some(color) }
+  }
+
+  let (entries, weights, ids) = distinct(blocks.iter().filter_map(|b| b.color.map(|(e, s)| (s, three_color(e) as usize))));
+  let replaced = quantize_entries(&entries, &weights, size(Kind::ColorSelector), |&(_, g)| g,
+    |&(s, g)| (0..16).map(|i| COLOR_RANK[g][(s[i / 4] >> (i % 4 * 2) & 3) as usize] as f32).collect(),
+    |center, group| {
+      let mut selector = [0; 4];
+      for (i, r) in round_ranks(center, 3).enumerate() { selector[i / 4] |= inverse(&COLOR_RANK[group])[r as usize] << (i % 4 * 2) }
+      (selector, group)
+    });
+  if let Some(replaced) = replaced {
+    for (block, &id) in blocks.iter_mut().filter(|b| b.color.is_some()).zip(&ids) {
+      if let Some((_, selector)) = &mut block.color { *selector = replaced[id].0 }
+    }
+  }
+
+  let (entries, weights, ids) = distinct(blocks.iter().flat_map(|b| b.alpha.iter().flatten().map(|&(e, _)| e)));
+  let replaced = quantize_entries(&entries, &weights, size(Kind::AlphaEndpoint), |&e| six_value(e) as usize,
+    |&(a0, a1)| vec![a0 as f32, a1 as f32],
+    |center, group| {
+      let (a0, a1) = (center[0].round() as u8, center[1].round() as u8);
+      if (group == 1) == (a0 > a1) { (a1, a0) } else { (a0, a1) }
+    });
+  if let Some(replaced) = replaced {
+    let jobs: Vec<(usize, usize, usize)> = (0..blocks.len()).flat_map(|i| (0..2).map(move |c| (i, c)))
+      .filter(|&(i, c)| blocks[i].alpha[c].is_some()).zip(ids).map(|((i, c), id)| (i, c, id)).collect();
+    let alphas = par_map(&jobs, |&(i, c, id)| texels[i].alpha[c].map(|values| (replaced[id], pick_alpha(&values, replaced[id]).0)));
+    for (&(i, c, _), alpha) in jobs.iter().zip(alphas) {
+      if alpha.is_some() { blocks[i].alpha[c] = alpha }
+    }
+  }
+
+  let (entries, weights, ids) = distinct(blocks.iter().flat_map(|b| b.alpha.iter().flatten().map(|&(e, s)| (s, six_value(e) as usize))));
+  let replaced = quantize_entries(&entries, &weights, size(Kind::AlphaSelector), |&(_, g)| g,
+    |&(s, g)| {
+      let bits = s.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64);
+      (0..16).map(|i| ALPHA_RANK[g][(bits >> (3 * i) & 7) as usize] as f32).collect()
+    },
+    |center, group| {
+      let bits = round_ranks(center, 7).enumerate().fold(0u64, |acc, (i, r)| acc | (inverse(&ALPHA_RANK[group])[r as usize] as u64) << (3 * i));
+      let mut selector = [0; 6];
+      selector.copy_from_slice(&bits.to_le_bytes()[..6]);
+      (selector, group)
+    });
+  if let Some(replaced) = replaced {
+    let mut ids = ids.iter();
+    for block in blocks.iter_mut() {
+      for (_, selector) in block.alpha.iter_mut().flatten() {
+        if let Some(&id) = ids.next() { *selector = replaced[id].0 }
+      }
+    }
+  }
+}
@@ -0,0 +1,71 @@
+//! A wasm-bindgen API for web viewers, decoding crn files handed over as a `Uint8Array`.
+//!
+//! Errors are thrown as JS `Error`s with the message of the `CrnError`. Levels hold all faces one
+//! after another, as `Header::unpack_level` returns them.
+
+use wasm_bindgen::prelude::*;
+use crate::error::CrnError as Error;
+use crate::{Header, ParseOptions};
+
+/// what `texture_info` returns, a plain object on the JS side
+pub use crate::info::CrnTextureInfo as TextureInfo;
+
+fn header(bytes: &[u8]) -> Result<Header, Error> {
+  Header::parse_with(bytes, &ParseOptions::default())
+}
+
+fn blocks(bytes: &[u8], level: u32) -> Result<(Header, Vec<u8>), Error> {
+  let header = header(bytes)?;
+  let tables = header.get_table(bytes)?;
+  let blocks = header.unpack_level(&tables, bytes, level as usize)?;
+  Ok((header, blocks))
+}
+
+fn rgba(bytes: &[u8], level: u32) -> Result<Vec<u8>, Error> {
+  let (header, blocks) = blocks(bytes, level)?;
+  let (width, height) = header.get_level_info(level as usize).ok_or_else(|| Error::level(level as usize))?;
+  let face_size = blocks.len() / header.face_count as usize;
+  let mut result = Vec::with_capacity(width as usize * height as usize * 4 * header.face_count as usize);
+  for face in blocks.chunks_exact(face_size) {
+    result.extend_from_slice(&crate::rgba::decode_image(header.format, face, width, height)?);
+  }
+  Ok(result)
+}
+
+fn js(e: Error) -> JsError {
+  JsError::new(&e.to_string())
+}
+
+/// dimensions, levels, faces and format of a crn file
+#[wasm_bindgen]
+pub fn texture_info(bytes: &[u8]) -> Result<TextureInfo, JsError> {
+  Ok((&header(bytes).map_err(js)?.texture_info()).into())
+}
+
+/// the blocks of `level`, e.g. for `compressedTexImage2D` with `WEBGL_compressed_texture_s3tc`
+#[wasm_bindgen]
+pub fn decode_level(bytes: &[u8], level: u32) -> Result<Vec<u8>, JsError> {
+  Ok(blocks(bytes, level).map_err(js)?.1)
+}
+
+/// `level` expanded into `width * height * 4` bytes of RGBA per face, e.g. for an `ImageData`
+#[wasm_bindgen]
+pub fn decode_to_rgba(bytes: &[u8], level: u32) -> Result<Vec<u8>, JsError> {
+  rgba(bytes, level).map_err(js)
+}
+
+#[test]
+fn test_wasm() {
+  use crate::{Format, testgen::TestCrn};
+  let gen = TestCrn { faces: 2, ..TestCrn::new(Format::Dxt5, 16, 8) };
+  let input = gen.build().unwrap();
+  let info = texture_info(&input).unwrap();
+  assert_eq!(info, TextureInfo { width: 16, height: 8, levels: 5, faces: 2, format: Format::Dxt5 as u32, bytes_per_block: 16 });
+  let levels = gen.blocks().unwrap();
+  assert_eq!(decode_level(&input, 1).unwrap(), levels[1]);
+  let rgba = decode_to_rgba(&input, 0).unwrap();
+  assert_eq!(rgba.len(), 16 * 8 * 4 * 2);
+  assert_eq!(&rgba[16 * 8 * 4..], crate::rgba::decode_image(Format::Dxt5, &levels[0][128..], 16, 8).unwrap());
+  // the JS side of errors needs a JS host
+  assert!(header(&input[1..]).is_err() && blocks(&input, 5).is_err());
+}